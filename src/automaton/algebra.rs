@@ -0,0 +1,660 @@
+use bit_set::BitSet;
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::error;
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Arc;
+
+use super::super::mapping::{Marker, Variable};
+use super::super::regex::MAX_STATES;
+use super::{Automaton, Label};
+
+/// Reasons for which spanners can't be combined.
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// Spanners of a union must assign the same variables.
+    DistinctVariables(Vec<String>, Vec<String>),
+    /// A projection keeps a variable that is not assigned by the spanner.
+    UnknownVariable(String),
+    /// An expression refers to a spanner that was not given.
+    UnknownSpanner(usize),
+    /// The expression is not a valid expression.
+    Syntax {
+        /// Description of the syntax error.
+        message: String,
+        /// Byte offset of the error in the expression.
+        position: usize,
+    },
+    /// The automaton has more states than the index can handle.
+    TooManyStates(usize),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::DistinctVariables(left, right) => write!(
+                f,
+                "spanners of a union must have the same variables, got {{{}}} and {{{}}}",
+                left.join(", "),
+                right.join(", ")
+            ),
+            Error::UnknownVariable(name) => write!(f, "unknown variable: {}", name),
+            Error::UnknownSpanner(index) => write!(f, "unknown spanner: {}", index),
+            Error::Syntax { message, position } => {
+                write!(f, "invalid syntax at position {}: {}", position, message)
+            }
+            Error::TooManyStates(nb_states) => write!(
+                f,
+                "the automaton has {} states, which exceeds the limit of {}",
+                nb_states, MAX_STATES
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+//     _    _            _
+//    / \  | | __ _  ___| |__  _ __ __ _
+//   / _ \ | |/ _` |/ _ \ '_ \| '__/ _` |
+//  / ___ \| | (_| |  __/ |_) | | | (_| |
+// /_/   \_\_|\__, |\___|_.__/|_|  \__,_|
+//            |___/
+
+/// Operations of the algebra of regular spanners, the resulting automata can
+/// be enumerated with an `IndexedDag` as any compiled pattern.
+///
+/// Variables of distinct automata are identified by their name.
+impl Automaton {
+    /// Build a spanner whose mappings are the mappings of both spanners, which
+    /// must assign the same variables.
+    pub fn union(&self, other: &Automaton) -> Result<Automaton, Error> {
+        let names = |automaton: &Automaton| -> Vec<String> {
+            let names: BTreeSet<_> = automaton
+                .get_variables()
+                .into_iter()
+                .map(|var| var.get_name().to_string())
+                .collect();
+            names.into_iter().collect()
+        };
+
+        let (left_names, right_names) = (names(self), names(other));
+
+        if left_names != right_names {
+            return Err(Error::DistinctVariables(left_names, right_names));
+        }
+
+        let variables = VariableNames::new(&[self, other]);
+        let (left, right) = (variables.rename(self), variables.rename(other));
+        let shift = 1 + left.nb_states;
+
+        // The new initial state behaves as the initial states of both spanners.
+        let from_initial = |automaton: &Automaton, shift: usize| {
+            automaton.adj[automaton.get_initial()]
+                .iter()
+                .map(move |(label, target)| (0, label.clone(), target + shift))
+                .collect::<Vec<_>>()
+        };
+
+        let shifted = |automaton: &Automaton, shift: usize| {
+            automaton
+                .transitions
+                .iter()
+                .map(move |(source, label, target)| (source + shift, label.clone(), target + shift))
+                .collect::<Vec<_>>()
+        };
+
+        let transitions = shifted(&left, 1)
+            .into_iter()
+            .chain(shifted(&right, shift))
+            .chain(from_initial(&left, 1))
+            .chain(from_initial(&right, shift));
+
+        let mut finals: Vec<_> = (left.finals.iter().map(|state| state + 1))
+            .chain(right.finals.iter().map(|state| state + shift))
+            .collect();
+
+        if left.finals.contains(left.get_initial()) || right.finals.contains(right.get_initial()) {
+            finals.push(0);
+        }
+
+        checked(Automaton::new(
+            shift + right.nb_states,
+            transitions,
+            finals.into_iter(),
+        ))
+    }
+
+    /// Build a spanner that only assigns the given variables, the mappings of
+    /// the spanner being restricted to these variables.
+    pub fn project(&self, names: &[&str]) -> Result<Automaton, Error> {
+        let assigned: HashSet<_> = self
+            .get_variables()
+            .into_iter()
+            .map(|var| var.get_name())
+            .collect();
+
+        if let Some(name) = names.iter().find(|name| !assigned.contains(*name)) {
+            return Err(Error::UnknownVariable(name.to_string()));
+        }
+
+        let dropped = |label: &Label| match label {
+            Label::Assignation(marker) => !names.contains(&marker.variable().get_name()),
+            _ => false,
+        };
+
+        // States reached from each state by assigning only dropped variables,
+        // these assignations are acyclic as they go toward greater states.
+        let mut closures: Vec<BitSet> = vec![BitSet::new(); self.nb_states];
+
+        for state in (0..self.nb_states).rev() {
+            let mut closure = BitSet::with_capacity(self.nb_states);
+            closure.insert(state);
+
+            for (label, target) in &self.adj[state] {
+                if dropped(label) {
+                    closure.union_with(&closures[*target]);
+                }
+            }
+
+            closures[state] = closure;
+        }
+
+        let mut transitions = Vec::new();
+        let mut seen = HashSet::new();
+
+        for (source, closure) in closures.iter().enumerate() {
+            for state in closure {
+                for (label, target) in &self.adj[state] {
+                    if !dropped(label) && seen.insert((source, Arc::as_ptr(label), *target)) {
+                        transitions.push((source, label.clone(), *target));
+                    }
+                }
+            }
+        }
+
+        let finals =
+            (0..self.nb_states).filter(|state| !closures[*state].is_disjoint(&self.finals));
+        let projected = Automaton::new(self.nb_states, transitions.into_iter(), finals);
+        Ok(VariableNames::new(&[&projected]).rename(&projected))
+    }
+
+    /// Build the natural join of two spanners: the mappings that agree on the
+    /// variables shared by both spanners, together with their other
+    /// variables.
+    ///
+    /// Between two characters, each spanner assigns a set of variables in any
+    /// order. The product automaton assigns the union of the sets of both
+    /// spanners, once they agree on shared variables, in a fixed order.
+    pub fn join(&self, other: &Automaton) -> Result<Automaton, Error> {
+        let variables = VariableNames::new(&[self, other]);
+        let (left, right) = (variables.rename(self), variables.rename(other));
+
+        let names = |automaton: &Automaton| -> HashSet<String> {
+            automaton
+                .get_variables()
+                .into_iter()
+                .map(|var| var.get_name().to_string())
+                .collect()
+        };
+
+        let shared: HashSet<_> = names(&left).intersection(&names(&right)).cloned().collect();
+        let shared_markers = |markers: &BTreeSet<Marker>| -> Vec<Marker> {
+            markers
+                .iter()
+                .filter(|marker| shared.contains(marker.variable().get_name()))
+                .cloned()
+                .collect()
+        };
+
+        let (left_closures, right_closures) = (
+            AssignationClosures::new(&left),
+            AssignationClosures::new(&right),
+        );
+
+        let mut product = Product::default();
+        let mut queue = VecDeque::new();
+        let initial = product.node(Node::Pair(left.get_initial(), right.get_initial()));
+        queue.push_back((left.get_initial(), right.get_initial(), true));
+        let mut expanded = HashSet::new();
+
+        while let Some((p, q, after_char)) = queue.pop_front() {
+            if !expanded.insert((p, q, after_char)) {
+                continue;
+            }
+
+            let source = product.node(Node::Pair(p, q));
+
+            // Sets of variables are only assigned after a character is read,
+            // as each set is assigned at once.
+            if after_char {
+                for (left_markers, p_next) in &left_closures.closures[p] {
+                    for (right_markers, q_next) in &right_closures.closures[q] {
+                        if (left_markers.is_empty() && right_markers.is_empty())
+                            || shared_markers(left_markers) != shared_markers(right_markers)
+                        {
+                            continue;
+                        }
+
+                        let markers: Vec<_> = left_markers.union(right_markers).cloned().collect();
+                        let mut current = source;
+
+                        for i in 0..markers.len() {
+                            let next = match i + 1 == markers.len() {
+                                true => Node::Pair(*p_next, *q_next),
+                                false => Node::Assigning(p, q, markers[..=i].to_vec()),
+                            };
+
+                            let next = product.node(next);
+                            let label = product.marker_label(&markers[i]);
+                            product.add_transition(current, label, next);
+                            current = next;
+                        }
+
+                        queue.push_back((*p_next, *q_next, false));
+                    }
+                }
+            }
+
+            for (left_label, p_next) in &left.adj[p] {
+                for (right_label, q_next) in &right.adj[q] {
+                    if let Some(label) = product.atom_label(left_label, right_label) {
+                        let target = product.node(Node::Pair(*p_next, *q_next));
+                        product.add_transition(source, label, target);
+                        queue.push_back((*p_next, *q_next, true));
+                    }
+                }
+            }
+        }
+
+        let finals = product.nodes.iter().filter_map(|(node, id)| match node {
+            Node::Pair(p, q) if left.finals.contains(*p) && right.finals.contains(*q) => Some(*id),
+            _ => None,
+        });
+
+        let finals: Vec<_> = finals.collect();
+        debug_assert_eq!(initial, 0);
+        product.into_automaton(finals)
+    }
+}
+
+fn checked(automaton: Automaton) -> Result<Automaton, Error> {
+    match automaton.nb_states > MAX_STATES {
+        true => Err(Error::TooManyStates(automaton.nb_states)),
+        false => Ok(automaton),
+    }
+}
+
+/// Variables of several automata, which are identified by their name and the
+/// pattern they belong to.
+struct VariableNames {
+    variables: HashMap<(usize, String), Arc<Variable>>,
+}
+
+impl VariableNames {
+    /// Number the variables of the automata in order of their ids in the
+    /// first automaton, then in the next ones.
+    fn new(automata: &[&Automaton]) -> VariableNames {
+        let mut variables = HashMap::new();
+
+        for automaton in automata {
+            for var in automaton.get_variables() {
+                let key = (var.get_pattern(), var.get_name().to_string());
+
+                if !variables.contains_key(&key) {
+                    let renamed = Variable::new(key.1.clone(), variables.len())
+                        .with_pattern(var.get_pattern());
+                    variables.insert(key, Arc::new(renamed));
+                }
+            }
+        }
+
+        VariableNames { variables }
+    }
+
+    fn rename(&self, automaton: &Automaton) -> Automaton {
+        let mut labels: HashMap<*const Label, Arc<Label>> = HashMap::new();
+
+        let transitions: Vec<_> = automaton
+            .transitions
+            .iter()
+            .map(|(source, label, target)| {
+                let renamed = labels
+                    .entry(Arc::as_ptr(label))
+                    .or_insert_with(|| match &**label {
+                        Label::Assignation(marker) => {
+                            let var = marker.variable();
+                            let var = self.variables
+                                [&(var.get_pattern(), var.get_name().to_string())]
+                                .clone();
+
+                            Arc::new(Label::Assignation(match marker {
+                                Marker::Open(_) => Marker::Open(var),
+                                Marker::Close(_) => Marker::Close(var),
+                            }))
+                        }
+                        _ => label.clone(),
+                    });
+
+                (*source, renamed.clone(), *target)
+            })
+            .collect();
+
+        Automaton::new(
+            automaton.nb_states,
+            transitions.into_iter(),
+            automaton.finals.iter(),
+        )
+    }
+}
+
+/// Sets of markers that can be assigned from each state of an automaton,
+/// together with the state reached, which reads a character or is final.
+/// Paths that assign a marker twice or close a variable before opening it are
+/// ignored, as no valid run follows them.
+pub(super) struct AssignationClosures {
+    pub(super) closures: Vec<Vec<(BTreeSet<Marker>, usize)>>,
+}
+
+impl AssignationClosures {
+    pub(super) fn new(automaton: &Automaton) -> AssignationClosures {
+        let reads_or_accepts = |state: usize| {
+            automaton.finals.contains(state)
+                || automaton.adj[state]
+                    .iter()
+                    .any(|(label, _)| label.get_marker().is_err())
+        };
+
+        let closures = (0..automaton.nb_states)
+            .map(|state| {
+                let mut seen = HashSet::new();
+                let mut stack = vec![(BTreeSet::new(), state)];
+                let mut closure = Vec::new();
+
+                while let Some((markers, state)) = stack.pop() {
+                    if !seen.insert((markers.clone(), state)) {
+                        continue;
+                    }
+
+                    if reads_or_accepts(state) {
+                        closure.push((markers.clone(), state));
+                    }
+
+                    for (label, target) in &automaton.adj[state] {
+                        if let Ok(marker) = label.get_marker() {
+                            let closed = match marker {
+                                Marker::Open(var) => markers.contains(&Marker::Close(var.clone())),
+                                Marker::Close(_) => false,
+                            };
+
+                            let mut markers = markers.clone();
+
+                            if markers.insert(marker.clone()) && !closed {
+                                stack.push((markers, *target));
+                            }
+                        }
+                    }
+                }
+
+                closure
+            })
+            .collect();
+
+        AssignationClosures { closures }
+    }
+}
+
+/// State of the product automaton of a join.
+#[derive(Clone, Eq, Hash, PartialEq)]
+enum Node {
+    /// A pair of states of both automata.
+    Pair(usize, usize),
+    /// A set of markers is being assigned from a pair of states.
+    Assigning(usize, usize, Vec<Marker>),
+}
+
+#[derive(Default)]
+struct Product {
+    nodes: HashMap<Node, usize>,
+    transitions: Vec<(usize, Arc<Label>, usize)>,
+    seen: HashSet<(usize, *const Label, usize)>,
+    markers: HashMap<Marker, Arc<Label>>,
+    atoms: HashMap<(*const Label, *const Label), Option<Arc<Label>>>,
+}
+
+impl Product {
+    fn node(&mut self, node: Node) -> usize {
+        let next_id = self.nodes.len();
+        *self.nodes.entry(node).or_insert(next_id)
+    }
+
+    fn add_transition(&mut self, source: usize, label: Arc<Label>, target: usize) {
+        if self.seen.insert((source, Arc::as_ptr(&label), target)) {
+            self.transitions.push((source, label, target));
+        }
+    }
+
+    fn marker_label(&mut self, marker: &Marker) -> Arc<Label> {
+        self.markers
+            .entry(marker.clone())
+            .or_insert_with(|| Arc::new(Label::Assignation(marker.clone())))
+            .clone()
+    }
+
+    /// Label reading the characters read by both labels, if any.
+    fn atom_label(&mut self, left: &Arc<Label>, right: &Arc<Label>) -> Option<Arc<Label>> {
+        self.atoms
+            .entry((Arc::as_ptr(left), Arc::as_ptr(right)))
+            .or_insert_with(|| match (&**left, &**right) {
+                (Label::Atom(x), Label::Atom(y)) => {
+                    x.intersection(y).map(|atom| Arc::new(Label::Atom(atom)))
+                }
+                _ => None,
+            })
+            .clone()
+    }
+
+    /// Number states such that assignations go toward greater states, the
+    /// initial state being the first one.
+    fn into_automaton(self, finals: Vec<usize>) -> Result<Automaton, Error> {
+        let nb_states = self.nodes.len();
+
+        if nb_states > MAX_STATES {
+            return Err(Error::TooManyStates(nb_states));
+        }
+
+        let mut successors = vec![Vec::new(); nb_states];
+        let mut nb_predecessors = vec![0; nb_states];
+
+        for (source, label, target) in &self.transitions {
+            if label.get_marker().is_ok() {
+                successors[*source].push(*target);
+                nb_predecessors[*target] += 1;
+            }
+        }
+
+        let mut queue: VecDeque<_> = (0..nb_states)
+            .filter(|state| nb_predecessors[*state] == 0)
+            .collect();
+        let mut rename = vec![0; nb_states];
+        let mut next_id = 0;
+
+        while let Some(state) = queue.pop_front() {
+            rename[state] = next_id;
+            next_id += 1;
+
+            for &target in &successors[state] {
+                nb_predecessors[target] -= 1;
+
+                if nb_predecessors[target] == 0 {
+                    queue.push_back(target);
+                }
+            }
+        }
+
+        let transitions = self
+            .transitions
+            .into_iter()
+            .map(|(source, label, target)| (rename[source], label, rename[target]));
+
+        Ok(Automaton::new(
+            nb_states,
+            transitions,
+            finals.into_iter().map(|state| rename[state]),
+        ))
+    }
+}
+
+//  _____                              _
+// | ____|_  ___ __  _ __ ___  ___ ___(_) ___  _ __
+// |  _| \ \/ / '_ \| '__/ _ \/ __/ __| |/ _ \| '_ \
+// | |___ >  <| |_) | | |  __/\__ \__ \ | (_) | | | |
+// |_____/_/\_\ .__/|_|  \___||___/___/_|\___/|_| |_|
+//            |_|
+
+/// An expression of the algebra of spanners, over spanners given by their
+/// index. It is written with the operators `union(e1, e2, ...)`,
+/// `join(e1, e2, ...)` and `project(e, var1, var2, ...)`, for example
+/// `project(join(0, union(1, 2)), x)`.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub enum Expr {
+    Spanner(usize),
+    Union(Vec<Expr>),
+    Join(Vec<Expr>),
+    Projection(Box<Expr>, Vec<String>),
+}
+
+impl Expr {
+    /// Build the automaton of the expression from the automata of the
+    /// spanners it refers to.
+    pub fn eval(&self, spanners: &[Automaton]) -> Result<Automaton, Error> {
+        let fold = |operands: &[Expr], operation: fn(&Automaton, &Automaton) -> _| {
+            let mut result = operands[0].eval(spanners)?;
+
+            for operand in &operands[1..] {
+                result = operation(&result, &operand.eval(spanners)?)?;
+            }
+
+            Ok(result)
+        };
+
+        match self {
+            Expr::Spanner(index) => spanners
+                .get(*index)
+                .cloned()
+                .ok_or(Error::UnknownSpanner(*index)),
+            Expr::Union(operands) => fold(operands, Automaton::union),
+            Expr::Join(operands) => fold(operands, Automaton::join),
+            Expr::Projection(operand, names) => {
+                let names: Vec<_> = names.iter().map(String::as_str).collect();
+                operand.eval(spanners)?.project(&names)
+            }
+        }
+    }
+}
+
+impl FromStr for Expr {
+    type Err = Error;
+
+    fn from_str(expr: &str) -> Result<Expr, Error> {
+        let mut parser = ExprParser { expr, position: 0 };
+        let result = parser.expr()?;
+
+        match parser.skip_spaces() {
+            None => Ok(result),
+            Some(_) => Err(parser.error("expected the end of the expression")),
+        }
+    }
+}
+
+struct ExprParser<'e> {
+    expr: &'e str,
+    position: usize,
+}
+
+impl<'e> ExprParser<'e> {
+    fn error(&self, message: &str) -> Error {
+        Error::Syntax {
+            message: message.to_string(),
+            position: self.position,
+        }
+    }
+
+    /// Skip spaces and return the next character.
+    fn skip_spaces(&mut self) -> Option<char> {
+        let rest = &self.expr[self.position..];
+        self.position += rest.len() - rest.trim_start().len();
+        self.expr[self.position..].chars().next()
+    }
+
+    fn expect(&mut self, expected: char) -> Result<(), Error> {
+        match self.skip_spaces() {
+            Some(x) if x == expected => {
+                self.position += 1;
+                Ok(())
+            }
+            _ => Err(self.error(&format!("expected `{}`", expected))),
+        }
+    }
+
+    /// Read a word made of alphanumeric characters and underscores.
+    fn word(&mut self) -> &'e str {
+        self.skip_spaces();
+        let rest = &self.expr[self.position..];
+        let len = rest
+            .find(|x: char| !x.is_alphanumeric() && x != '_')
+            .unwrap_or(rest.len());
+        self.position += len;
+        &rest[..len]
+    }
+
+    fn expr(&mut self) -> Result<Expr, Error> {
+        let start = self.position;
+        let word = self.word();
+
+        if let Ok(index) = word.parse() {
+            return Ok(Expr::Spanner(index));
+        }
+
+        match word {
+            "union" | "join" => {
+                self.expect('(')?;
+                let mut operands = vec![self.expr()?];
+
+                while self.skip_spaces() == Some(',') {
+                    self.position += 1;
+                    operands.push(self.expr()?);
+                }
+
+                self.expect(')')?;
+
+                Ok(match word {
+                    "union" => Expr::Union(operands),
+                    _ => Expr::Join(operands),
+                })
+            }
+            "project" => {
+                self.expect('(')?;
+                let operand = self.expr()?;
+                let mut names = Vec::new();
+
+                while self.skip_spaces() == Some(',') {
+                    self.position += 1;
+
+                    match self.word() {
+                        "" => return Err(self.error("expected a variable")),
+                        name => names.push(name.to_string()),
+                    }
+                }
+
+                self.expect(')')?;
+                Ok(Expr::Projection(Box::new(operand), names))
+            }
+            _ => {
+                self.position = start;
+                self.skip_spaces();
+                Err(self.error("expected a spanner or an operator"))
+            }
+        }
+    }
+}
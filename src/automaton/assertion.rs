@@ -0,0 +1,88 @@
+use std::fmt;
+
+use regex_syntax;
+
+/// Kind of a character regarding assertions, the beginning and the end of the
+/// text are seen as a special character.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Ord, PartialOrd)]
+pub enum CharKind {
+    Edge,
+    NewLine,
+    Word,
+    Other,
+}
+
+impl CharKind {
+    pub const ALL: [CharKind; 4] = [
+        CharKind::Edge,
+        CharKind::NewLine,
+        CharKind::Word,
+        CharKind::Other,
+    ];
+
+    pub fn of(x: char) -> CharKind {
+        if x == '\n' {
+            CharKind::NewLine
+        } else if regex_syntax::is_word_character(x) {
+            CharKind::Word
+        } else {
+            CharKind::Other
+        }
+    }
+
+    /// Kind of a raw byte, where only ASCII characters can be word characters.
+    pub fn of_byte(x: u8) -> CharKind {
+        if x == b'\n' {
+            CharKind::NewLine
+        } else if x.is_ascii_alphanumeric() || x == b'_' {
+            CharKind::Word
+        } else {
+            CharKind::Other
+        }
+    }
+}
+
+/// A zero-width assertion, which only checks the characters surrounding a
+/// position of the text.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash)]
+pub enum Assertion {
+    /// `(?m:^)`: beginning of the text or of a line.
+    StartLine,
+    /// `(?m:$)`: end of the text or of a line.
+    EndLine,
+    /// `\A`: beginning of the text.
+    StartText,
+    /// `\z`: end of the text.
+    EndText,
+    /// `\b`: the position is between a word character and a non-word one.
+    WordBoundary,
+    /// `\B`: negation of `\b`.
+    NotWordBoundary,
+}
+
+impl Assertion {
+    /// Check if the assertion holds between two characters.
+    pub fn is_match(self, prev: CharKind, next: CharKind) -> bool {
+        match self {
+            Assertion::StartLine => prev == CharKind::Edge || prev == CharKind::NewLine,
+            Assertion::EndLine => next == CharKind::Edge || next == CharKind::NewLine,
+            Assertion::StartText => prev == CharKind::Edge,
+            Assertion::EndText => next == CharKind::Edge,
+            Assertion::WordBoundary => (prev == CharKind::Word) != (next == CharKind::Word),
+            Assertion::NotWordBoundary => (prev == CharKind::Word) == (next == CharKind::Word),
+        }
+    }
+}
+
+impl fmt::Display for Assertion {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Assertion::StartLine => write!(f, "(?m:^)"),
+            Assertion::EndLine => write!(f, "(?m:$)"),
+            Assertion::StartText => write!(f, "\\A"),
+            Assertion::EndText => write!(f, "\\z"),
+            Assertion::WordBoundary => write!(f, "\\b"),
+            Assertion::NotWordBoundary => write!(f, "\\B"),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use regex_syntax::hir;
+use std::convert::TryFrom;
 use std::fmt;
 
 /// Represent a set of characters as an union of ranges.
@@ -9,14 +10,85 @@ pub enum Atom {
 }
 
 impl Atom {
-    /// Check if a unicode character matches an atom.
+    /// Check if a unicode character matches an atom, a byte being matched by
+    /// the character with the same code point.
     pub fn is_match(&self, a: &char) -> bool {
         match self {
             Atom::Literal(hir::Literal::Unicode(x)) => a == x,
+            Atom::Literal(hir::Literal::Byte(x)) => u32::from(*a) == u32::from(*x),
             Atom::Class(hir::Class::Unicode(class)) => class
                 .iter()
                 .any(|range| range.start() <= *a && *a <= range.end()),
-            _ => panic!("Byte regex are not supported"),
+            Atom::Class(hir::Class::Bytes(class)) => u8::try_from(*a).is_ok_and(|a| {
+                class
+                    .iter()
+                    .any(|range| range.start() <= a && a <= range.end())
+            }),
+        }
+    }
+
+    /// Ranges of code points matched by the atom, a byte being matched by the
+    /// character with the same code point.
+    pub fn ranges(&self) -> Vec<(u32, u32)> {
+        match self {
+            Atom::Literal(hir::Literal::Unicode(x)) => vec![(u32::from(*x), u32::from(*x))],
+            Atom::Literal(hir::Literal::Byte(x)) => vec![(u32::from(*x), u32::from(*x))],
+            Atom::Class(hir::Class::Unicode(class)) => class
+                .iter()
+                .map(|range| (u32::from(range.start()), u32::from(range.end())))
+                .collect(),
+            Atom::Class(hir::Class::Bytes(class)) => class
+                .iter()
+                .map(|range| (u32::from(range.start()), u32::from(range.end())))
+                .collect(),
+        }
+    }
+
+    /// Build an atom matching the characters matched by both atoms, if there
+    /// is any. It matches bytes if both atoms match bytes.
+    pub fn intersection(&self, other: &Atom) -> Option<Atom> {
+        let class = match (self.to_class(), other.to_class()) {
+            (hir::Class::Bytes(mut x), hir::Class::Bytes(y)) => {
+                x.intersect(&y);
+                hir::Class::Bytes(x)
+            }
+            (x, y) => {
+                let mut x = Atom::to_unicode_class(x);
+                x.intersect(&Atom::to_unicode_class(y));
+                hir::Class::Unicode(x)
+            }
+        };
+
+        let atom = Atom::Class(class);
+
+        match atom.ranges().is_empty() {
+            true => None,
+            false => Some(atom),
+        }
+    }
+
+    fn to_class(&self) -> hir::Class {
+        match self {
+            Atom::Literal(hir::Literal::Unicode(x)) => hir::Class::Unicode(hir::ClassUnicode::new(
+                vec![hir::ClassUnicodeRange::new(*x, *x)],
+            )),
+            Atom::Literal(hir::Literal::Byte(x)) => {
+                hir::Class::Bytes(hir::ClassBytes::new(vec![hir::ClassBytesRange::new(
+                    *x, *x,
+                )]))
+            }
+            Atom::Class(class) => class.clone(),
+        }
+    }
+
+    /// Read a class of bytes as the class of the characters with the same
+    /// code points.
+    fn to_unicode_class(class: hir::Class) -> hir::ClassUnicode {
+        match class {
+            hir::Class::Unicode(class) => class,
+            hir::Class::Bytes(class) => hir::ClassUnicode::new(class.iter().map(|range| {
+                hir::ClassUnicodeRange::new(char::from(range.start()), char::from(range.end()))
+            })),
         }
     }
 }
@@ -25,6 +97,7 @@ impl fmt::Display for Atom {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
         match self {
             Atom::Literal(hir::Literal::Unicode(x)) => write!(f, "'{}'", x),
+            Atom::Literal(hir::Literal::Byte(x)) => write!(f, "\\x{:02X}", x),
             Atom::Class(hir::Class::Unicode(class)) => {
                 write!(f, "[")?;
                 for range in class.iter() {
@@ -32,7 +105,13 @@ impl fmt::Display for Atom {
                 }
                 write!(f, "]")
             }
-            _ => panic!("Byte regex are not supported"),
+            Atom::Class(hir::Class::Bytes(class)) => {
+                write!(f, "[")?;
+                for range in class.iter() {
+                    write!(f, "\\x{:02X}-\\x{:02X}", range.start(), range.end())?;
+                }
+                write!(f, "]")
+            }
         }
     }
 }
@@ -1,13 +1,20 @@
+pub mod algebra;
+pub mod assertion;
 pub mod atom;
+pub mod normalize;
+pub mod validity;
+
+mod serialize;
 
 use bit_set::BitSet;
+use std::cmp;
 use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::fs::File;
 use std::io::prelude::*;
-use std::rc::Rc;
+use std::sync::Arc;
 
-use super::mapping::Marker;
+use super::mapping::{Marker, Variable};
 
 //     _         _                        _
 //    / \  _   _| |_ ___  _ __ ___   __ _| |_ ___  _ __
@@ -18,16 +25,16 @@ use super::mapping::Marker;
 #[derive(Clone, Debug)]
 pub struct Automaton {
     pub nb_states: usize,
-    pub transitions: Vec<(usize, Rc<Label>, usize)>,
+    pub transitions: Vec<(usize, Arc<Label>, usize)>,
     pub finals: BitSet,
 
     // Redundant caching structures
-    adj: Vec<Vec<(Rc<Label>, usize)>>,
+    adj: Vec<Vec<(Arc<Label>, usize)>>,
     adj_for_char: HashMap<char, Vec<Vec<usize>>>,
     adj_for_char_with_closure: HashMap<char, Vec<Vec<usize>>>,
     rev_adj_for_char_with_closure: HashMap<char, Vec<Vec<usize>>>,
-    assignations: Vec<Vec<(Rc<Label>, usize)>>,
-    rev_assignations: Vec<Vec<(Rc<Label>, usize)>>,
+    assignations: Vec<Vec<(Arc<Label>, usize)>>,
+    rev_assignations: Vec<Vec<(Arc<Label>, usize)>>,
     closure_for_assignations: Vec<Vec<usize>>,
     closure_for_rev_assignations: Vec<Vec<usize>>,
     jump_states: BitSet,
@@ -36,7 +43,7 @@ pub struct Automaton {
 impl Automaton {
     pub fn new<T, U>(nb_states: usize, transitions: T, finals: U) -> Automaton
     where
-        T: Iterator<Item = (usize, Rc<Label>, usize)>,
+        T: Iterator<Item = (usize, Arc<Label>, usize)>,
         U: Iterator<Item = usize>,
     {
         let mut automaton = Automaton {
@@ -71,15 +78,265 @@ impl Automaton {
         }) + 1
     }
 
+    /// Get the list of variables that are assigned by the automaton, ordered by
+    /// their id.
+    pub fn get_variables(&self) -> Vec<&Variable> {
+        let mut variables: Vec<_> = self
+            .transitions
+            .iter()
+            .filter_map(|(_, label, _)| label.get_marker().ok())
+            .map(|marker| marker.variable())
+            .collect();
+
+        variables.sort();
+        variables.dedup();
+        variables
+    }
+
+    /// Get the number of patterns the automaton was compiled from.
+    pub fn num_patterns(&self) -> usize {
+        self.get_variables()
+            .iter()
+            .map(|var| var.get_pattern() + 1)
+            .max()
+            .unwrap_or(1)
+    }
+
     pub fn get_initial(&self) -> usize {
         0
     }
 
+    /// Build an automaton recognizing the mirror of the language of this
+    /// automaton, markers are left unchanged.
+    ///
+    /// A new initial state is added, which behaves as any final state of the
+    /// original automaton, and other states are numbered in reverse order to
+    /// keep assignations from smaller to greater states.
+    pub fn reversed(&self) -> Automaton {
+        let nb_states = self.nb_states;
+        let rename = |state: usize| nb_states - state;
+
+        let from_initial = self
+            .transitions
+            .iter()
+            .filter(|(_, _, target)| self.finals.contains(*target))
+            .map(|(source, label, _)| (0, label.clone(), rename(*source)));
+
+        let transitions = self
+            .transitions
+            .iter()
+            .map(|(source, label, target)| (rename(*target), label.clone(), rename(*source)))
+            .chain(from_initial)
+            .collect::<Vec<_>>();
+
+        let mut finals = vec![rename(self.get_initial())];
+
+        if self.finals.contains(self.get_initial()) {
+            finals.push(0);
+        }
+
+        Automaton::new(nb_states + 1, transitions.into_iter(), finals.into_iter())
+    }
+
+    /// Build an automaton that starts from any of the states `initials` and
+    /// accepts in the states `finals`. A new initial state is added, which
+    /// behaves as the given initial states, and other states are shifted by
+    /// one.
+    pub fn with_boundaries(&self, initials: &BitSet, finals: &BitSet) -> Automaton {
+        // Assignations are deduplicated, otherwise a same mapping would be
+        // enumerated several times.
+        let mut assignations = HashSet::new();
+
+        let from_initial = self
+            .transitions
+            .iter()
+            .filter(|(source, label, target)| {
+                initials.contains(*source)
+                    && match label.get_marker() {
+                        Ok(marker) => assignations.insert((marker.clone(), *target)),
+                        Err(_) => true,
+                    }
+            })
+            .map(|(_, label, target)| (0, label.clone(), target + 1))
+            .collect::<Vec<_>>();
+
+        let transitions = self
+            .transitions
+            .iter()
+            .map(|(source, label, target)| (source + 1, label.clone(), target + 1))
+            .chain(from_initial);
+
+        let mut new_finals: Vec<_> = finals.iter().map(|state| state + 1).collect();
+
+        if !initials.is_disjoint(finals) {
+            new_finals.push(0);
+        }
+
+        Automaton::new(self.nb_states + 1, transitions, new_finals.into_iter())
+    }
+
+    /// Get the states from which any text can be read to reach a final state,
+    /// without assigning any variable. The text is made of unicode characters,
+    /// or of bytes if `unicode` is not set.
+    pub fn universal_states(&self, unicode: bool) -> BitSet {
+        let max_char = if unicode { 0x10_FFFF } else { 0xFF };
+        let mut universal = self.finals.clone();
+
+        loop {
+            let next: BitSet = universal
+                .iter()
+                .filter(|&state| {
+                    let mut ranges: Vec<_> = self.adj[state]
+                        .iter()
+                        .filter(|(_, target)| universal.contains(*target))
+                        .filter_map(|(label, _)| match &**label {
+                            Label::Atom(atom) => Some(atom.ranges()),
+                            _ => None,
+                        })
+                        .flatten()
+                        .collect();
+
+                    // Surrogates are not characters.
+                    ranges.push((0xD800, 0xDFFF));
+                    ranges.sort();
+
+                    let mut covered = 0;
+
+                    for (start, end) in ranges {
+                        if start > covered {
+                            break;
+                        }
+
+                        covered = cmp::max(covered, end + 1);
+                    }
+
+                    covered > max_char
+                })
+                .collect();
+
+            if next == universal {
+                return universal;
+            }
+
+            universal = next;
+        }
+    }
+
+    /// Get the maximum number of characters read from the first assignation of
+    /// a variable until the last one, then until the run can be accepted
+    /// whatever the rest of the text is, as given by `universal_states`.
+    /// Returns `None` if it is not bounded.
+    pub fn max_match_len(&self, unicode: bool) -> Option<usize> {
+        let universal = self.universal_states(unicode);
+
+        // States from which a final state can be reached, and states from
+        // which an assignation can be reached.
+        let coaccessible = self.coaccessible_states(self.finals.iter());
+        let assigning = self.coaccessible_states(
+            self.transitions
+                .iter()
+                .filter(|(_, label, _)| label.get_marker().is_ok())
+                .map(|(source, _, _)| *source),
+        );
+
+        let follow = |source: usize, target: usize| {
+            coaccessible.contains(target)
+                && (!universal.contains(source) || assigning.contains(target))
+        };
+
+        // Longest path from each state, computed by a depth-first search where
+        // a cycle means that the length is not bounded.
+        let mut longest: Vec<Option<usize>> = vec![None; self.nb_states];
+        let mut visiting = BitSet::with_capacity(self.nb_states);
+        let mut result = 0;
+
+        let starts = self
+            .transitions
+            .iter()
+            .filter(|(_, label, target)| {
+                label.get_marker().is_ok() && coaccessible.contains(*target)
+            })
+            .map(|(_, _, target)| *target);
+
+        for start in starts {
+            let mut stack = vec![(start, 0)];
+
+            if longest[start].is_none() {
+                visiting.insert(start);
+            }
+
+            while let Some((state, next_edge)) = stack.pop() {
+                if longest[state].is_some() {
+                    continue;
+                }
+
+                match self.adj[state].get(next_edge) {
+                    None => {
+                        let length = self.adj[state]
+                            .iter()
+                            .filter(|(_, target)| follow(state, *target))
+                            .map(|(label, target)| {
+                                let weight = match **label {
+                                    Label::Atom(_) => 1,
+                                    _ => 0,
+                                };
+
+                                weight + longest[*target].unwrap()
+                            })
+                            .max()
+                            .unwrap_or(0);
+
+                        longest[state] = Some(length);
+                        visiting.remove(state);
+                    }
+                    Some((_, target)) => {
+                        stack.push((state, next_edge + 1));
+
+                        if follow(state, *target) && longest[*target].is_none() {
+                            if visiting.contains(*target) {
+                                return None;
+                            }
+
+                            visiting.insert(*target);
+                            stack.push((*target, 0));
+                        }
+                    }
+                }
+            }
+
+            result = cmp::max(result, longest[start].unwrap());
+        }
+
+        Some(result)
+    }
+
+    /// Get the states from which one of the given states can be reached.
+    fn coaccessible_states(&self, targets: impl Iterator<Item = usize>) -> BitSet {
+        let mut rev_adj = vec![Vec::new(); self.nb_states];
+
+        for (source, _, target) in &self.transitions {
+            rev_adj[*target].push(*source);
+        }
+
+        let mut states: BitSet = targets.collect();
+        let mut stack: Vec<_> = states.iter().collect();
+
+        while let Some(state) = stack.pop() {
+            for &source in &rev_adj[state] {
+                if states.insert(source) {
+                    stack.push(source);
+                }
+            }
+        }
+
+        states
+    }
+
     pub fn get_nb_states(&self) -> usize {
         self.nb_states
     }
 
-    pub fn get_adj(&self) -> &Vec<Vec<(Rc<Label>, usize)>> {
+    pub fn get_adj(&self) -> &Vec<Vec<(Arc<Label>, usize)>> {
         &self.adj
     }
 
@@ -109,6 +366,18 @@ impl Automaton {
         self.rev_adj_for_char_with_closure.get(&x).unwrap()
     }
 
+    /// Same as `get_adj_for_char`, `get_adj_for_char_with_closure` must
+    /// already have been called for this char.
+    pub fn get_prepared_adj_for_char(&self, x: char) -> &Vec<Vec<usize>> {
+        self.adj_for_char.get(&x).unwrap()
+    }
+
+    /// Same as `get_adj_for_char_with_closure`, which must already have been
+    /// called for this char.
+    pub fn get_prepared_adj_for_char_with_closure(&self, x: char) -> &Vec<Vec<usize>> {
+        self.adj_for_char_with_closure.get(&x).unwrap()
+    }
+
     pub fn get_adj_for_char_with_closure(&mut self, x: char) -> &Vec<Vec<usize>> {
         let nb_states = self.get_nb_states();
         let adj_for_char = &mut self.adj_for_char;
@@ -161,13 +430,13 @@ impl Automaton {
 
     /// Get adjacency lists labeled with the corresponding marker for
     /// transitions labeled with an assignation.
-    pub fn get_assignations(&self) -> &Vec<Vec<(Rc<Label>, usize)>> {
+    pub fn get_assignations(&self) -> &Vec<Vec<(Arc<Label>, usize)>> {
         &self.assignations
     }
 
     /// Get the reverse of assignations as defined in
     /// `Automata::get_assignations`.
-    pub fn get_rev_assignations(&self) -> &Vec<Vec<(Rc<Label>, usize)>> {
+    pub fn get_rev_assignations(&self) -> &Vec<Vec<(Arc<Label>, usize)>> {
         &self.rev_assignations
     }
 
@@ -180,18 +449,18 @@ impl Automaton {
     /// Render the automaton as a dotfile for later rendering with graphviz.
     pub fn render(&self, filename: &str) -> std::io::Result<()> {
         let mut buf = File::create(filename)?;
-        buf.write(b"digraph automaton {\n")?;
+        buf.write_all(b"digraph automaton {\n")?;
 
         // Use doublecircles for final states
-        buf.write(b"\tnode [shape=doublecircle]\n")?;
+        buf.write_all(b"\tnode [shape=doublecircle]\n")?;
 
         for state in &self.finals {
             let node = format!("\tq{}\n", state);
-            buf.write(node.as_bytes())?;
+            buf.write_all(node.as_bytes())?;
         }
 
         // Draw edges
-        buf.write(b"\n\tnode [shape=circle]\n")?;
+        buf.write_all(b"\n\tnode [shape=circle]\n")?;
 
         for (source, label, target) in &self.transitions {
             let mut label_str = format!("{}", label).escape_debug().to_string();
@@ -201,18 +470,18 @@ impl Automaton {
             }
 
             let edge = format!("\tq{} -> q{} [label=\" {} \"]\n", source, target, label_str);
-            buf.write(edge.as_bytes())?;
+            buf.write_all(edge.as_bytes())?;
         }
 
         // Add an arrow towards initial state
-        buf.write(b"\n\tnode [shape=point]\n")?;
-        buf.write(b"\tbefore_q0 -> q0\n")?;
+        buf.write_all(b"\n\tnode [shape=point]\n")?;
+        buf.write_all(b"\tbefore_q0 -> q0\n")?;
 
-        buf.write(b"}\n")?;
+        buf.write_all(b"}\n")?;
         Ok(())
     }
 
-    fn init_adj(&self) -> Vec<Vec<(Rc<Label>, usize)>> {
+    fn init_adj(&self) -> Vec<Vec<(Arc<Label>, usize)>> {
         let mut ret = vec![Vec::new(); self.nb_states];
 
         for (source, label, target) in &self.transitions {
@@ -222,18 +491,12 @@ impl Automaton {
         ret
     }
 
-    fn init_assignations(&self) -> Vec<Vec<(Rc<Label>, usize)>> {
+    fn init_assignations(&self) -> Vec<Vec<(Arc<Label>, usize)>> {
         // Compute adjacency list
         let mut adj = vec![Vec::new(); self.get_nb_states()];
 
         for (source, label, target) in &self.transitions {
             if let Label::Assignation(_) = **label {
-                if source > target {
-                    panic!(
-                        "Source must be smaller then target in transition ({},{},{})",
-                        source, label, target
-                    );
-                }
                 adj[*source].push((label.clone(), *target))
             }
         }
@@ -241,7 +504,7 @@ impl Automaton {
         adj
     }
 
-    fn init_rev_assignations(&self) -> Vec<Vec<(Rc<Label>, usize)>> {
+    fn init_rev_assignations(&self) -> Vec<Vec<(Arc<Label>, usize)>> {
         // Compute adjacency list
         let mut adj = vec![Vec::new(); self.get_nb_states()];
 
@@ -306,7 +569,7 @@ impl Automaton {
             .into_iter()
             .filter_map(|(_, l, q)| match *l {
                 Label::Assignation(_) => Some(q),
-                Label::Atom(_) => None,
+                Label::Atom(_) | Label::Assertion(_) => None,
             })
             .collect::<BitSet>()
     }
@@ -323,6 +586,9 @@ impl Automaton {
 pub enum Label {
     Atom(atom::Atom),
     Assignation(Marker),
+    /// Zero-width assertions are not read by the automaton, they must be
+    /// eliminated when compiling a pattern.
+    Assertion(assertion::Assertion),
 }
 
 impl Label {
@@ -330,6 +596,7 @@ impl Label {
         match self {
             Label::Assignation(marker) => Ok(marker),
             Label::Atom(_) => Err("Can't get a marker out of an atom label."),
+            Label::Assertion(_) => Err("Can't get a marker out of an assertion label."),
         }
     }
 }
@@ -339,6 +606,7 @@ impl fmt::Display for Label {
         match self {
             Label::Assignation(marker) => write!(f, "{}", marker),
             Label::Atom(atom) => write!(f, "{}", atom),
+            Label::Assertion(assertion) => write!(f, "{}", assertion),
         }
     }
 }
@@ -0,0 +1,248 @@
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+use super::super::mapping::{Marker, Variable};
+use super::super::regex::MAX_STATES;
+use super::algebra::AssignationClosures;
+use super::validity::Status;
+use super::{Automaton, Label};
+
+/// Reasons for which an automaton can't be normalized.
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// The normalized automaton would have more states than the index can
+    /// handle.
+    TooManyStates,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::TooManyStates => write!(
+                f,
+                "the normalized automaton would exceed the limit of {} states",
+                MAX_STATES
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {}
+
+//  _   _                            _ _          _   _
+// | \ | | ___  _ __ _ __ ___   __ _| (_)______ _| |_(_) ___  _ __
+// |  \| |/ _ \| '__| '_ ` _ \ / _` | | |_  / _` | __| |/ _ \| '_ \
+// | |\  | (_) | |  | | | | | | (_| | | |/ / (_| | |_| | (_) | | | |
+// |_| \_|\___/|_|  |_| |_| |_|\__,_|_|_/___\__,_|\__|_|\___/|_| |_|
+//
+
+impl Automaton {
+    /// Check that assignations lead to greater states, which is required to
+    /// enumerate the mappings of the automaton.
+    pub fn is_normalized(&self) -> bool {
+        self.transitions
+            .iter()
+            .all(|(source, label, target)| label.get_marker().is_err() || source < target)
+    }
+
+    /// Build an equivalent automaton where assignations lead to greater
+    /// states, whatever the order of states and markers of this automaton.
+    ///
+    /// The markers assigned between two characters are assigned at once, in
+    /// the order of markers, before reaching a state that only reads or
+    /// accepts. Paths of assignations that assign a marker twice or close a
+    /// variable before opening it are dropped, as no valid run follows them,
+    /// but runs that misuse a variable across characters are kept (see
+    /// `Automaton::sequential`).
+    pub fn normalize(&self) -> Result<Automaton, Error> {
+        self.normalize_with_statuses(Vec::new())
+    }
+
+    /// Build an equivalent normalized automaton that only keeps the runs
+    /// that use variables validly, such that it is sequential. The status of
+    /// each variable is kept in the states of the automaton, such that a
+    /// variable can't be assigned again after a character is read, nor left
+    /// open in a final state.
+    pub fn sequential(&self) -> Result<Automaton, Error> {
+        self.normalize_with_statuses(self.get_variables().into_iter().cloned().collect())
+    }
+
+    fn normalize_with_statuses(&self, variables: Vec<Variable>) -> Result<Automaton, Error> {
+        let closures = AssignationClosures::new(self);
+        let mut normalized = Normalized::default();
+        let initial = vec![Status::Unassigned; variables.len()];
+        normalized.node(Node::Reading(self.get_initial(), initial))?;
+
+        while let Some((node, id)) = normalized.queue.pop() {
+            let (state, statuses) = match &node {
+                Node::Reading(state, statuses) | Node::Assigned(state, statuses) => {
+                    (*state, statuses)
+                }
+                Node::Assigning(..) => continue,
+            };
+
+            if self.finals.contains(state) && !statuses.contains(&Status::Opened) {
+                normalized.finals.push(id);
+            }
+
+            for (label, target) in &self.adj[state] {
+                if label.get_marker().is_err() {
+                    let target = normalized.node(Node::Reading(*target, statuses.clone()))?;
+                    normalized.add_transition(id, label.clone(), target);
+                }
+            }
+
+            // Markers are only assigned after a character is read.
+            if let Node::Assigned(..) = node {
+                continue;
+            }
+
+            for (markers, target) in &closures.closures[state] {
+                let assigned = match assign(&variables, statuses, markers) {
+                    Some(assigned) => assigned,
+                    None => continue,
+                };
+
+                let markers: Vec<_> = markers.iter().cloned().collect();
+                let mut current = id;
+
+                for i in 0..markers.len() {
+                    let next = match i + 1 == markers.len() {
+                        true => Node::Assigned(*target, assigned.clone()),
+                        false => Node::Assigning(state, statuses.clone(), markers[..=i].to_vec()),
+                    };
+
+                    let next = normalized.node(next)?;
+                    let label = normalized.marker_label(&markers[i]);
+                    normalized.add_transition(current, label, next);
+                    current = next;
+                }
+            }
+        }
+
+        Ok(normalized.into_automaton())
+    }
+}
+
+/// Statuses of variables after a set of markers is assigned between two
+/// characters, if none of the variables is misused.
+fn assign(
+    variables: &[Variable],
+    statuses: &[Status],
+    markers: &BTreeSet<Marker>,
+) -> Option<Vec<Status>> {
+    let mut statuses = statuses.to_vec();
+
+    // A variable may be opened and closed between the same characters.
+    let (opens, closes): (Vec<_>, Vec<_>) = markers
+        .iter()
+        .partition(|marker| matches!(marker, Marker::Open(_)));
+
+    for marker in opens.into_iter().chain(closes) {
+        let i = match variables.iter().position(|var| var == marker.variable()) {
+            Some(i) => i,
+            None => continue,
+        };
+
+        statuses[i] = match (marker, statuses[i]) {
+            (Marker::Open(_), Status::Unassigned) => Status::Opened,
+            (Marker::Close(_), Status::Opened) => Status::Closed,
+            _ => return None,
+        };
+    }
+
+    Some(statuses)
+}
+
+/// State of a normalized automaton.
+#[derive(Clone, Eq, Hash, PartialEq)]
+enum Node {
+    /// A state of the original automaton, reached after reading a character,
+    /// with the statuses of variables if they are followed.
+    Reading(usize, Vec<Status>),
+    /// Some markers are being assigned from a state of the original automaton.
+    Assigning(usize, Vec<Status>, Vec<Marker>),
+    /// A state of the original automaton, reached after assigning markers.
+    Assigned(usize, Vec<Status>),
+}
+
+impl Node {
+    /// Rank of the node in the numbering of the normalized automaton, such
+    /// that assignations lead to greater ranks.
+    fn rank(&self) -> usize {
+        match self {
+            Node::Reading(..) => 0,
+            Node::Assigning(..) => 1,
+            Node::Assigned(..) => 2,
+        }
+    }
+}
+
+#[derive(Default)]
+struct Normalized {
+    nodes: HashMap<Node, usize>,
+    queue: Vec<(Node, usize)>,
+    transitions: Vec<(usize, Arc<Label>, usize)>,
+    finals: Vec<usize>,
+    seen: HashSet<(usize, *const Label, usize)>,
+    markers: HashMap<Marker, Arc<Label>>,
+}
+
+impl Normalized {
+    /// Get the id of a node, nodes that were not seen yet are queued to be
+    /// explored.
+    fn node(&mut self, node: Node) -> Result<usize, Error> {
+        if let Some(&id) = self.nodes.get(&node) {
+            return Ok(id);
+        }
+
+        if self.nodes.len() >= MAX_STATES {
+            return Err(Error::TooManyStates);
+        }
+
+        let id = self.nodes.len();
+        self.nodes.insert(node.clone(), id);
+        self.queue.push((node, id));
+        Ok(id)
+    }
+
+    fn add_transition(&mut self, source: usize, label: Arc<Label>, target: usize) {
+        if self.seen.insert((source, Arc::as_ptr(&label), target)) {
+            self.transitions.push((source, label, target));
+        }
+    }
+
+    fn marker_label(&mut self, marker: &Marker) -> Arc<Label> {
+        self.markers
+            .entry(marker.clone())
+            .or_insert_with(|| Arc::new(Label::Assignation(marker.clone())))
+            .clone()
+    }
+
+    /// Number nodes by rank, then in the order they were found, which keeps
+    /// the initial state first and is a topological order of assignations
+    /// since a sequence of markers is found after its prefixes.
+    fn into_automaton(self) -> Automaton {
+        let mut nodes: Vec<_> = self.nodes.into_iter().collect();
+        nodes.sort_by_key(|(node, id)| (node.rank(), *id));
+
+        let mut rename = vec![0; nodes.len()];
+
+        for (new_id, (_, id)) in nodes.iter().enumerate() {
+            rename[*id] = new_id;
+        }
+
+        let transitions = self
+            .transitions
+            .into_iter()
+            .map(|(source, label, target)| (rename[source], label, rename[target]));
+
+        Automaton::new(
+            nodes.len(),
+            transitions,
+            self.finals.into_iter().map(|state| rename[state]),
+        )
+    }
+}
@@ -0,0 +1,280 @@
+//< Serialization of automata, a transition is represented by a triple
+//< `[source, label, target]` where labels are written as follows:
+//<
+//<  - `{"Literal": "a"}` for a single character,
+//<  - `{"Class": [["a", "z"], ["0", "9"]]}` for a union of ranges of characters,
+//<  - `{"Byte": 255}` and `{"ByteClass": [[128, 255]]}` for raw bytes,
+//<  - `{"Open": {"id": 0, "name": "x"}}` for the opening marker of a variable,
+//<  - `{"Close": {"id": 0, "name": "x"}}` for the closing marker of a variable.
+//<
+//< The initial state is 0, assignations that don't lead to a greater state are
+//< normalized with `Automaton::normalize` when the automaton is read. The
+//< automaton reads the whole text, thus matches that may appear anywhere are
+//< surrounded by loops over any character. When an automaton is written by
+//< hand, markers may only name their variable, as in `{"Open": "x"}`, which
+//< then gets the first id that is not used:
+//<
+//< ```json
+//< {
+//<   "nb_states": 4,
+//<   "finals": [3],
+//<   "transitions": [
+//<     [0, {"Class": [["\u0000", "\udbff\udfff"]]}, 0],
+//<     [0, {"Open": "x"}, 1],
+//<     [1, {"Class": [["a", "z"]]}, 2],
+//<     [2, {"Class": [["a", "z"]]}, 2],
+//<     [2, {"Close": "x"}, 3],
+//<     [3, {"Class": [["\u0000", "\udbff\udfff"]]}, 3]
+//<   ]
+//< }
+//< ```
+
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+use std::sync::Arc;
+
+use regex_syntax::hir;
+use serde::de::{self, Deserializer};
+use serde::ser::{self, Serializer};
+use serde::{Deserialize, Serialize};
+
+use super::super::mapping::{Marker, Variable};
+use super::atom::Atom;
+use super::{Automaton, Label};
+
+#[derive(Serialize, Deserialize)]
+enum LabelRepr {
+    Literal(char),
+    Class(Vec<(char, char)>),
+    Byte(u8),
+    ByteClass(Vec<(u8, u8)>),
+    Open(VariableRepr),
+    Close(VariableRepr),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(untagged)]
+enum VariableRepr {
+    Name(String),
+    Variable(Variable),
+}
+
+#[derive(Serialize, Deserialize)]
+struct AutomatonRepr {
+    nb_states: usize,
+    finals: Vec<usize>,
+    transitions: Vec<(usize, LabelRepr, usize)>,
+}
+
+impl LabelRepr {
+    fn from_label(label: &Label) -> Result<LabelRepr, &'static str> {
+        Ok(match label {
+            Label::Atom(Atom::Literal(hir::Literal::Unicode(x))) => LabelRepr::Literal(*x),
+            Label::Atom(Atom::Class(hir::Class::Unicode(class))) => LabelRepr::Class(
+                class
+                    .iter()
+                    .map(|range| (range.start(), range.end()))
+                    .collect(),
+            ),
+            Label::Atom(Atom::Literal(hir::Literal::Byte(x))) => LabelRepr::Byte(*x),
+            Label::Atom(Atom::Class(hir::Class::Bytes(class))) => LabelRepr::ByteClass(
+                class
+                    .iter()
+                    .map(|range| (range.start(), range.end()))
+                    .collect(),
+            ),
+            Label::Assertion(_) => return Err("assertions can't be serialized"),
+            Label::Assignation(Marker::Open(var)) => {
+                LabelRepr::Open(VariableRepr::Variable((**var).clone()))
+            }
+            Label::Assignation(Marker::Close(var)) => {
+                LabelRepr::Close(VariableRepr::Variable((**var).clone()))
+            }
+        })
+    }
+
+    /// Convert back into a label, variables are shared between labels through
+    /// the input cache.
+    fn into_label(self, variables: &mut Variables) -> Label {
+        match self {
+            LabelRepr::Literal(x) => Label::Atom(Atom::Literal(hir::Literal::Unicode(x))),
+            LabelRepr::Class(ranges) => {
+                Label::Atom(Atom::Class(hir::Class::Unicode(hir::ClassUnicode::new(
+                    ranges
+                        .into_iter()
+                        .map(|(start, end)| hir::ClassUnicodeRange::new(start, end)),
+                ))))
+            }
+            LabelRepr::Byte(x) => Label::Atom(Atom::Literal(hir::Literal::Byte(x))),
+            LabelRepr::ByteClass(ranges) => {
+                Label::Atom(Atom::Class(hir::Class::Bytes(hir::ClassBytes::new(
+                    ranges
+                        .into_iter()
+                        .map(|(start, end)| hir::ClassBytesRange::new(start, end)),
+                ))))
+            }
+            LabelRepr::Open(var) => Label::Assignation(Marker::Open(variables.get(var))),
+            LabelRepr::Close(var) => Label::Assignation(Marker::Close(variables.get(var))),
+        }
+    }
+
+    fn variable(&self) -> Option<&VariableRepr> {
+        match self {
+            LabelRepr::Open(var) | LabelRepr::Close(var) => Some(var),
+            _ => None,
+        }
+    }
+}
+
+/// Variables of a deserialized automaton, indexed by id and by name.
+#[derive(Default)]
+struct Variables {
+    by_id: HashMap<usize, Arc<Variable>>,
+    by_name: HashMap<(usize, String), Arc<Variable>>,
+}
+
+impl Variables {
+    /// Declare a variable with an explicit id, which must be consistent with
+    /// the variables that are already declared.
+    fn declare(&mut self, var: &Variable) -> Result<(), String> {
+        let key = (var.get_pattern(), var.get_name().to_string());
+
+        if let Some(other) = self.by_id.get(&var.get_id()) {
+            return match (other.get_pattern(), other.get_name()) == (key.0, &key.1) {
+                true => Ok(()),
+                false => Err(format!(
+                    "variables `{}` and `{}` have the same id {}",
+                    other,
+                    var,
+                    var.get_id()
+                )),
+            };
+        }
+
+        if self.by_name.contains_key(&key) {
+            return Err(format!("variable `{}` has several ids", var));
+        }
+
+        let var = Arc::new(var.clone());
+        self.by_id.insert(var.get_id(), var.clone());
+        self.by_name.insert(key, var);
+        Ok(())
+    }
+
+    /// Get a declared variable, a variable that is only named gets the first
+    /// id that is not used.
+    fn get(&mut self, var: VariableRepr) -> Arc<Variable> {
+        match var {
+            VariableRepr::Variable(var) => self.by_id[&var.get_id()].clone(),
+            VariableRepr::Name(name) => {
+                let by_id = &mut self.by_id;
+
+                self.by_name
+                    .entry((0, name.clone()))
+                    .or_insert_with(|| {
+                        let id = (0..).find(|id| !by_id.contains_key(id)).unwrap();
+                        let var = Arc::new(Variable::new(name, id));
+                        by_id.insert(id, var.clone());
+                        var
+                    })
+                    .clone()
+            }
+        }
+    }
+
+    /// Check that ids are consecutive, starting from 0.
+    fn check_ids(&self) -> Result<(), String> {
+        match self.by_id.keys().find(|&&id| id >= self.by_id.len()) {
+            None => Ok(()),
+            Some(id) => Err(format!(
+                "variable ids must be consecutive from 0, found id {} for {} variables",
+                id,
+                self.by_id.len()
+            )),
+        }
+    }
+}
+
+impl Serialize for Automaton {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let transitions = self
+            .transitions
+            .iter()
+            .map(|(source, label, target)| {
+                LabelRepr::from_label(label).map(|label| (*source, label, *target))
+            })
+            .collect::<Result<_, _>>()
+            .map_err(ser::Error::custom)?;
+
+        AutomatonRepr {
+            nb_states: self.nb_states,
+            finals: self.finals.iter().collect(),
+            transitions,
+        }
+        .serialize(serializer)
+    }
+}
+
+impl<'de> Deserialize<'de> for Automaton {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Automaton, D::Error> {
+        let repr = AutomatonRepr::deserialize(deserializer)?;
+        let nb_states = repr.nb_states;
+
+        if nb_states == 0 {
+            return Err(de::Error::custom("an automaton must have an initial state"));
+        }
+
+        if let Some(state) = repr.finals.iter().find(|&&state| state >= nb_states) {
+            return Err(de::Error::custom(format!(
+                "final state {} is out of bounds",
+                state
+            )));
+        }
+
+        let mut variables = Variables::default();
+
+        for (_, label, _) in &repr.transitions {
+            if let Some(VariableRepr::Variable(var)) = label.variable() {
+                variables.declare(var).map_err(de::Error::custom)?;
+            }
+        }
+
+        let mut transitions = Vec::with_capacity(repr.transitions.len());
+
+        for (source, label, target) in repr.transitions {
+            if source >= nb_states || target >= nb_states {
+                return Err(de::Error::custom(format!(
+                    "transition ({}, {}) is out of bounds",
+                    source, target
+                )));
+            }
+
+            let label = label.into_label(&mut variables);
+            transitions.push((source, Arc::new(label), target));
+        }
+
+        variables.check_ids().map_err(de::Error::custom)?;
+
+        let automaton = Automaton::new(nb_states, transitions.into_iter(), repr.finals.into_iter());
+
+        match automaton.is_normalized() {
+            true => Ok(automaton),
+            false => automaton.normalize().map_err(de::Error::custom),
+        }
+    }
+}
+
+impl Automaton {
+    /// Read an automaton from JSON, the format is described in `serialize.rs`.
+    pub fn load(input: impl Read) -> io::Result<Automaton> {
+        Ok(serde_json::from_reader(input)?)
+    }
+
+    /// Write the automaton as JSON, the format is described in `serialize.rs`,
+    /// automata with assertions can't be written.
+    pub fn save(&self, mut output: impl Write) -> io::Result<()> {
+        serde_json::to_writer_pretty(&mut output, self)?;
+        output.write_all(b"\n")
+    }
+}
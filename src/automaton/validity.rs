@@ -0,0 +1,224 @@
+use bit_set::BitSet;
+use std::collections::VecDeque;
+use std::error;
+use std::fmt;
+use std::sync::Arc;
+
+use super::super::mapping::{Marker, Variable};
+use super::{Automaton, Label};
+
+/// Ways a run of an automaton can misuse a variable.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Violation {
+    OpenedTwice,
+    ClosedTwice,
+    ClosedBeforeOpened,
+    /// The variable is opened but never closed.
+    NotClosed,
+    /// The variable is never assigned, which only breaks functionality.
+    NotAssigned,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Violation::OpenedTwice => write!(f, "opened twice"),
+            Violation::ClosedTwice => write!(f, "closed twice"),
+            Violation::ClosedBeforeOpened => write!(f, "closed before being opened"),
+            Violation::NotClosed => write!(f, "never closed"),
+            Violation::NotAssigned => write!(f, "never assigned"),
+        }
+    }
+}
+
+/// A run of an automaton that misuses a variable, it can be completed into an
+/// accepting run.
+#[derive(Clone, Debug)]
+pub struct InvalidRun {
+    pub variable: Variable,
+    pub violation: Violation,
+    /// Transitions followed from the initial state until the violation is
+    /// found, which is a final state for `NotClosed` and `NotAssigned`.
+    pub transitions: Vec<(usize, Arc<Label>, usize)>,
+}
+
+impl fmt::Display for InvalidRun {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "variable {} is {} along the run 0",
+            self.variable, self.violation
+        )?;
+
+        for (_, label, target) in &self.transitions {
+            // Classes may contain control characters.
+            let label: String = (label.to_string().chars())
+                .map(|x| match x.is_control() {
+                    true => x.escape_debug().to_string(),
+                    false => x.to_string(),
+                })
+                .collect();
+
+            write!(f, " -{}-> {}", label, target)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl error::Error for InvalidRun {}
+
+// __     __    _ _     _ _ _
+// \ \   / /_ _| (_) __| (_) |_ _   _
+//  \ \ / / _` | | |/ _` | | __| | | |
+//   \ V / (_| | | | (_| | | |_| |_| |
+//    \_/ \__,_|_|_|\__,_|_|\__|\__, |
+//                              |___/
+
+/// Status of a variable along a run.
+#[derive(Clone, Copy, Eq, Hash, PartialEq)]
+pub(super) enum Status {
+    Unassigned = 0,
+    Opened = 1,
+    Closed = 2,
+}
+
+impl Automaton {
+    /// Check that the spanner is sequential: along each accepting run, each
+    /// variable is either never assigned, or opened once and then closed once.
+    /// Mappings of runs that are not valid are meaningless.
+    pub fn check_sequential(&self) -> Result<(), InvalidRun> {
+        self.check_runs(false)
+    }
+
+    /// Check that the spanner is functional: it is sequential and each
+    /// accepting run assigns all variables. An automaton compiled from several
+    /// patterns is not functional if the patterns have distinct variables.
+    pub fn check_functional(&self) -> Result<(), InvalidRun> {
+        self.check_runs(true)
+    }
+
+    fn check_runs(&self, functional: bool) -> Result<(), InvalidRun> {
+        // Only runs that can be accepted are considered.
+        let useful = self.accessible_states();
+        let useful = self
+            .coaccessible_states(self.finals.iter())
+            .intersection(&useful)
+            .collect();
+
+        for var in self.get_variables() {
+            self.check_variable(var, &useful, functional)?;
+        }
+
+        Ok(())
+    }
+
+    /// Check the runs for a single variable, with a breadth-first search over
+    /// pairs of a state and the status of the variable, such that the shortest
+    /// invalid run is reported.
+    fn check_variable(
+        &self,
+        var: &Variable,
+        useful: &BitSet,
+        functional: bool,
+    ) -> Result<(), InvalidRun> {
+        let initial = self.get_initial();
+
+        if !useful.contains(initial) {
+            return Ok(());
+        }
+
+        // Transition followed to reach each pair for the first time.
+        let mut parent: Vec<Option<(usize, Status, Arc<Label>)>> = vec![None; 3 * self.nb_states];
+        let mut seen = BitSet::with_capacity(3 * self.nb_states);
+        let mut queue = VecDeque::new();
+
+        seen.insert(3 * initial + Status::Unassigned as usize);
+        queue.push_back((initial, Status::Unassigned));
+
+        let invalid_run = |parent: &[Option<(usize, Status, Arc<Label>)>],
+                           mut state: usize,
+                           mut status: Status,
+                           violation: Violation| {
+            let mut transitions = Vec::new();
+
+            while let Some((source, source_status, label)) = &parent[3 * state + status as usize] {
+                transitions.push((*source, label.clone(), state));
+                state = *source;
+                status = *source_status;
+            }
+
+            transitions.reverse();
+
+            InvalidRun {
+                variable: var.clone(),
+                violation,
+                transitions,
+            }
+        };
+
+        while let Some((state, status)) = queue.pop_front() {
+            if self.finals.contains(state) {
+                let violation = match status {
+                    Status::Opened => Some(Violation::NotClosed),
+                    Status::Unassigned if functional => Some(Violation::NotAssigned),
+                    _ => None,
+                };
+
+                if let Some(violation) = violation {
+                    return Err(invalid_run(&parent, state, status, violation));
+                }
+            }
+
+            for (label, target) in &self.adj[state] {
+                if !useful.contains(*target) {
+                    continue;
+                }
+
+                let next_status = match label.get_marker() {
+                    Ok(marker) if marker.variable() == var => match (marker, status) {
+                        (Marker::Open(_), Status::Unassigned) => Ok(Status::Opened),
+                        (Marker::Open(_), _) => Err(Violation::OpenedTwice),
+                        (Marker::Close(_), Status::Opened) => Ok(Status::Closed),
+                        (Marker::Close(_), Status::Unassigned) => {
+                            Err(Violation::ClosedBeforeOpened)
+                        }
+                        (Marker::Close(_), Status::Closed) => Err(Violation::ClosedTwice),
+                    },
+                    _ => Ok(status),
+                };
+
+                let next_status = next_status.map_err(|violation| {
+                    let mut run = invalid_run(&parent, state, status, violation);
+                    run.transitions.push((state, label.clone(), *target));
+                    run
+                })?;
+
+                if seen.insert(3 * target + next_status as usize) {
+                    parent[3 * target + next_status as usize] =
+                        Some((state, status, label.clone()));
+                    queue.push_back((*target, next_status));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Get the states that can be reached from the initial state.
+    fn accessible_states(&self) -> BitSet {
+        let mut states = BitSet::with_capacity(self.nb_states);
+        let mut stack = vec![self.get_initial()];
+        states.insert(self.get_initial());
+
+        while let Some(state) = stack.pop() {
+            for (_, target) in &self.adj[state] {
+                if states.insert(*target) {
+                    stack.push(*target);
+                }
+            }
+        }
+
+        states
+    }
+}
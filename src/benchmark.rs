@@ -1,8 +1,5 @@
-use super::mapping::{
-    indexed_dag::{IndexedDag, TrimmingStrategy},
-    SpannerEnumerator,
-};
 use super::Algorithm;
+use enum_spanner_rs::{IndexedDag, SpannerEnumerator, TrimmingStrategy};
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
@@ -10,8 +7,7 @@ use std::time::Instant;
 
 use serde::{Deserialize, Serialize};
 
-use super::naive;
-use super::regex;
+use enum_spanner_rs::{naive, regex};
 
 #[derive(Serialize, Deserialize, Clone)]
 pub struct BenchmarkCase {
@@ -64,13 +60,13 @@ impl BenchmarkCase {
     ) -> Result<Vec<BenchmarkCase>, Box<dyn std::error::Error>> {
         let mut input = String::new();
 
-        File::open(&filename)?.read_to_string(&mut input)?;
+        File::open(filename)?.read_to_string(&mut input)?;
         let path = filename.parent();
 
         let mut benchmarks: Vec<BenchmarkCase> = serde_json::from_str(&input)?;
 
         if let Some(path) = path {
-            for mut benchmark in &mut benchmarks {
+            for benchmark in &mut benchmarks {
                 benchmark.filename = path
                     .join(benchmark.filename.clone())
                     .to_str()
@@ -118,10 +114,8 @@ impl BenchmarkCase {
             let mut times = Vec::with_capacity(count_matches);
             let _ = compiled_matches
                 .iter()
-                .map(|x| {
+                .inspect(|_| {
                     times.push(start_time.elapsed().subsec_nanos());
-
-                    x
                 })
                 .count();
 
@@ -161,8 +155,8 @@ impl BenchmarkCase {
                 .collect()
         };
 
-        let mean = stats::mean(mean_delays.iter().map(|&x| x));
-        let stddev = stats::stddev(mean_delays.iter().map(|&x| x));
+        let mean = stats::mean(mean_delays.iter().copied());
+        let stddev = stats::stddev(mean_delays.iter().copied());
         let max: usize = *mean_delays.iter().max().unwrap_or(&0) as usize;
         let min = *mean_delays.iter().min().unwrap_or(&0);
         let mut hist = vec![0; max / 1000 + 1];
@@ -182,7 +176,7 @@ impl BenchmarkCase {
     fn measure<'a>(&'a self, enumerator: &mut impl SpannerEnumerator<'a>) -> (usize, f64, f64) {
         // Prepare the enumeration.
         let timer = Instant::now();
-        enumerator.preprocess();
+        enumerator.preprocess().unwrap();
         let preprocess = timer.elapsed();
 
         // Count matches.
@@ -197,28 +191,26 @@ impl BenchmarkCase {
         )
     }
 
-    pub fn run(&self, algorithm: Algorithm, k: usize) -> Result<BenchmarkResult, std::io::Error> {
+    pub fn run(
+        &self,
+        algorithm: Algorithm,
+        k: usize,
+    ) -> Result<BenchmarkResult, Box<dyn std::error::Error>> {
         let mut input = String::new();
         let trimming_strategy = match self.trimming {
             None => TrimmingStrategy::FullTrimming,
             Some(s) => s,
         };
 
-        let jump_distance = match self.jump {
-            None => 1,
-            Some(d) => d,
-        };
+        let jump_distance = self.jump.unwrap_or(1);
 
         // Read input file content.
         File::open(&self.filename)?
-            .take(match self.length {
-                Some(l) => l,
-                None => std::u64::MAX,
-            })
+            .take(self.length.unwrap_or(u64::MAX))
             .read_to_string(&mut input)?;
 
         // Compile the regex.
-        let automaton = regex::compile(&self.regex);
+        let automaton = regex::compile(&self.regex)?;
 
         let num_states = automaton.get_nb_states();
 
@@ -298,7 +290,7 @@ impl BenchmarkCase {
             }
             Algorithm::NaiveQuadratic => {
                 let mut enumerator =
-                    naive::naive_quadratic::NaiveEnumQuadratic::new(&self.regex, &input);
+                    naive::naive_quadratic::NaiveEnumQuadratic::new(&self.regex, &input)?;
                 let (count_matches, preprocess, enumerate) = self.measure(&mut enumerator);
                 let delays = self.measure_delays(count_matches, &enumerator, k);
 
@@ -327,8 +319,7 @@ impl BenchmarkCase {
                 })
             }
             Algorithm::NaiveCubic => {
-                let mut enumerator =
-                    naive::naive_cubic::NaiveEnumCubic::new(&self.regex, &input).unwrap();
+                let mut enumerator = naive::naive_cubic::NaiveEnumCubic::new(&self.regex, &input)?;
                 let (count_matches, preprocess, enumerate) = self.measure(&mut enumerator);
                 let delays = self.measure_delays(count_matches, &enumerator, k);
 
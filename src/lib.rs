@@ -0,0 +1,57 @@
+//! Constant-delay enumeration for nondeterministic document spanners.
+//!
+//! A pattern is first compiled into a variable automaton, which is then
+//! combined with a text into an `IndexedDag`. After a preprocessing phase that
+//! is linear in the text, all the distinct mappings of the spanner over the
+//! text can be enumerated with constant delay.
+//!
+//! ```
+//! use enum_spanner_rs::{regex, IndexedDag, SpannerEnumerator, TrimmingStrategy};
+//!
+//! let automaton = regex::compile(r"(?P<login>\w+)@(?P<server>\w+)")?;
+//! let mut enumerator = IndexedDag::new(
+//!     automaton,
+//!     "a@b",
+//!     1,
+//!     TrimmingStrategy::FullTrimming,
+//!     false,
+//! );
+//! enumerator.preprocess()?;
+//!
+//! for mapping in enumerator.iter() {
+//!     for (name, text) in mapping.iter_groups_text() {
+//!         println!("{}: {}", name, text);
+//!     }
+//! }
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+#![allow(clippy::module_inception)]
+// Mappings are hashed by their spans, not by the text they refer to.
+#![allow(clippy::mutable_key_type)]
+#![allow(clippy::needless_range_loop)]
+#![allow(clippy::too_many_arguments)]
+#![allow(clippy::type_complexity)]
+
+pub mod automaton;
+pub mod mapping;
+pub mod naive;
+pub mod regex;
+
+mod matrix;
+mod parallel;
+mod persist;
+mod progress;
+
+extern crate bit_vec;
+extern crate rand;
+extern crate regex as lib_regex;
+extern crate regex_syntax;
+
+pub use automaton::Automaton;
+pub use mapping::indexed_dag::{
+    Cursor, IndexedDag, InvalidCursor, Order, RandomAccess, ResumedIterator, TrimmingStrategy,
+};
+pub use mapping::{
+    Mapping, Marker, SlidingWindow, SpannerEnumerator, StringEquality, Text, TextFile, Variable,
+};
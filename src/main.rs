@@ -1,35 +1,72 @@
-mod automaton;
 mod benchmark;
-mod mapping;
-mod matrix;
-mod naive;
-mod progress;
-mod regex;
 
-extern crate bit_vec;
 extern crate clap;
-extern crate regex as lib_regex;
-extern crate regex_syntax;
+extern crate enum_spanner_rs;
+extern crate rand;
 
-use std::fs::File;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs::{self, File};
 use std::io::prelude::*;
 use std::io::stdin;
+use std::io::{BufReader, BufWriter};
 use std::path::Path;
+use std::str;
 use std::time;
 
 use benchmark::BenchmarkCase;
 use clap::{App, Arg};
-use mapping::indexed_dag::{IndexedDag, TrimmingStrategy};
-use mapping::SpannerEnumerator;
+use enum_spanner_rs::automaton::algebra;
+use enum_spanner_rs::{
+    Automaton, IndexedDag, Mapping, Order, SlidingWindow, SpannerEnumerator, StringEquality, Text,
+    TextFile, TrimmingStrategy,
+};
+use enum_spanner_rs::{naive, regex};
+use rand::SeedableRng;
+use rand::rngs::StdRng;
 
 #[derive(PartialEq, Eq)]
 enum DisplayFormat {
-    /// Only display the count of matches
-    Count,
+    /// Only display the count of matches, or the count of matches of each
+    /// pattern if there are several patterns
+    Count { by_pattern: bool },
     /// Display in the re-compare format: https://github.com/gchase/re-compare
-    CompareFormat,
+    CompareFormat { show_pattern: bool },
     /// Human-readable format
-    Verbose { show_offset: bool },
+    Verbose {
+        show_offset: bool,
+        show_pattern: bool,
+    },
+}
+
+impl DisplayFormat {
+    /// Display the pattern of each match if there are several patterns.
+    fn with_patterns(self, num_patterns: usize) -> DisplayFormat {
+        let several = num_patterns > 1;
+
+        match self {
+            DisplayFormat::Count { .. } => DisplayFormat::Count {
+                by_pattern: several,
+            },
+            DisplayFormat::CompareFormat { .. } => DisplayFormat::CompareFormat {
+                show_pattern: several,
+            },
+            DisplayFormat::Verbose { show_offset, .. } => DisplayFormat::Verbose {
+                show_offset,
+                show_pattern: several,
+            },
+        }
+    }
+}
+
+/// Which matches of the index should be displayed.
+enum Selection {
+    /// All the matches, in enumeration order
+    All,
+    /// Distinct matches drawn uniformly at random
+    Sample { amount: u128, seed: Option<u64> },
+    /// A slice of the matches, in enumeration order
+    Page { offset: u128, limit: Option<usize> },
 }
 
 #[derive(Clone, Copy)]
@@ -66,9 +103,115 @@ fn main() {
         .arg(
             Arg::with_name("regex")
                 .help("The pattern to look for.")
-                .required(true)
+                .required_unless_one(&[
+                    "benchmark-file",
+                    "load_index",
+                    "automaton",
+                    "regexps",
+                    "pattern_file",
+                ])
                 .conflicts_with("benchmark-file"),
         )
+        .arg(
+            Arg::with_name("regexps")
+                .short("e")
+                .long("regexp")
+                .takes_value(true)
+                .value_name("PATTERN")
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with_all(&["load_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Look for several patterns at once, the text is only preprocessed once. \
+                       Patterns are numbered from 0 in the order they are given, which is \
+                       displayed along with each match. The only positional argument is then \
+                       the file."),
+        )
+        .arg(
+            Arg::with_name("pattern_file")
+                .short("f")
+                .long("patterns")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["load_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Read patterns from a file, one per line, as with -e. Empty lines are \
+                       ignored."),
+        )
+        .arg(
+            Arg::with_name("algebra")
+                .long("algebra")
+                .takes_value(true)
+                .value_name("EXPR")
+                .help("Combine the patterns given with -e or --patterns, which are referred to \
+                       by their number, with the operators union(E, ...), join(E, ...) and \
+                       project(E, VAR, ...), e.g. 'project(join(0, union(1, 2)), x)'. Patterns \
+                       combined by a union must have the same groups, a join keeps the \
+                       matches that agree on shared groups."),
+        )
+        .arg(
+            Arg::with_name("select")
+                .long("select")
+                .takes_value(true)
+                .value_name("GROUPS")
+                .conflicts_with_all(&["load_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive_cubic", "use_naive_quadratic"])
+                .help("Only display the given groups, separated by commas. The pattern is \
+                       projected before the text is preprocessed, such that each distinct \
+                       match of these groups is displayed once."),
+        )
+        .arg(
+            Arg::with_name("equal")
+                .long("equal")
+                .takes_value(true)
+                .value_name("GROUPS")
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with_all(&["save_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive_cubic", "use_naive_quadratic"])
+                .help("Only display the matches where the given groups, separated by commas, \
+                       capture the same text. Matches are selected while the index is \
+                       descended, thus --count, --offset and --sample only consider the \
+                       selected matches."),
+        )
+        .arg(
+            Arg::with_name("formula")
+                .long("formula")
+                .conflicts_with_all(&["load_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive_cubic", "use_naive_quadratic"])
+                .help("Read patterns as regex formulas, where a variable x is bound with \
+                       x{...} instead of a named group, e.g. 'x{a+}|x{b+}y{c}'."),
+        )
+        .arg(
+            Arg::with_name("dictionaries")
+                .long("dictionary")
+                .takes_value(true)
+                .value_name("NAME=FILE")
+                .multiple(true)
+                .number_of_values(1)
+                .conflicts_with_all(&["load_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Read a dictionary from a file with one entry per line, which patterns \
+                       refer to as (?&NAME). It matches any entry, which is captured by a group \
+                       called NAME."),
+        )
+        .arg(
+            Arg::with_name("fold_case")
+                .long("fold-case")
+                .requires("dictionaries")
+                .help("Match entries of dictionaries regardless of case."),
+        )
+        .arg(
+            Arg::with_name("macros")
+                .long("macros")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["load_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Read macros from a file with one definition NAME = PATTERN per line, \
+                       which patterns refer to as {{NAME}}. Macros digit, integer, number, \
+                       word, date, time, ipv4, email_local, domain and email are built in."),
+        )
         .arg(
             Arg::with_name("file")
                 .help("The file to be read, if none is specified, STDIN is used.")
@@ -79,6 +222,44 @@ fn main() {
                 .long("count")
                 .help("Display the number of matches instead."),
         )
+        .arg(
+            Arg::with_name("bytes")
+                .long("bytes")
+                .conflicts_with_all(&["benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Read the input as raw bytes instead of UTF-8 text. The pattern matches \
+                       bytes, unless unicode mode is enabled with (?u)."),
+        )
+        .arg(
+            Arg::with_name("stream")
+                .long("stream")
+                .requires("file")
+                .conflicts_with_all(&["threads", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Read the file as a stream while preprocessing and keep the DAG in a \
+                       temporary file, matched text is then read back from the file. This \
+                       allows to index files that don't fit in memory."),
+        )
+        .arg(
+            Arg::with_name("window")
+                .long("window")
+                .conflicts_with_all(&["stream", "threads", "order", "save_index", "load_index"])
+                .conflicts_with_all(&["sample", "offset", "limit", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Display matches while reading the input, which may never end, over a \
+                       sliding window. Only matches up to a maximum length are found, which is \
+                       derived from the pattern or given with --max-len. Trailing newlines are \
+                       not removed."),
+        )
+        .arg(
+            Arg::with_name("max_len")
+                .long("max-len")
+                .takes_value(true)
+                .value_name("N")
+                .requires("window")
+                .help("Maximum length of a match in characters, from its first group until \
+                       the rest of the text doesn't matter anymore."),
+        )
         .arg(
             Arg::with_name("bytes_offset")
                 .short("b")
@@ -122,6 +303,123 @@ fn main() {
                        is a trade-off between pre-processing and enumeration time. Bigger values mean \
                        faster preprocessing and possibly slower enumeration."),
 		)
+        .arg(
+            Arg::with_name("save_index")
+                .long("index")
+                .takes_value(true)
+                .value_name("INDEX")
+                .conflicts_with_all(&["load_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Preprocess the text and save the resulting index into a file instead of \
+                       displaying matches."),
+        )
+        .arg(
+            Arg::with_name("load_index")
+                .long("load-index")
+                .takes_value(true)
+                .value_name("INDEX")
+                .conflicts_with_all(&["benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Load an index built with --index instead of compiling a pattern, the only \
+                       positional argument is then the file the index was built over."),
+        )
+        .arg(
+            Arg::with_name("automaton")
+                .long("automaton")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["load_index", "regexps", "pattern_file", "algebra"])
+                .conflicts_with_all(&["formula", "dictionaries", "macros"])
+                .conflicts_with_all(&["benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Load a variable automaton written as JSON, such as with \
+                       --save-automaton, instead of compiling a pattern. The only positional \
+                       argument is then the file. Its initial state is 0 and it must read the \
+                       whole text."),
+        )
+        .arg(
+            Arg::with_name("check")
+                .long("check")
+                .takes_value(true)
+                .value_name("PROPERTY")
+                .default_value("sequential")
+                .possible_value("none")
+                .possible_value("sequential")
+                .possible_value("functional")
+                .help("Refuse spanners that are not sequential, where a group may be captured \
+                       several times or left open, or also spanners that are not functional, \
+                       where a group may not be captured. With none, only a warning is \
+                       displayed for spanners that are not sequential and their runs that \
+                       misuse a group are ignored."),
+        )
+        .arg(
+            Arg::with_name("save_automaton")
+                .long("save-automaton")
+                .takes_value(true)
+                .value_name("FILE")
+                .conflicts_with_all(&["load_index", "save_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Save the automaton compiled from the pattern as JSON into a file instead \
+                       of displaying matches, it can be loaded back with --automaton."),
+        )
+        .arg(
+            Arg::with_name("sample")
+                .long("sample")
+                .takes_value(true)
+                .value_name("N")
+                .conflicts_with_all(&["count", "save_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Display N distinct matches drawn uniformly at random, in the order they \
+                       would be enumerated."),
+        )
+        .arg(
+            Arg::with_name("seed")
+                .long("seed")
+                .takes_value(true)
+                .value_name("S")
+                .requires("sample")
+                .help("Seed of the random generator used by --sample, a random seed is picked \
+                       if none is specified."),
+        )
+        .arg(
+            Arg::with_name("offset")
+                .long("offset")
+                .takes_value(true)
+                .value_name("K")
+                .conflicts_with_all(&["count", "sample", "save_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Skip the K first matches, without enumerating them."),
+        )
+        .arg(
+            Arg::with_name("limit")
+                .long("limit")
+                .takes_value(true)
+                .value_name("N")
+                .conflicts_with_all(&["count", "sample", "save_index", "benchmark", "benchmark-file"])
+                .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+                .help("Display at most N matches."),
+        )
+        .arg(
+            Arg::with_name("order")
+            .long("order")
+            .takes_value(true)
+            .default_value("backward")
+            .possible_value("backward")
+            .possible_value("document")
+            .conflicts_with_all(&["use_naive", "use_naive_cubic", "use_naive_quadratic"])
+            .help("Order of the matches: by decreasing end offset (backward) or by increasing \
+                   start offset (document). Ignored when loading an index, which keeps the order \
+                   it was built with."),
+        )
+        .arg(
+            Arg::with_name("threads")
+            .long("threads")
+            .takes_value(true)
+            .value_name("N")
+            .conflicts_with_all(&["load_index", "use_naive", "use_naive_cubic", "use_naive_quadratic"])
+            .help("Number of threads used to build the index, the text is split into as many \
+                   chunks. A single thread is used by default."),
+        )
         .arg(
             Arg::with_name("trimming_strategy")
             .long("trimming")
@@ -152,6 +450,8 @@ fn main() {
         },
     };
     let count = matches.is_present("count");
+    let bytes = matches.is_present("bytes");
+    let stream = matches.is_present("stream");
     let show_offset = matches.is_present("bytes_offset");
     let compare_format = matches.is_present("compare");
 
@@ -176,6 +476,13 @@ fn main() {
         Some(s) => panic!("Invalid option for trimming: {}", s),
     };
 
+    let order = match matches.value_of("order") {
+        None => Order::Backward,
+        Some("backward") => Order::Backward,
+        Some("document") => Order::Document,
+        Some(s) => panic!("Invalid option for order: {}", s),
+    };
+
     let jump_distance_str = matches.value_of("jump_distance");
     let jump_distance = match jump_distance_str {
         None => 1,
@@ -185,12 +492,69 @@ fn main() {
         },
     };
 
+    fn parse_number<T: std::str::FromStr>(s: &str) -> T {
+        match s.parse::<T>() {
+            Ok(n) => n,
+            Err(_) => panic!("Not a number: {}", s),
+        }
+    }
+
+    let threads: usize = matches.value_of("threads").map_or(1, parse_number);
+
+    if threads == 0 {
+        panic!("At least one thread is required");
+    }
+
+    let selection = if let Some(amount) = matches.value_of("sample") {
+        Selection::Sample {
+            amount: parse_number(amount),
+            seed: matches.value_of("seed").map(parse_number),
+        }
+    } else if matches.is_present("offset") || matches.is_present("limit") {
+        Selection::Page {
+            offset: matches.value_of("offset").map_or(0, parse_number),
+            limit: matches.value_of("limit").map(parse_number),
+        }
+    } else {
+        Selection::All
+    };
+
     let display_format = match (count, compare_format, show_offset) {
-        (true, _, _) => DisplayFormat::Count,
-        (_, true, _) => DisplayFormat::CompareFormat,
-        _ => DisplayFormat::Verbose { show_offset },
+        (true, _, _) => DisplayFormat::Count { by_pattern: false },
+        (_, true, _) => DisplayFormat::CompareFormat {
+            show_pattern: false,
+        },
+        _ => DisplayFormat::Verbose {
+            show_offset,
+            show_pattern: false,
+        },
     };
 
+    // Several patterns can be given with -e or read from a file.
+    let several_patterns = matches.is_present("regexps") || matches.is_present("pattern_file");
+    let mut patterns: Vec<String> = matches
+        .values_of("regexps")
+        .map_or(Vec::new(), |values| values.map(String::from).collect());
+
+    if let Some(pattern_file) = matches.value_of("pattern_file") {
+        let content = fs::read_to_string(pattern_file).unwrap_or_else(|err| exit_with_error(err));
+        let lines = content.lines().filter(|line| !line.is_empty());
+        patterns.extend(lines.map(String::from));
+    }
+
+    if several_patterns && patterns.is_empty() {
+        exit_with_error("no pattern was given");
+    }
+
+    let algebra = matches.value_of("algebra").map(|expr| {
+        if !several_patterns {
+            exit_with_error("patterns combined with --algebra must be given with -e or --patterns");
+        }
+
+        expr.parse::<algebra::Expr>()
+            .unwrap_or_else(|err| exit_with_error(format!("--algebra: {}", err)))
+    });
+
     //  ____                  _                          _
     // | __ )  ___ _ __   ___| |__  _ __ ___   __ _ _ __| | __
     // |  _ \ / _ \ '_ \ / __| '_ \| '_ ` _ \ / _` | '__| |/ /
@@ -200,14 +564,17 @@ fn main() {
 
     let benchmark_file = matches.value_of("benchmark-file");
 
-    if benchmark_file != None {
+    if let Some(benchmark_file) = benchmark_file {
         print!("[");
-        let path = Path::new(benchmark_file.unwrap());
-        let benchmarks = benchmark::BenchmarkCase::read_from_file(&path).unwrap();
+        let path = Path::new(benchmark_file);
+        let benchmarks = benchmark::BenchmarkCase::read_from_file(path)
+            .unwrap_or_else(|err| exit_with_error(err));
         let mut first = true;
         for benchmark in benchmarks {
             println!("{}", if first { "" } else { "," });
-            let result = benchmark.run(algorithm, repetitions).unwrap();
+            let result = benchmark
+                .run(algorithm, repetitions)
+                .unwrap_or_else(|err| exit_with_error(err));
             print!("{}", serde_json::to_string_pretty(&result).unwrap());
             first = false;
         }
@@ -215,7 +582,153 @@ fn main() {
         return;
     }
 
-    let regex_str = matches.value_of("regex").unwrap();
+    let load_index = matches.value_of("load_index");
+    let save_index = matches.value_of("save_index");
+    let load_automaton = matches.value_of("automaton");
+    let save_automaton = matches.value_of("save_automaton");
+
+    // When an index or an automaton is loaded, the pattern is read from it and
+    // the only positional argument is the text.
+    let (regex_str, filename) = match load_index.or(load_automaton) {
+        None if several_patterns && matches.is_present("file") => {
+            exit_with_error("a pattern can't be specified together with -e or --patterns")
+        }
+        None if several_patterns => ("", matches.value_of("regex")),
+        None => (matches.value_of("regex").unwrap(), matches.value_of("file")),
+        Some(_) if matches.is_present("file") => exit_with_error(
+            "a pattern can't be specified together with --load-index or --automaton",
+        ),
+        Some(_) => ("", matches.value_of("regex")),
+    };
+
+    let mut compiler = match bytes {
+        true => regex::Compiler::bytes(),
+        false => regex::Compiler::unicode(),
+    };
+
+    if matches.is_present("formula") {
+        compiler = compiler.with_syntax(regex::Syntax::Formula);
+    }
+
+    for dictionary in matches.values_of("dictionaries").into_iter().flatten() {
+        let (name, path) = match dictionary.find('=') {
+            Some(i) => (&dictionary[..i], &dictionary[i + 1..]),
+            None => exit_with_error("dictionaries must be given as NAME=FILE"),
+        };
+
+        let dictionary = File::open(path)
+            .and_then(|file| regex::Dictionary::read(BufReader::new(file)))
+            .unwrap_or_else(|err| exit_with_error(format!("{}: {}", path, err)))
+            .with_case_folding(matches.is_present("fold_case"));
+
+        compiler = compiler.with_dictionary(name, dictionary);
+    }
+
+    let mut macros = regex::Macros::builtin();
+
+    if let Some(path) = matches.value_of("macros") {
+        let definitions = File::open(path)
+            .and_then(|file| regex::Macros::read(BufReader::new(file)))
+            .unwrap_or_else(|err| exit_with_error(format!("{}: {}", path, err)));
+
+        macros = macros.with_macros(definitions);
+        compiler = compiler.with_macros(macros.clone());
+    }
+
+    let selected_groups: Option<Vec<&str>> = matches.value_of("select").map(|groups| {
+        groups
+            .split(',')
+            .map(str::trim)
+            .filter(|group| !group.is_empty())
+            .collect()
+    });
+
+    let mut equality = StringEquality::new();
+
+    for groups in matches.values_of("equal").into_iter().flatten() {
+        let groups: Vec<_> = groups.split(',').map(str::trim).collect();
+
+        if groups.len() < 2 || groups.contains(&"") {
+            exit_with_error("--equal expects at least two groups separated by commas");
+        }
+
+        equality = equality.with_group(groups);
+    }
+
+    let compile_patterns = || {
+        if let Some(path) = load_automaton {
+            return File::open(path)
+                .and_then(|file| Automaton::load(BufReader::new(file)))
+                .unwrap_or_else(|err| exit_with_error(format!("{}: {}", path, err)));
+        }
+
+        if !several_patterns {
+            return compiler
+                .compile(regex_str)
+                .unwrap_or_else(|err| exit_with_regex_error(&[regex_str], &macros, err));
+        }
+
+        let regexes: Vec<&str> = patterns.iter().map(String::as_str).collect();
+
+        if let Some(expr) = &algebra {
+            let spanners: Vec<_> = (regexes.iter().enumerate())
+                .map(|(pattern, regex)| {
+                    compiler.compile(regex).unwrap_or_else(|err| {
+                        let err = regex::Error::InPattern(pattern, Box::new(err));
+                        exit_with_regex_error(&regexes, &macros, err)
+                    })
+                })
+                .collect();
+
+            return expr
+                .eval(&spanners)
+                .unwrap_or_else(|err| exit_with_error(format!("--algebra: {}", err)));
+        }
+
+        compiler
+            .compile_many(&regexes)
+            .unwrap_or_else(|err| exit_with_regex_error(&regexes, &macros, err))
+    };
+
+    let compile = || {
+        let automaton = compile_patterns();
+
+        let automaton = match &selected_groups {
+            None => automaton,
+            Some(groups) => automaton
+                .project(groups)
+                .unwrap_or_else(|err| exit_with_error(format!("--select: {}", err))),
+        };
+
+        // Groups that are not selected can't be compared anymore.
+        for name in equality.get_variables() {
+            if !(automaton.get_variables().iter()).any(|var| var.get_name() == name) {
+                exit_with_error(format!("--equal: unknown group: {}", name));
+            }
+        }
+
+        let property = matches.value_of("check").unwrap();
+
+        let check = match property {
+            "functional" => automaton.check_functional(),
+            _ => automaton.check_sequential(),
+        };
+
+        if let Err(run) = check {
+            match property {
+                "none" => eprintln!(
+                    "warning: the spanner is not sequential, runs that misuse a group are ignored: {}",
+                    run
+                ),
+                _ => exit_with_error(format!(
+                    "the spanner is not {}, use --check none to ignore it: {}",
+                    property, run
+                )),
+            }
+        }
+
+        automaton
+    };
 
     if benchmark {
         let benchmark_case = BenchmarkCase::new(
@@ -226,13 +739,83 @@ fn main() {
             jump_distance,
             trimming_strategy,
         );
-        let result = benchmark_case.run(algorithm, repetitions).unwrap();
+        let result = benchmark_case
+            .run(algorithm, repetitions)
+            .unwrap_or_else(|err| exit_with_error(err));
 
         print!("{}", serde_json::to_string_pretty(&result).unwrap());
 
         return;
     }
 
+    if let Some(path) = save_automaton {
+        let automaton = compile();
+
+        File::create(path)
+            .and_then(|file| {
+                let mut output = BufWriter::new(file);
+                automaton.save(&mut output)?;
+                output.flush()
+            })
+            .unwrap_or_else(|err| exit_with_error(format!("{}: {}", path, err)));
+
+        return;
+    }
+
+    if matches.is_present("window") {
+        let automaton = compile();
+        let num_patterns = automaton.num_patterns();
+        let display_format = display_format.with_patterns(num_patterns);
+
+        let window = match bytes {
+            true => SlidingWindow::bytes(automaton),
+            false => SlidingWindow::unicode(automaton),
+        };
+
+        let window = match matches.value_of("max_len") {
+            None => window,
+            Some(max_len) => window.with_max_len(parse_number(max_len)),
+        };
+
+        let input: Box<dyn Read> = match filename {
+            Some(filename) => {
+                Box::new(File::open(filename).unwrap_or_else(|err| exit_with_error(err)))
+            }
+            None => Box::new(stdin()),
+        };
+
+        let timer = time::Instant::now();
+        let mut count: u128 = 0;
+        let mut counts = BTreeMap::new();
+
+        window
+            .for_each(input, |offset, mapping| {
+                if !equality.is_satisfied(&mapping) {
+                    return;
+                }
+
+                count += 1;
+
+                match display_format {
+                    DisplayFormat::Count { by_pattern: true } => {
+                        *counts.entry(mapping.get_pattern()).or_insert(0) += 1
+                    }
+                    DisplayFormat::Count { by_pattern: false } => {}
+                    _ => display_match(count, offset, &mapping, &timer, &display_format),
+                }
+            })
+            .unwrap_or_else(|err| exit_with_error(err));
+
+        match display_format {
+            DisplayFormat::Count { by_pattern: true } => display_counts(&counts, num_patterns),
+            DisplayFormat::Count { by_pattern: false } => println!("{}", count),
+            DisplayFormat::CompareFormat { .. } => display_eof(&timer),
+            DisplayFormat::Verbose { .. } => {}
+        }
+
+        return;
+    }
+
     //  ___                   _
     // |_ _|_ __  _ __  _   _| |_ ___
     //  | || '_ \| '_ \| | | | __/ __|
@@ -241,19 +824,51 @@ fn main() {
     //           |_|
 
     // Read the text
-    let mut text = String::new();
-    match matches.value_of("file") {
-        Some(filename) => {
-            let mut file = File::open(filename).unwrap();
-            file.read_to_string(&mut text).unwrap()
+    let mut input = Vec::new();
+    let text_file;
+
+    let text = if stream {
+        let file = File::open(filename.unwrap()).unwrap_or_else(|err| exit_with_error(err));
+        let mut file = match bytes {
+            true => TextFile::bytes(file),
+            false => TextFile::unicode(file),
         }
-        None => stdin().read_to_string(&mut text).unwrap(),
-    };
+        .unwrap_or_else(|err| exit_with_error(err));
 
-    // Remove trailing newlines
-    while text.as_bytes().last() == Some(&b'\n') {
-        text.pop();
-    }
+        // Remove trailing newlines
+        while !file.is_empty() {
+            let last = file.len() - 1;
+
+            match file.read(last..last + 1) {
+                Ok(byte) if byte == b"\n" => file.truncate(last),
+                Ok(_) => break,
+                Err(err) => exit_with_error(err),
+            }
+        }
+
+        text_file = file;
+        Text::File(&text_file)
+    } else {
+        match filename {
+            Some(filename) => {
+                let mut file = File::open(filename).unwrap();
+                file.read_to_end(&mut input).unwrap()
+            }
+            None => stdin().read_to_end(&mut input).unwrap(),
+        };
+
+        // Remove trailing newlines
+        while input.last() == Some(&b'\n') {
+            input.pop();
+        }
+
+        match bytes {
+            true => Text::Bytes(&input),
+            false => Text::Unicode(str::from_utf8(&input).unwrap_or_else(|err| {
+                exit_with_error(format!("{}, use --bytes to read raw bytes", err))
+            })),
+        }
+    };
 
     //  __  __       _       _
     // |  \/  | __ _| |_ ___| |__
@@ -262,92 +877,264 @@ fn main() {
     // |_|  |_|\__,_|\__\___|_| |_|
     //
 
-    let automaton = regex::compile(regex_str);
+    let timer = time::Instant::now();
+
+    if let Some(index_file) = load_index {
+        let mut enumerator = File::open(index_file)
+            .and_then(|file| IndexedDag::load(&mut BufReader::new(file), text, true))
+            .unwrap_or_else(|err| exit_with_error(err))
+            .with_equality(equality);
+
+        handle_index(&mut enumerator, selection, &timer, display_format);
+        std::process::exit(0);
+    }
+
+    let automaton = compile();
     automaton
         .render("automaton.dot")
         .expect("Could not create the dotfile.");
 
-    let timer = time::Instant::now();
+    if let Some(index_file) = save_index {
+        let mut enumerator =
+            IndexedDag::new(automaton, text, jump_distance, trimming_strategy, true)
+                .with_order(order)
+                .with_threads(threads);
+        enumerator
+            .preprocess()
+            .unwrap_or_else(|err| exit_with_error(err));
+
+        File::create(index_file)
+            .and_then(|file| {
+                let mut output = BufWriter::new(file);
+                enumerator.save(&mut output)?;
+                output.flush()
+            })
+            .unwrap_or_else(|err| exit_with_error(err));
+
+        std::process::exit(0);
+    }
 
     fn handle_matches<'t>(
         enumerator: &mut impl SpannerEnumerator<'t>,
-        text: &str,
+        equality: &StringEquality,
         timer: &time::Instant,
         display_format: DisplayFormat,
     ) {
-        enumerator.preprocess();
-        let matches = enumerator.iter();
+        enumerator
+            .preprocess()
+            .unwrap_or_else(|err| exit_with_error(err));
 
         match display_format {
-            DisplayFormat::Count => {
-                let count = matches.count();
-                println!("{}", count)
+            DisplayFormat::Count { .. } if equality.is_empty() => {
+                println!("{}", enumerator.count())
             }
-            DisplayFormat::CompareFormat => {
-                for mapping in matches {
-                    let span = mapping
-                        .main_span()
-                        .expect("A mapping should never be empty");
-
-                    println!(
-                        r#">>>>{{"match": {:?}, "span": [{},{}], "time": {}}}"#,
-                        &text[span.clone()],
-                        span.start,
-                        span.end,
-                        timer.elapsed().as_millis()
-                    )
-                }
+            _ => display_matches(equality.filter(enumerator.iter()), 0, timer, display_format),
+        }
+    }
+
+    fn handle_index(
+        enumerator: &mut IndexedDag,
+        selection: Selection,
+        timer: &time::Instant,
+        display_format: DisplayFormat,
+    ) {
+        enumerator
+            .preprocess()
+            .unwrap_or_else(|err| exit_with_error(err));
+
+        let num_patterns = enumerator.num_patterns();
+        let display_format = display_format.with_patterns(num_patterns);
+
+        match selection {
+            Selection::All if display_format == (DisplayFormat::Count { by_pattern: true }) => {
+                display_counts(&enumerator.count_by_pattern(), num_patterns)
+            }
+            // The selection is applied by the index.
+            Selection::All => {
+                handle_matches(enumerator, &StringEquality::new(), timer, display_format)
+            }
+            Selection::Sample { amount, seed } => {
+                let mut rng = match seed {
+                    None => StdRng::from_entropy(),
+                    Some(seed) => StdRng::seed_from_u64(seed),
+                };
+
+                let samples = enumerator
+                    .random_access()
+                    .unwrap_or_else(|err| exit_with_error(err))
+                    .sample_distinct(amount, &mut rng);
+                display_matches(samples.into_iter(), 0, timer, display_format);
+            }
+            Selection::Page { offset, limit } => {
+                let random_access = enumerator
+                    .random_access()
+                    .unwrap_or_else(|err| exit_with_error(err));
+                let matches = random_access
+                    .iter_from(offset)
+                    .take(limit.unwrap_or(usize::MAX));
+                display_matches(matches, offset, timer, display_format);
+            }
+        }
+    }
+
+    /// Display matches, `first` being the position of the first one in the
+    /// enumeration.
+    fn display_matches<'t>(
+        matches: impl Iterator<Item = Mapping<'t>>,
+        first: u128,
+        timer: &time::Instant,
+        display_format: DisplayFormat,
+    ) {
+        if let DisplayFormat::Count { .. } = display_format {
+            println!("{}", matches.count());
+            return;
+        }
+
+        for (count, mapping) in matches.enumerate() {
+            display_match(
+                first + count as u128 + 1,
+                0,
+                &mapping,
+                timer,
+                &display_format,
+            );
+        }
+
+        if let DisplayFormat::CompareFormat { .. } = display_format {
+            display_eof(timer);
+        }
+    }
+
+    /// Display the count of matches of each pattern, matches that don't
+    /// assign any group can't be told apart and are counted as "none".
+    fn display_counts(counts: &BTreeMap<Option<usize>, u128>, num_patterns: usize) {
+        for pattern in 0..num_patterns {
+            println!("{}:{}", pattern, counts.get(&Some(pattern)).unwrap_or(&0));
+        }
+
+        if let Some(count) = counts.get(&None) {
+            println!("none:{}", count);
+        }
+    }
+
+    /// Display a match, `position` being its position in the enumeration and
+    /// `offset` the offset of its text in the input.
+    fn display_match(
+        position: u128,
+        offset: usize,
+        mapping: &Mapping,
+        timer: &time::Instant,
+        display_format: &DisplayFormat,
+    ) {
+        let pattern = || {
+            mapping
+                .get_pattern()
+                .map_or("none".to_string(), |pattern| pattern.to_string())
+        };
+
+        match display_format {
+            DisplayFormat::Count { .. } => {}
+            DisplayFormat::CompareFormat { show_pattern } => {
+                let span = mapping
+                    .main_span()
+                    .expect("A mapping should never be empty");
+
+                let pattern = match show_pattern {
+                    true => match mapping.get_pattern() {
+                        Some(pattern) => format!(r#", "pattern": {}"#, pattern),
+                        None => r#", "pattern": null"#.to_string(),
+                    },
+                    false => String::new(),
+                };
 
                 println!(
-                    r#">>>>{{"match": "EOF", "span": [-1,-1], "time": {}}}"#,
+                    r#">>>>{{"match": {}, "span": [{},{}]{}, "time": {}}}"#,
+                    escape(
+                        &mapping
+                            .get_text()
+                            .read_bytes(span.clone())
+                            .unwrap_or_else(|err| exit_with_error(err))
+                    ),
+                    span.start + offset,
+                    span.end + offset,
+                    pattern,
                     timer.elapsed().as_millis()
-                );
+                )
             }
-            DisplayFormat::Verbose { show_offset } => {
-                for (count, mapping) in matches.enumerate() {
-                    print!("{} -", count + 1);
-
-                    if show_offset {
-                        for (name, range) in mapping.iter_groups() {
-                            print!(" {}:{},{}", name, range.start, range.end);
-                        }
-                    } else {
-                        for (name, text) in mapping.iter_groups_text() {
-                            print!(" {}:{:?}", name, text);
-                        }
+            DisplayFormat::Verbose {
+                show_offset,
+                show_pattern,
+            } => {
+                print!("{} -", position);
+
+                if *show_pattern {
+                    print!(" [{}]", pattern());
+                }
+
+                if *show_offset {
+                    for (name, range) in mapping.iter_groups() {
+                        print!(" {}:{},{}", name, range.start + offset, range.end + offset);
                     }
+                } else {
+                    let groups = mapping
+                        .read_groups_bytes()
+                        .unwrap_or_else(|err| exit_with_error(err));
 
-                    println!();
+                    for (name, text) in groups {
+                        print!(" {}:{}", name, escape(&text));
+                    }
                 }
+
+                println!();
             }
         }
     }
 
+    fn display_eof(timer: &time::Instant) {
+        println!(
+            r#">>>>{{"match": "EOF", "span": [-1,-1], "time": {}}}"#,
+            timer.elapsed().as_millis()
+        );
+    }
+
+    // Naive algorithms only run over UTF-8 text, which is ensured by
+    // conflicting arguments.
+    let unicode_text = || match text {
+        Text::Unicode(text) => text,
+        Text::Bytes(_) => exit_with_error("naive algorithms can't read raw bytes"),
+        Text::File(_) => exit_with_error("naive algorithms can't read a text as a stream"),
+    };
+
     match algorithm {
         Algorithm::Naive => handle_matches(
-            &mut naive::naive::NaiveEnum::new(&automaton, &text),
-            &text,
+            &mut naive::naive::NaiveEnum::new(&automaton, unicode_text()),
+            &equality,
             &timer,
             display_format,
         ),
         Algorithm::NaiveCubic => handle_matches(
-            &mut naive::naive_cubic::NaiveEnumCubic::new(regex_str, &text).unwrap(),
-            &text,
+            &mut naive::naive_cubic::NaiveEnumCubic::new(regex_str, unicode_text())
+                .unwrap_or_else(|err| exit_with_error(err)),
+            &equality,
             &timer,
             display_format,
         ),
         Algorithm::NaiveQuadratic => handle_matches(
-            &mut naive::naive_quadratic::NaiveEnumQuadratic::new(regex_str, &text),
-            &text,
-            &timer,
-            display_format,
-        ),
-        Algorithm::ICDT19 => handle_matches(
-            &mut IndexedDag::new(automaton, &text, jump_distance, trimming_strategy, true),
-            &text,
+            &mut naive::naive_quadratic::NaiveEnumQuadratic::new(regex_str, unicode_text())
+                .unwrap_or_else(|err| exit_with_regex_error(&[regex_str], &macros, err)),
+            &equality,
             &timer,
             display_format,
         ),
+        Algorithm::ICDT19 => {
+            let mut enumerator =
+                IndexedDag::new(automaton, text, jump_distance, trimming_strategy, true)
+                    .with_order(order)
+                    .with_threads(threads)
+                    .with_equality(equality);
+
+            handle_index(&mut enumerator, selection, &timer, display_format);
+        }
     }
 
     //  ____       _                   ___        __
@@ -364,3 +1151,50 @@ fn main() {
 
     std::process::exit(0);
 }
+
+/// Quote a matched text, bytes that are not part of valid UTF-8 are escaped.
+fn escape(text: &[u8]) -> String {
+    match str::from_utf8(text) {
+        Ok(text) => format!("{:?}", text),
+        Err(_) => format!(
+            "b\"{}\"",
+            text.iter()
+                .flat_map(|&byte| std::ascii::escape_default(byte))
+                .map(char::from)
+                .collect::<String>()
+        ),
+    }
+}
+
+/// Display an error on stderr and exit with a non-zero status.
+fn exit_with_error(err: impl fmt::Display) -> ! {
+    eprintln!("error: {}", err);
+    std::process::exit(1);
+}
+
+/// Display an error that occured while compiling patterns, pointing at the
+/// faulty position for syntax errors, and exit with a non-zero status.
+fn exit_with_regex_error(regexes: &[&str], macros: &regex::Macros, err: regex::Error) -> ! {
+    eprintln!("error: {}", err);
+
+    let (regex, err) = match err {
+        regex::Error::InPattern(pattern, err) => (regexes[pattern], *err),
+        err => (regexes[0], err),
+    };
+
+    // Syntax errors in a macro are located in its definition.
+    let (regex, err) = match err {
+        regex::Error::InMacro(name, err) => (macros.get(&name).unwrap_or_default(), *err),
+        err => (regex, err),
+    };
+
+    if let regex::Error::Syntax { position, .. } = err {
+        let column = regex
+            .get(..position)
+            .map_or(0, |prefix| prefix.chars().count());
+        eprintln!("    {}", regex);
+        eprintln!("    {}^", " ".repeat(column));
+    }
+
+    std::process::exit(1);
+}
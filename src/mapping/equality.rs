@@ -0,0 +1,320 @@
+use bit_set::BitSet;
+use std::collections::HashMap;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::ops::Range;
+
+use super::super::automaton::Automaton;
+use super::{Mapping, Marker, Text};
+
+/// Maximal number of spans whose hash is kept while filtering mappings.
+const MAX_CACHED_SPANS: usize = 1 << 20;
+
+/// Base of the polynomial hashes of the prefixes of a text.
+const HASH_BASE: u64 = 0x0100_0000_01b3;
+
+/// Size of the chunks of text read while it is hashed.
+const READ_LEN: usize = 1 << 16;
+
+//  _____                  _ _ _
+// | ____|__ _ _   _  __ _| (_) |_ _   _
+// |  _| / _` | | | |/ _` | | | __| | | |
+// | |__| (_| | |_| | (_| | | | |_| |_| |
+// |_____\__, |\__,_|\__,_|_|_|\__|\__, |
+//          |_|                    |___/
+
+/// String-equality selection of core spanners, which keeps the mappings where
+/// the variables of each group capture the same text. Variables that are not
+/// assigned by a mapping are not constrained.
+///
+/// Such a selection can't be expressed by an automaton. Given to an index with
+/// `IndexedDag::with_equality`, partial mappings are discarded while the index
+/// is descended. Otherwise mappings of any enumerator can be selected once
+/// they are built with `filter`: spans of distinct lengths
+/// are rejected at once, and each span is hashed once before its text is
+/// compared, but the delay between two selected mappings depends on the number
+/// of mappings that are rejected in between.
+#[derive(Clone, Debug, Default)]
+pub struct StringEquality {
+    groups: Vec<Vec<String>>,
+}
+
+impl StringEquality {
+    pub fn new() -> StringEquality {
+        StringEquality::default()
+    }
+
+    /// Require the given variables to capture the same text.
+    pub fn with_group<I, S>(mut self, variables: I) -> StringEquality
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.groups
+            .push(variables.into_iter().map(Into::into).collect());
+        self
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.groups.is_empty()
+    }
+
+    /// Iterate over the variables that are constrained.
+    pub fn get_variables(&self) -> impl Iterator<Item = &str> {
+        self.groups.iter().flatten().map(String::as_str)
+    }
+
+    /// Check if the variables of each group capture the same text.
+    pub fn is_satisfied(&self, mapping: &Mapping) -> bool {
+        self.check(mapping, &mut SpanHashes::default())
+    }
+
+    /// Only keep the mappings that satisfy the selection.
+    pub fn filter<'s, 't: 's>(
+        &'s self,
+        mappings: impl Iterator<Item = Mapping<'t>> + 's,
+    ) -> impl Iterator<Item = Mapping<'t>> + 's {
+        let mut hashes = SpanHashes::default();
+        mappings.filter(move |mapping| self.check(mapping, &mut hashes))
+    }
+
+    fn check(&self, mapping: &Mapping, hashes: &mut SpanHashes) -> bool {
+        self.groups.iter().all(|group| {
+            let spans: Vec<_> = group
+                .iter()
+                .filter_map(|name| {
+                    mapping
+                        .iter_groups()
+                        .find(|(var, _)| var == name)
+                        .map(|(_, span)| span)
+                })
+                .collect();
+
+            let first = match spans.first() {
+                None => return true,
+                Some(first) => first,
+            };
+
+            spans.iter().all(|span| span.len() == first.len())
+                && spans
+                    .iter()
+                    .all(|span| span == first || hashes.same_text(mapping.get_text(), first, span))
+        })
+    }
+}
+
+/// Hashes of the text of spans, which are computed once.
+#[derive(Default)]
+struct SpanHashes {
+    hashes: HashMap<(usize, usize), u64>,
+}
+
+impl SpanHashes {
+    fn get(&mut self, text: Text, span: &Range<usize>) -> u64 {
+        if self.hashes.len() >= MAX_CACHED_SPANS {
+            self.hashes.clear();
+        }
+
+        *self
+            .hashes
+            .entry((span.start, span.end))
+            .or_insert_with(|| {
+                let mut hasher = DefaultHasher::new();
+                text.get_bytes(span.clone()).hash(&mut hasher);
+                hasher.finish()
+            })
+    }
+
+    /// Check if two spans capture the same text, which is only compared once
+    /// their hashes are equal.
+    fn same_text(&mut self, text: Text, x: &Range<usize>, y: &Range<usize>) -> bool {
+        self.get(text, x) == self.get(text, y)
+            && text.get_bytes(x.clone()) == text.get_bytes(y.clone())
+    }
+}
+
+/// A string-equality selection applied while an index is descended. The
+/// markers of a partial mapping are assigned from one end of the text towards
+/// the other, thus the text captured by a variable whose first marker is
+/// assigned is known up to the current level of the index. Partial mappings
+/// are discarded as soon as this text can't match the text known for the
+/// other variables of its group, which is checked with hashes of the prefixes
+/// of the text. Texts of complete mappings are then compared.
+pub(super) struct EqualityIndex {
+    /// Ids of the variables of each group.
+    groups: Vec<Vec<usize>>,
+    constrained: BitSet,
+    /// Polynomial hash of each prefix of the text, by its length in bytes.
+    prefixes: Vec<u64>,
+    /// Markers are assigned from the end of the text, as in `Order::Backward`,
+    /// otherwise from its start and positions are counted from its end.
+    backward: bool,
+}
+
+impl EqualityIndex {
+    pub(super) fn new(
+        equality: &StringEquality,
+        automaton: &Automaton,
+        text: Text,
+        backward: bool,
+    ) -> io::Result<EqualityIndex> {
+        let variables = automaton.get_variables();
+
+        let groups: Vec<Vec<usize>> = (equality.groups.iter())
+            .map(|group| {
+                (variables.iter())
+                    .filter(|var| group.iter().any(|name| name == var.get_name()))
+                    .map(|var| var.get_id())
+                    .collect()
+            })
+            .collect();
+
+        let mut index = EqualityIndex {
+            constrained: groups.iter().flatten().cloned().collect(),
+            groups,
+            prefixes: vec![0],
+            backward,
+        };
+
+        index.hash_text(text, 0)?;
+        Ok(index)
+    }
+
+    /// Hash the text from a given offset, the text before this offset must be
+    /// the one that was already hashed.
+    pub(super) fn hash_text(&mut self, text: Text, from: usize) -> io::Result<()> {
+        self.prefixes.truncate(from + 1);
+        let mut hash = self.prefixes[from];
+
+        for start in (from..text.len()).step_by(READ_LEN) {
+            let bytes = text.read_bytes(start..text.len().min(start + READ_LEN))?;
+
+            for &byte in bytes.iter() {
+                hash = hash
+                    .wrapping_mul(HASH_BASE)
+                    .wrapping_add(u64::from(byte) + 1);
+                self.prefixes.push(hash);
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(super) fn is_constrained(&self, marker: &Marker) -> bool {
+        self.constrained.contains(marker.variable().get_id())
+    }
+
+    /// Check if a partial mapping can still be completed into a mapping that
+    /// satisfies the selection, given its markers with their position in the
+    /// index and the position of the next level where markers are assigned.
+    pub(super) fn is_feasible(&self, markers: &[(&Marker, usize)], next_pos: usize) -> bool {
+        self.groups.iter().all(|group| {
+            let known = self.known_texts(group, markers, next_pos);
+
+            // The text of a variable whose markers are assigned is complete,
+            // others may still be extended.
+            let len = known.iter().find(|(_, complete)| *complete);
+            let len = len.map(|(span, _)| span.len());
+
+            if let Some(len) = len {
+                let valid_len = |(span, complete): &(Range<usize>, bool)| match complete {
+                    true => span.len() == len,
+                    false => span.len() <= len,
+                };
+
+                if !known.iter().all(valid_len) {
+                    return false;
+                }
+            }
+
+            let longest = known.iter().max_by_key(|(span, _)| span.len());
+
+            match longest {
+                None => true,
+                Some((longest, _)) => known.iter().all(|(span, _)| {
+                    self.hash(self.align(span, span.len()))
+                        == self.hash(self.align(longest, span.len()))
+                }),
+            }
+        })
+    }
+
+    /// Check if a complete mapping satisfies the selection, given its markers
+    /// with their position in the index. Texts are only compared once their
+    /// hashes are equal.
+    pub(super) fn is_satisfied(&self, text: Text, markers: &[(&Marker, usize)]) -> bool {
+        self.is_feasible(markers, 0)
+            && self.groups.iter().all(|group| {
+                let spans: Vec<_> = (self.known_texts(group, markers, 0).into_iter())
+                    .map(|(span, _)| span)
+                    .collect();
+
+                spans.windows(2).all(|pair| {
+                    pair[0] == pair[1]
+                        || text.get_bytes(pair[0].clone()) == text.get_bytes(pair[1].clone())
+                })
+            })
+    }
+
+    /// Spans of the text known to be captured by the assigned variables of a
+    /// group, given the position of the next level where markers are
+    /// assigned, together with whether the whole text of the variable is
+    /// known.
+    fn known_texts(
+        &self,
+        group: &[usize],
+        markers: &[(&Marker, usize)],
+        next_pos: usize,
+    ) -> Vec<(Range<usize>, bool)> {
+        let len = self.prefixes.len() - 1;
+
+        group
+            .iter()
+            .filter_map(|&id| {
+                let mut positions = (markers.iter())
+                    .filter(|(marker, _)| marker.variable().get_id() == id)
+                    .map(|(_, pos)| *pos);
+
+                let first = positions.next()?;
+
+                let (span, complete) = match positions.next() {
+                    None => (next_pos..first, false),
+                    Some(second) => (first.min(second)..first.max(second), true),
+                };
+
+                match self.backward {
+                    true => Some((span, complete)),
+                    false => Some((len - span.end..len - span.start, complete)),
+                }
+            })
+            .collect()
+    }
+
+    /// Part of the known text of a variable of given length that is shared
+    /// with other variables: its end as markers are assigned from the end of
+    /// the text, its start otherwise.
+    fn align(&self, span: &Range<usize>, len: usize) -> Range<usize> {
+        match self.backward {
+            true => span.end - len..span.end,
+            false => span.start..span.start + len,
+        }
+    }
+
+    fn hash(&self, span: Range<usize>) -> u64 {
+        let mut power: u64 = 1;
+        let mut base = HASH_BASE;
+        let mut exponent = span.len();
+
+        while exponent > 0 {
+            if exponent & 1 == 1 {
+                power = power.wrapping_mul(base);
+            }
+
+            base = base.wrapping_mul(base);
+            exponent >>= 1;
+        }
+
+        self.prefixes[span.end].wrapping_sub(self.prefixes[span.start].wrapping_mul(power))
+    }
+}
@@ -1,13 +1,32 @@
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::error;
+use std::fmt;
+use std::io;
+use std::io::prelude::*;
 use std::iter;
+use std::ops::Range;
+use std::str;
 
 use super::super::automaton::Automaton;
-use super::super::mapping::{Mapping, Marker, SpannerEnumerator};
+use super::super::mapping::{Mapping, Marker, SpannerEnumerator, StringEquality, Text, TextFile};
+use super::super::parallel::{explore_parallel, map_parallel};
+use super::super::persist::{Persist, invalid_data};
 use super::super::progress::Progress;
-use super::jump::Jump;
+use super::equality::EqualityIndex;
+use super::jump::{Jump, SEGMENT_LEN, successors};
+use super::levelset::LevelFile;
 use bit_set::BitSet;
+use rand::Rng;
 use serde::{Deserialize, Serialize};
 use std::time::{Duration, Instant};
 
+/// Magic number at the beginning of files holding a persisted index.
+const INDEX_MAGIC: &[u8; 8] = b"ESPANIDX";
+
+/// Version of the format of persisted indexes, it must be increased whenever
+/// the layout of persisted structures changes.
+const INDEX_VERSION: u64 = 3;
+
 //  ___           _                   _ ____
 // |_ _|_ __   __| | _____  _____  __| |  _ \  __ _  __ _
 //  | || '_ \ / _` |/ _ \ \/ / _ \/ _` | | | |/ _` |/ _` |
@@ -22,10 +41,15 @@ use std::time::{Duration, Instant};
 /// constant delay iteration).
 pub struct IndexedDag<'t> {
     automaton: Automaton,
-    text: &'t str,
+    text: Text<'t>,
     jump_distance: usize,
     trimming_strategy: TrimmingStrategy,
+    order: Order,
+    threads: usize,
+    equality: StringEquality,
+    selection: Option<EqualityIndex>,
     jump: Option<Jump>,
+    preprocessed: bool,
     toggle_progress: bool,
     create_dag_time: Option<Duration>,
     trim_time: Option<Duration>,
@@ -39,27 +63,188 @@ pub enum TrimmingStrategy {
     FullTrimming,
 }
 
+/// Order in which the mappings are enumerated, it is determined by the
+/// direction in which the index is built.
+#[derive(Eq, PartialEq, Serialize, Deserialize, Clone, Copy, Debug)]
+pub enum Order {
+    /// Mappings are sorted by decreasing end of their main span.
+    Backward,
+    /// Mappings are sorted by increasing start of their main span, the index
+    /// is built over the mirror of the text and of the automaton.
+    Document,
+}
+
 impl<'t> IndexedDag<'t> {
+    /// Prepare the index of an automaton over a text, the automaton is
+    /// normalized while preprocessing if its assignations don't lead to
+    /// greater states, and only its valid runs are kept if it is not
+    /// sequential.
     pub fn new(
         automaton: Automaton,
-        text: &str,
+        text: impl Into<Text<'t>>,
         jump_distance: usize,
         trimming_strategy: TrimmingStrategy,
         toggle_progress: bool,
-    ) -> IndexedDag {
+    ) -> IndexedDag<'t> {
         IndexedDag {
             automaton,
-            text,
+            text: text.into(),
             jump_distance,
             trimming_strategy,
             toggle_progress,
+            order: Order::Backward,
+            threads: 1,
+            equality: StringEquality::new(),
+            selection: None,
             jump: None,
+            preprocessed: false,
             create_dag_time: None,
             trim_time: None,
             index_time: None,
         }
     }
 
+    /// Choose the order in which mappings are enumerated, this must be set
+    /// before preprocessing.
+    pub fn with_order(mut self, order: Order) -> IndexedDag<'t> {
+        assert!(
+            !self.preprocessed,
+            "the order must be set before preprocessing"
+        );
+        self.order = order;
+        self
+    }
+
+    pub fn get_order(&self) -> Order {
+        self.order
+    }
+
+    pub fn get_text(&self) -> Text<'t> {
+        self.text
+    }
+
+    /// Choose the number of threads used during preprocessing, the text is
+    /// then split into as many chunks over which the DAG and its jump
+    /// pointers are built in parallel.
+    pub fn with_threads(mut self, threads: usize) -> IndexedDag<'t> {
+        assert!(threads > 0, "at least one thread is required");
+        self.threads = threads;
+        self
+    }
+
+    pub fn get_threads(&self) -> usize {
+        self.threads
+    }
+
+    /// Only enumerate the mappings that satisfy a string-equality selection.
+    /// Partial mappings are discarded while the index is descended, thus the
+    /// selection is also respected by `count`, `count_by_pattern`, random
+    /// access and parallel enumeration. The text is hashed for the selection
+    /// while preprocessing, which is then required even over a loaded index.
+    pub fn with_equality(mut self, equality: StringEquality) -> IndexedDag<'t> {
+        self.equality = equality;
+        self.selection = None;
+        self
+    }
+
+    pub fn get_equality(&self) -> &StringEquality {
+        &self.equality
+    }
+
+    /// Write the index computed by `preprocess` into a binary stream, it can
+    /// later be loaded back with `IndexedDag::load` over the same text.
+    pub fn save<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        if !self.preprocessed {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the index must be preprocessed before being saved",
+            ));
+        }
+
+        let automaton = serde_json::to_string(&self.automaton)?;
+
+        output.write_all(INDEX_MAGIC)?;
+        INDEX_VERSION.persist(output)?;
+        self.text.len().persist(output)?;
+        text_checksum(self.text)?.persist(output)?;
+        self.jump_distance.persist(output)?;
+        self.trimming_strategy.persist(output)?;
+        self.order.persist(output)?;
+        automaton.persist(output)?;
+
+        match &self.jump {
+            None => 0u64.persist(output),
+            Some(jump) => {
+                1u64.persist(output)?;
+                jump.persist(output)
+            }
+        }
+    }
+
+    /// Load an index written by `IndexedDag::save`, the result is already
+    /// preprocessed and ready for enumeration. The input text must be the
+    /// same as the one the index was built over.
+    pub fn load<R: Read>(
+        input: &mut R,
+        text: impl Into<Text<'t>>,
+        toggle_progress: bool,
+    ) -> io::Result<IndexedDag<'t>> {
+        let text = text.into();
+        let mut magic = [0; 8];
+        input.read_exact(&mut magic)?;
+
+        if &magic != INDEX_MAGIC {
+            return Err(invalid_data("not an index file"));
+        }
+
+        let version = u64::restore(input)?;
+
+        if version != INDEX_VERSION {
+            return Err(invalid_data(&format!(
+                "unsupported index version {} (expected {})",
+                version, INDEX_VERSION
+            )));
+        }
+
+        let text_len = usize::restore(input)?;
+        let checksum = u64::restore(input)?;
+
+        if text_len != text.len() || checksum != text_checksum(text)? {
+            return Err(invalid_data("the index was built over a different text"));
+        }
+
+        let jump_distance = usize::restore(input)?;
+        let trimming_strategy = TrimmingStrategy::restore(input)?;
+        let order = Order::restore(input)?;
+        let automaton: Automaton = serde_json::from_str(&String::restore(input)?)?;
+
+        let jump = match u64::restore(input)? {
+            0 => None,
+            1 => Some(Jump::restore(input)?),
+            _ => return Err(invalid_data("invalid index")),
+        };
+
+        if let Some(jump) = &jump {
+            if jump.num_vertices() != automaton.get_nb_states()
+                || jump.get_pos(jump.num_levels() - 1) != text.len()
+            {
+                return Err(invalid_data("the index doesn't match its automaton"));
+            }
+        }
+
+        let mut indexed_dag = IndexedDag::new(
+            automaton,
+            text,
+            jump_distance,
+            trimming_strategy,
+            toggle_progress,
+        );
+        indexed_dag.order = order;
+        indexed_dag.jump = jump;
+        indexed_dag.preprocessed = true;
+        Ok(indexed_dag)
+    }
+
     pub fn num_levels(&self) -> Option<usize> {
         self.jump.as_ref().map(|j| j.num_levels())
     }
@@ -85,7 +270,7 @@ impl<'t> IndexedDag<'t> {
             for (label, target) in &adj[source] {
                 let label_id = label.get_marker().unwrap().get_id();
                 if !k.contains(label_id) {
-                    expected_markers.push(&label.get_marker().unwrap());
+                    expected_markers.push(label.get_marker().unwrap());
                     k.insert(label_id);
                 }
                 if !states.contains(*target) {
@@ -98,6 +283,249 @@ impl<'t> IndexedDag<'t> {
         NextLevelIterator::explore(&self.automaton, expected_markers, gamma)
     }
 
+    /// Symbols of the text and their width, in the order the levels of the
+    /// index are built.
+    fn symbols(&self) -> Box<dyn DoubleEndedIterator<Item = (char, usize)> + 't> {
+        symbols_in_order(self.text, self.order)
+    }
+
+    /// Chunks of the text processed by each thread, in the order the levels
+    /// of the index are built.
+    fn chunks(&self) -> Vec<Text<'t>> {
+        let mut chunks = self.text.split(self.threads);
+
+        if self.order == Order::Document {
+            chunks.reverse();
+        }
+
+        chunks
+    }
+
+    /// Build a mapping from markers assigned at given levels positions, which
+    /// are mirrored if the index is built over the mirror of the text.
+    fn build_mapping(&self, markers: Vec<(&Marker, usize)>, num_vars: usize) -> Mapping<'t> {
+        let len = self.text.len();
+        let aligned_markers = markers.into_iter().map(|(marker, pos)| match self.order {
+            Order::Backward => (marker.clone(), pos),
+            Order::Document => (marker.clone(), len - pos),
+        });
+
+        Mapping::from_markers(self.text, aligned_markers, num_vars)
+    }
+
+    /// Node the enumeration starts from, if there is any match.
+    fn root(&self) -> Option<(usize, BitSet)> {
+        assert!(
+            self.jump.is_none() || self.equality.is_empty() || self.selection.is_some(),
+            "the index must be preprocessed once a selection is chosen"
+        );
+
+        self.jump.as_ref().map(|jump| {
+            let mut start = jump.finals();
+            start.intersect_with(&self.automaton.finals);
+            (jump.num_levels() - 1, start)
+        })
+    }
+
+    /// Successors of a node `(level, gamma)` explored by the enumeration,
+    /// along with the markers assigned at this level. On level 0, a successor
+    /// `None` is yielded for each mapping that is complete. Successors that
+    /// can't satisfy the string-equality selection are discarded, given the
+    /// markers already assigned by the partial mapping.
+    fn successors<'a: 'b, 'b>(
+        &'a self,
+        level: usize,
+        gamma: BitSet,
+        assigned: &'b [(&'a Marker, usize)],
+    ) -> impl Iterator<Item = (Vec<&'a Marker>, Option<(usize, BitSet)>)> + 'b {
+        let jump = self.jump.as_ref().unwrap();
+
+        self.next_level(gamma)
+            .filter_map(move |(s_p, mut new_gamma)| {
+                if new_gamma.is_empty()
+                    || (level == 0 && !new_gamma.contains(self.automaton.get_initial()))
+                {
+                    return None;
+                }
+
+                let next = match level {
+                    0 => None,
+                    _ => Some((jump.jump(level, &mut new_gamma)?, new_gamma)),
+                };
+
+                if self.selection.is_some() {
+                    let pos = jump.get_pos(level);
+                    let markers: Vec<_> = (assigned.iter().cloned())
+                        .chain(s_p.iter().map(|&marker| (marker, pos)))
+                        .collect();
+
+                    if !self.is_selected(&markers, next.as_ref().map(|(level, _)| *level)) {
+                        return None;
+                    }
+                }
+
+                Some((s_p, next))
+            })
+    }
+
+    /// Check if a partial mapping can still satisfy the string-equality
+    /// selection, given the next level where markers are assigned, or if a
+    /// complete mapping satisfies it when there is no such level.
+    fn is_selected(&self, markers: &[(&Marker, usize)], next_level: Option<usize>) -> bool {
+        let selection = match &self.selection {
+            None => return true,
+            Some(selection) => selection,
+        };
+
+        match next_level {
+            None => selection.is_satisfied(self.text, markers),
+            Some(level) => {
+                selection.is_feasible(markers, self.jump.as_ref().unwrap().get_pos(level))
+            }
+        }
+    }
+
+    /// Markers of the variables constrained by the string-equality selection,
+    /// after some markers are assigned at a level to a partial mapping. Nodes
+    /// of the enumeration can only be merged if they share these markers.
+    fn assigned_after<'a>(
+        &self,
+        assigned: &[(&'a Marker, usize)],
+        level: usize,
+        markers: &[&'a Marker],
+    ) -> Vec<(&'a Marker, usize)> {
+        let selection = match &self.selection {
+            None => return Vec::new(),
+            Some(selection) => selection,
+        };
+
+        let pos = self.jump.as_ref().unwrap().get_pos(level);
+
+        (assigned.iter().cloned())
+            .chain(markers.iter().map(|&marker| (marker, pos)))
+            .filter(|(marker, _)| selection.is_constrained(marker))
+            .collect()
+    }
+
+    /// Get the number of patterns the automaton of the index was compiled
+    /// from, see `regex::compile_many`.
+    pub fn num_patterns(&self) -> usize {
+        self.automaton.num_patterns()
+    }
+
+    /// Count the number of distinct mappings of each pattern, when several
+    /// patterns are compiled into a single automaton, saturating at
+    /// `u128::MAX`. Mappings that don't assign any variable are counted under
+    /// `None`. As for `count`, mappings are not enumerated.
+    pub fn count_by_pattern(&self) -> BTreeMap<Option<usize>, u128> {
+        let mut totals = BTreeMap::new();
+
+        let (root_level, root_gamma) = match self.root() {
+            None => return totals,
+            Some(root) => root,
+        };
+
+        // The pattern of a partial mapping is known from its first marker.
+        let mut pending: BTreeMap<usize, HashMap<_, u128>> = BTreeMap::new();
+        pending
+            .entry(root_level)
+            .or_default()
+            .insert((root_gamma, None, Vec::new()), 1);
+
+        while let Some((level, nodes)) = pending.pop_last() {
+            for ((gamma, pattern, assigned), count) in nodes {
+                for (markers, next) in self.successors(level, gamma, &assigned) {
+                    let pattern =
+                        pattern.or_else(|| markers.first().map(|m| m.variable().get_pattern()));
+
+                    let target = match next {
+                        None => totals.entry(pattern).or_insert(0),
+                        Some((next_level, next_gamma)) => pending
+                            .entry(next_level)
+                            .or_default()
+                            .entry((
+                                next_gamma,
+                                pattern,
+                                self.assigned_after(&assigned, level, &markers),
+                            ))
+                            .or_insert(0),
+                    };
+
+                    *target = target.saturating_add(count);
+                }
+            }
+        }
+
+        totals
+    }
+
+    /// Prepare random access to the mappings, this requires to count the
+    /// mappings reachable from each node of the index. This fails if the text
+    /// is stored in a file that can't be read.
+    pub fn random_access<'i>(&'i self) -> io::Result<RandomAccess<'i, 't>> {
+        RandomAccess::init(self)
+    }
+
+    /// Enumerate the mappings whose main span ends after a given offset, such
+    /// as the mappings that overlap with text appended after this offset.
+    ///
+    /// Mappings are enumerated by decreasing end of their main span, thus the
+    /// enumeration stops at the first mapping that ends before the offset. It
+    /// requires the index to be built in `Order::Backward`.
+    pub fn iter_ending_after<'i>(
+        &'i self,
+        offset: usize,
+    ) -> impl Iterator<Item = Mapping<'t>> + 'i {
+        assert!(
+            self.order == Order::Backward,
+            "mappings are only sorted by their end in backward order"
+        );
+
+        self.iter()
+            .take_while(move |mapping| match mapping.main_span() {
+                None => false,
+                Some(span) => span.end > offset,
+            })
+    }
+
+    /// Enumerate the mappings from several threads, which are distributed
+    /// between threads as the enumeration goes. The callback is called from
+    /// worker threads with the index of the worker and a mapping, for example
+    /// to write mappings to one file per worker or to send them through a
+    /// channel. Mappings are not delivered in the enumeration order.
+    pub fn for_each_parallel<F>(&self, threads: usize, callback: F)
+    where
+        F: Fn(usize, Mapping<'t>) + Sync,
+    {
+        let jump = match &self.jump {
+            None => return,
+            Some(jump) => jump,
+        };
+
+        let roots = match self.root() {
+            None => Vec::new(),
+            Some((level, gamma)) => vec![(level, gamma, Vec::new())],
+        };
+
+        let num_vars = self.automaton.num_vars();
+
+        explore_parallel(roots, threads, |worker, (level, gamma, mapping), stack| {
+            let pos = jump.get_pos(level);
+
+            for (s_p, next) in self.successors(level, gamma, &mapping) {
+                let mut new_mapping: Vec<(&Marker, usize)> = mapping.clone();
+                new_mapping.extend(s_p.into_iter().map(|marker| (marker, pos)));
+
+                match next {
+                    None => callback(worker, self.build_mapping(new_mapping, num_vars)),
+                    Some((next_level, next_gamma)) => {
+                        stack.push_back((next_level, next_gamma, new_mapping))
+                    }
+                }
+            }
+        });
+    }
+
     pub fn get_memory_usage(&self) -> Option<(usize, usize, usize, usize)> {
         self.jump.as_ref().map(|j| j.get_memory_usage())
     }
@@ -112,8 +540,99 @@ impl<'t> SpannerEnumerator<'t> for IndexedDag<'t> {
         Box::new(IndexedDagIterator::init(self))
     }
 
-    /// Compute the index of matches of an automaton over input text.
-    fn preprocess(&mut self) {
+    /// Count the mappings without enumerating them.
+    ///
+    /// The nodes explored by the enumeration are merged when they share the
+    /// same level and set of states, and the number of distinct partial
+    /// mappings leading to each of them is propagated from the last level
+    /// to the first one. Thus the running time only depends on the number of
+    /// distinct nodes: it is linear in the text, but each level may hold a node
+    /// for every subset of states of the automaton, hence a factor that can be
+    /// exponential in its number of states. With a string-equality selection,
+    /// nodes are also told apart by the markers of constrained variables that
+    /// are already assigned, thus their number also grows with the number of
+    /// spans of these variables.
+    fn count(&self) -> u128 {
+        let (root_level, root_gamma) = match self.root() {
+            None => return 0,
+            Some(root) => root,
+        };
+
+        // Number of partial mappings reaching each node, indexed by level.
+        let mut pending: BTreeMap<usize, HashMap<_, u128>> = BTreeMap::new();
+        pending
+            .entry(root_level)
+            .or_default()
+            .insert((root_gamma, Vec::new()), 1);
+
+        let mut total: u128 = 0;
+
+        while let Some((level, nodes)) = pending.pop_last() {
+            for ((gamma, assigned), count) in nodes {
+                for (markers, next) in self.successors(level, gamma, &assigned) {
+                    let target = match next {
+                        None => &mut total,
+                        Some((next_level, next_gamma)) => pending
+                            .entry(next_level)
+                            .or_default()
+                            .entry((next_gamma, self.assigned_after(&assigned, level, &markers)))
+                            .or_insert(0),
+                    };
+
+                    *target = target.saturating_add(count);
+                }
+            }
+        }
+
+        total
+    }
+
+    /// Compute the index of matches of an automaton over input text, this has
+    /// no effect if the index has already been computed or loaded. Errors are
+    /// raised if the automaton can't be normalized or while reading a text
+    /// stored in a file, in which case the index is left empty.
+    ///
+    /// The DAG of a text stored in a file is built while reading the file as
+    /// a stream and is kept in a temporary file, only the levels of the DAG
+    /// that can be jumped to are loaded in memory. Preprocessing a text stored
+    /// in a file is not parallel.
+    fn preprocess(&mut self) -> io::Result<()> {
+        if !self.preprocessed {
+            let automaton = match self.automaton.check_sequential() {
+                Err(_) => Some(self.automaton.sequential()),
+                Ok(()) if !self.automaton.is_normalized() => Some(self.automaton.normalize()),
+                Ok(()) => None,
+            };
+
+            if let Some(automaton) = automaton {
+                self.automaton =
+                    automaton.map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, err))?;
+            }
+        }
+
+        if self.selection.is_none() && !self.equality.is_empty() {
+            self.selection = Some(EqualityIndex::new(
+                &self.equality,
+                &self.automaton,
+                self.text,
+                self.order == Order::Backward,
+            )?);
+        }
+
+        if let Text::File(text) = self.text {
+            return self.preprocess_file(text);
+        }
+
+        if self.preprocessed {
+            return Ok(());
+        }
+
+        self.preprocessed = true;
+
+        if self.order == Order::Document {
+            self.automaton = self.automaton.reversed();
+        }
+
         // Compute the jump function
         let mut jump = Jump::new(
             iter::once(self.automaton.get_initial()),
@@ -128,18 +647,23 @@ impl<'t> SpannerEnumerator<'t> for IndexedDag<'t> {
 
         let start_time = Instant::now();
 
-        let chars = self.text.chars();
-        let mut progress = Progress::from_iter(chars).auto_refresh(self.toggle_progress);
+        if self.threads > 1 {
+            self.preprocess_parallel(jump);
+            return Ok(());
+        }
+
+        let progress = Progress::from_iter(self.symbols()).auto_refresh(self.toggle_progress);
 
-        while let Some(curr_char) = progress.next() {
+        for (curr_char, _) in progress {
             let adj_for_char = self.automaton.get_adj_for_char_with_closure(curr_char);
             jump.init_next_level(adj_for_char);
 
             if jump.is_disconnected() {
-                return;
+                return Ok(());
             }
         }
 
+        jump.record_checkpoints(0);
         self.create_dag_time = Some(start_time.elapsed());
 
         let start_time = Instant::now();
@@ -149,15 +673,15 @@ impl<'t> SpannerEnumerator<'t> for IndexedDag<'t> {
         }
 
         if jump.is_disconnected() {
-            return;
+            return Ok(());
         }
 
         if self.trimming_strategy != TrimmingStrategy::NoTrimming {
-            let chars = self.text.chars();
             let mut level = jump.get_last_level();
-            let mut progress = Progress::from_iter(chars.rev()).auto_refresh(self.toggle_progress);
+            let progress =
+                Progress::from_iter(self.symbols().rev()).auto_refresh(self.toggle_progress);
 
-            while let Some(curr_char) = progress.next() {
+            for (curr_char, _) in progress {
                 let rev_adj_for_char = self.automaton.get_rev_adj_for_char_with_closure(curr_char);
                 jump.trim_level(level, rev_adj_for_char);
                 level -= 1;
@@ -165,15 +689,481 @@ impl<'t> SpannerEnumerator<'t> for IndexedDag<'t> {
         }
 
         self.trim_time = Some(start_time.elapsed());
+        self.init_jump_levels(jump);
+        Ok(())
+    }
+}
+
+impl<'t> IndexedDag<'t> {
+    /// Extend the text of the index, which must start with the text the index
+    /// was built over. Only the appended text is read, the levels of the index
+    /// are extended from its last level as if the whole text was preprocessed.
+    ///
+    /// Mappings over the extended text can then be enumerated as usual, or
+    /// only the ones that overlap with the appended text with
+    /// `iter_ending_after`. Since more text may follow, the last level of an
+    /// index that can be extended must not be trimmed: this is only supported
+    /// for an index built in `Order::Backward` without `FullTrimming`.
+    /// Vertices of the levels that were already indexed that can't reach the
+    /// appended text are not trimmed either.
+    pub fn append(&mut self, text: impl Into<Text<'t>>) -> io::Result<()> {
+        let text = text.into();
+        let old_len = self.text.len();
+
+        if text.len() < old_len || text.is_unicode() != self.text.is_unicode() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the text must extend the text of the index",
+            ));
+        }
+
+        if !self.preprocessed {
+            self.text = text;
+            return Ok(());
+        }
+
+        if self.order != Order::Backward || self.trimming_strategy == TrimmingStrategy::FullTrimming
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "text can only be appended to an index built in backward order without full \
+                 trimming",
+            ));
+        }
+
+        let symbols = text.read_symbols(old_len..text.len())?;
+
+        if let Some(selection) = &mut self.selection {
+            selection.hash_text(text, old_len)?;
+        }
+
+        self.text = text;
+        self.extend_index(&symbols);
+        Ok(())
+    }
+
+    /// Replace a range of the text of the index, given the whole updated text
+    /// and the range of bytes of the previous text that was edited, so that
+    /// text can be inserted, deleted or replaced.
+    ///
+    /// The index is split into segments of at most 1024 characters, and
+    /// only the segments around the edit are built again, as far as the
+    /// vertices kept at their boundaries differ from before with any trimming
+    /// strategy. Other segments are kept and the reach matrices of following
+    /// boundaries are composed again, thus enumeration remains constant-delay.
+    /// This is only supported in `Order::Backward`.
+    pub fn update(&mut self, text: impl Into<Text<'t>>, edit: Range<usize>) -> io::Result<()> {
+        let text = text.into();
+
+        if edit.start > edit.end
+            || edit.end > self.text.len()
+            || edit.start > text.len()
+            || text.is_unicode() != self.text.is_unicode()
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the edit must be a range of the text of the index",
+            ));
+        }
+
+        if !self.preprocessed {
+            self.text = text;
+            return Ok(());
+        }
+
+        if self.order != Order::Backward {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "text can only be updated in an index built in backward order",
+            ));
+        }
+
+        if let Some(selection) = &mut self.selection {
+            selection.hash_text(text, edit.start)?;
+        }
+
+        let jump = match &self.jump {
+            Some(jump) if jump.num_boundaries() > 1 => jump,
+            _ => {
+                self.text = text;
+                self.jump = None;
+                self.preprocessed = false;
+                return self.preprocess();
+            }
+        };
+
+        let num_vertices = self.automaton.get_nb_states();
+        let final_vertices = Jump::final_vertices(
+            &self.automaton.finals,
+            self.automaton.get_closure_for_assignations(),
+        );
+
+        let mut segments = jump.segments_around(&edit);
+
+        let (symbols, forward, trimmed) = loop {
+            let start = jump.get_boundary_pos(segments.start);
+            let end = jump.get_boundary_pos(segments.end) + text.len() - self.text.len();
+            let is_last = segments.end + 1 == jump.num_boundaries();
+            let symbols = text.read_symbols(start..end)?;
+
+            // Segments that get too short are merged with their neighbours.
+            if symbols.len() < SEGMENT_LEN / 2 && (segments.start > 0 || !is_last) {
+                if is_last {
+                    segments.start -= 1;
+                } else {
+                    segments.end += 1;
+                }
+
+                continue;
+            }
+
+            let mut forward = vec![jump.get_boundary_forward(segments.start).clone()];
+
+            for &(symbol, _) in &symbols {
+                let adj_for_char = self.automaton.get_adj_for_char_with_closure(symbol);
+                let vertices = successors(adj_for_char, forward.last().unwrap(), num_vertices);
+                forward.push(vertices);
+            }
+
+            // The DAG that follows is unchanged if the same vertices are
+            // reached at the last boundary.
+            if !is_last && forward.last() != Some(jump.get_boundary_forward(segments.end)) {
+                segments.end += 1;
+                continue;
+            }
+
+            let mut trimmed = forward.clone();
+
+            if self.trimming_strategy != TrimmingStrategy::NoTrimming {
+                let last_level = trimmed.last_mut().unwrap();
+
+                if !is_last {
+                    *last_level = jump.get_boundary_level(segments.end);
+                } else if self.trimming_strategy == TrimmingStrategy::FullTrimming {
+                    last_level.intersect_with(&final_vertices);
+                }
+
+                for (level, &(symbol, _)) in symbols.iter().enumerate().rev() {
+                    let rev_adj_for_char = self.automaton.get_rev_adj_for_char_with_closure(symbol);
+                    let keep = successors(rev_adj_for_char, &trimmed[level + 1], num_vertices);
+                    trimmed[level].intersect_with(&keep);
+                }
+            }
+
+            // The levels that precede are still valid if they hold all the
+            // vertices of the first boundary that are kept.
+            if segments.start > 0 && !trimmed[0].is_subset(&jump.get_boundary_level(segments.start))
+            {
+                segments.start -= 1;
+                continue;
+            }
+
+            break (symbols, forward, trimmed);
+        };
+
+        self.text = text;
+
+        if trimmed.iter().any(BitSet::is_empty) {
+            self.jump = None;
+            return Ok(());
+        }
+
+        let automaton = &self.automaton;
+        let symbols: Vec<_> = symbols
+            .iter()
+            .map(|&(symbol, width)| {
+                (
+                    width,
+                    automaton.get_prepared_adj_for_char(symbol).as_slice(),
+                )
+            })
+            .collect();
+
+        self.jump.as_mut().unwrap().replace_segments(
+            segments,
+            &symbols,
+            &forward,
+            &trimmed,
+            automaton.get_closure_for_assignations(),
+        );
+
+        Ok(())
+    }
+
+    /// Index the symbols that follow the last level of the index, which is
+    /// not trimmed.
+    fn extend_index(&mut self, symbols: &[(char, usize)]) {
+        let jump = match &mut self.jump {
+            None => return,
+            Some(jump) => jump,
+        };
+
+        let closure_for_assignations = self.automaton.get_closure_for_assignations().clone();
+        let first_level = jump.get_last_level();
+        jump.extend_levels(symbols.len());
+
+        for &(curr_char, _) in symbols {
+            let adj_for_char = self.automaton.get_adj_for_char_with_closure(curr_char);
+            jump.init_next_level(adj_for_char);
+
+            if jump.is_disconnected() {
+                self.jump = None;
+                return;
+            }
+        }
+
+        jump.record_checkpoints(first_level);
+
+        // The first level of the DAG is already indexed, it can't be trimmed.
+        if self.trimming_strategy != TrimmingStrategy::NoTrimming {
+            let mut level = jump.get_last_level();
+
+            for &(curr_char, _) in symbols.iter().skip(1).rev() {
+                let rev_adj_for_char = self.automaton.get_rev_adj_for_char_with_closure(curr_char);
+                jump.trim_level(level, rev_adj_for_char);
+                level -= 1;
+            }
+        }
+
+        for (level, &(curr_char, width)) in (first_level + 1..).zip(symbols) {
+            let adj_for_char = self.automaton.get_adj_for_char(curr_char);
+            jump.init_reach(level, width, adj_for_char, &closure_for_assignations);
+        }
+    }
+
+    fn preprocess_file(&mut self, text: &TextFile) -> io::Result<()> {
+        if self.preprocessed {
+            return Ok(());
+        }
+
+        self.preprocessed = true;
+
+        if self.order == Order::Document {
+            self.automaton = self.automaton.reversed();
+        }
+
+        let mut jump = Jump::new(
+            iter::once(self.automaton.get_initial()),
+            self.automaton.get_closure_for_assignations(),
+            self.automaton.get_jump_states(),
+            1,
+            self.automaton.get_nb_states(),
+            self.jump_distance,
+        );
+
+        let num_vertices = self.automaton.get_nb_states();
+        let closure_for_assignations = self.automaton.get_closure_for_assignations().clone();
+        let start_time = Instant::now();
+
+        // Build the DAG, level by level, while reading the text.
+        let mut levels = LevelFile::create(num_vertices)?;
+        let mut vertices = jump.get_level(0);
+        let mut result = levels.push(char::default(), &vertices);
+        let automaton = &mut self.automaton;
+
+        text.for_each_symbol(self.order == Order::Document, |symbol, _| {
+            if result.is_ok() {
+                let adj_for_char = automaton.get_adj_for_char_with_closure(symbol);
+                vertices = successors(adj_for_char, &vertices, num_vertices);
+                result = levels.push(symbol, &vertices);
+            }
+        })?;
+
+        result?;
+
+        if vertices.is_empty() {
+            return Ok(());
+        }
+
+        self.create_dag_time = Some(start_time.elapsed());
+        let start_time = Instant::now();
+
+        // Trim the DAG, from the last level to the first one.
+        let last_level = levels.num_levels() - 1;
+        let checkpoints = Jump::checkpoint_levels(0, last_level);
+        let no_trimming = self.trimming_strategy == TrimmingStrategy::NoTrimming;
+
+        if !no_trimming {
+            let automaton = &self.automaton;
+            let full_trimming = self.trimming_strategy == TrimmingStrategy::FullTrimming;
+            let mut next_symbol = char::default();
+            let mut keep = BitSet::new();
+            let mut disconnected = false;
+
+            levels.update_rev(|level, symbol, vertices| {
+                if checkpoints.binary_search(&level).is_ok() {
+                    jump.add_checkpoint(level, vertices.clone());
+                }
+
+                if level < last_level {
+                    let rev_adj_for_char = automaton.get_rev_adj_for_char_with_closure(next_symbol);
+                    vertices.intersect_with(&successors(rev_adj_for_char, &keep, num_vertices));
+                } else if full_trimming {
+                    vertices.intersect_with(&Jump::final_vertices(
+                        &automaton.finals,
+                        &closure_for_assignations,
+                    ));
+                    disconnected = vertices.is_empty();
+                }
+
+                keep = vertices.clone();
+                next_symbol = symbol;
+            })?;
+
+            if disconnected {
+                return Ok(());
+            }
+        }
+
+        self.trim_time = Some(start_time.elapsed());
+        let start_time = Instant::now();
+
+        // Build the jump levels, only levels that can be jumped to are kept.
+        let automaton = &mut self.automaton;
+        let mut prev_level: Option<BitSet> = None;
+
+        levels.for_each(|level, symbol, vertices| {
+            if no_trimming && checkpoints.binary_search(&level).is_ok() {
+                jump.add_checkpoint(level, vertices.clone());
+            }
+
+            match &prev_level {
+                None => jump.init_levels_with(&vertices, last_level),
+                Some(prev_level) => {
+                    let width = if text.is_unicode() {
+                        symbol.len_utf8()
+                    } else {
+                        1
+                    };
+
+                    let adj_for_char = automaton.get_adj_for_char(symbol);
+                    jump.push_reach(
+                        level,
+                        width,
+                        &vertices,
+                        prev_level,
+                        adj_for_char,
+                        &closure_for_assignations,
+                    );
+                }
+            }
+
+            prev_level = Some(vertices);
+        })?;
+
+        self.index_time = Some(start_time.elapsed());
+        self.jump = Some(jump);
+        Ok(())
+    }
+
+    /// Build and trim the DAG over chunks of the text in parallel, then build
+    /// the jump levels over the same chunks.
+    fn preprocess_parallel(&mut self, mut jump: Jump) {
+        let start_time = Instant::now();
+        let chunks = self.chunks();
+
+        // Adjacency lists are computed lazily by the automaton, they must be
+        // computed for all symbols before being shared between threads.
+        let symbols = map_parallel(&chunks, |chunk| {
+            chunk
+                .symbols()
+                .map(|(symbol, _)| symbol)
+                .collect::<HashSet<_>>()
+        });
+
+        for &symbol in symbols.iter().flatten() {
+            self.automaton.get_adj_for_char_with_closure(symbol);
+        }
+
+        let automaton = &self.automaton;
+        let order = self.order;
+
+        let forward_chunks: Vec<_> = chunks
+            .iter()
+            .map(|&chunk| {
+                move || {
+                    symbols_in_order(chunk, order).map(move |(symbol, _)| {
+                        automaton
+                            .get_prepared_adj_for_char_with_closure(symbol)
+                            .as_slice()
+                    })
+                }
+            })
+            .collect();
+
+        jump.init_next_levels_parallel(&forward_chunks);
+
+        if jump.is_disconnected() {
+            return;
+        }
+
+        jump.record_checkpoints(0);
+
+        self.create_dag_time = Some(start_time.elapsed());
         let start_time = Instant::now();
-        let chars = self.text.chars();
-        let mut progress = Progress::from_iter(chars).auto_refresh(self.toggle_progress);
-        let mut level = 1;
 
-        while let Some(curr_char) = progress.next() {
+        if self.trimming_strategy == TrimmingStrategy::FullTrimming {
+            jump.trim_last_level(
+                &self.automaton.finals,
+                self.automaton.get_closure_for_assignations(),
+            );
+        }
+
+        if jump.is_disconnected() {
+            return;
+        }
+
+        if self.trimming_strategy != TrimmingStrategy::NoTrimming {
+            let backward_chunks: Vec<_> = chunks
+                .iter()
+                .map(|&chunk| {
+                    move || {
+                        symbols_in_order(chunk, order)
+                            .rev()
+                            .map(move |(symbol, _)| {
+                                automaton
+                                    .get_rev_adj_for_char_with_closure(symbol)
+                                    .as_slice()
+                            })
+                    }
+                })
+                .collect();
+
+            jump.trim_levels_parallel(&backward_chunks);
+        }
+
+        self.trim_time = Some(start_time.elapsed());
+        let start_time = Instant::now();
+
+        let reach_chunks: Vec<_> = chunks
+            .iter()
+            .map(|&chunk| {
+                move || {
+                    symbols_in_order(chunk, order).map(move |(symbol, width)| {
+                        (
+                            width,
+                            automaton.get_prepared_adj_for_char(symbol).as_slice(),
+                        )
+                    })
+                }
+            })
+            .collect();
+
+        jump.init_reach_parallel(&reach_chunks, automaton.get_closure_for_assignations());
+        self.index_time = Some(start_time.elapsed());
+        self.jump = Some(jump);
+    }
+
+    /// Build the jump levels of a trimmed DAG, which completes preprocessing.
+    fn init_jump_levels(&mut self, mut jump: Jump) {
+        let closure_for_assignations = self.automaton.get_closure_for_assignations().clone();
+        let start_time = Instant::now();
+        jump.init_levels();
+        let progress = Progress::from_iter(self.symbols()).auto_refresh(self.toggle_progress);
+
+        for (level, (curr_char, width)) in (1..).zip(progress) {
             let adj_for_char = self.automaton.get_adj_for_char(curr_char);
-            jump.init_reach(level, curr_char, adj_for_char, &closure_for_assignations);
-            level += 1;
+            jump.init_reach(level, width, adj_for_char, &closure_for_assignations);
         }
 
         self.index_time = Some(start_time.elapsed());
@@ -182,6 +1172,72 @@ impl<'t> SpannerEnumerator<'t> for IndexedDag<'t> {
     }
 }
 
+/// Symbols of a text and their width, in the order the levels of an index are
+/// built.
+fn symbols_in_order<'t>(
+    text: Text<'t>,
+    order: Order,
+) -> Box<dyn DoubleEndedIterator<Item = (char, usize)> + 't> {
+    match order {
+        Order::Backward => text.symbols(),
+        Order::Document => Box::new(text.symbols().rev()),
+    }
+}
+
+impl Persist for Order {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let id: u64 = match self {
+            Order::Backward => 0,
+            Order::Document => 1,
+        };
+
+        id.persist(output)
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<Order> {
+        match u64::restore(input)? {
+            0 => Ok(Order::Backward),
+            1 => Ok(Order::Document),
+            _ => Err(invalid_data("invalid enumeration order")),
+        }
+    }
+}
+
+impl Persist for TrimmingStrategy {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let id: u64 = match self {
+            TrimmingStrategy::NoTrimming => 0,
+            TrimmingStrategy::PartialTrimming => 1,
+            TrimmingStrategy::FullTrimming => 2,
+        };
+
+        id.persist(output)
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<TrimmingStrategy> {
+        match u64::restore(input)? {
+            0 => Ok(TrimmingStrategy::NoTrimming),
+            1 => Ok(TrimmingStrategy::PartialTrimming),
+            2 => Ok(TrimmingStrategy::FullTrimming),
+            _ => Err(invalid_data("invalid trimming strategy")),
+        }
+    }
+}
+
+/// A FNV-1a hash of the text, used to check that a persisted index is loaded
+/// over the text it was built from.
+fn text_checksum(text: Text) -> io::Result<u64> {
+    let mut checksum = 0xcbf2_9ce4_8422_2325;
+
+    text.for_each_block(|block| {
+        checksum = block.iter().fold(checksum, |hash, &byte| {
+            (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+        })
+    })?;
+
+    Ok(checksum)
+}
+
 //  ___           _                   _
 // |_ _|_ __   __| | _____  _____  __| |
 //  | || '_ \ / _` |/ _ \ \/ / _ \/ _` |
@@ -206,23 +1262,36 @@ struct IndexedDagIterator<'i, 't> {
 
 impl<'i, 't> IndexedDagIterator<'i, 't> {
     fn init(indexed_dag: &'i IndexedDag<'t>) -> IndexedDagIterator<'i, 't> {
-        IndexedDagIterator {
+        let stack = match indexed_dag.root() {
+            None => Vec::new(),
+            Some((level, gamma)) => vec![(level, gamma, Vec::new())],
+        };
+
+        // `curr_next_level` is initialized empty, thus theses values will be
+        // replaced before the first iteration.
+        IndexedDagIterator::resume(
             indexed_dag,
-            stack: match &indexed_dag.jump {
-                None => Vec::new(),
-                Some(j) => {
-                    let mut start = j.finals().clone();
-                    start.intersect_with(&indexed_dag.automaton.finals);
-
-                    vec![(j.num_levels() - 1, start, Vec::new())]
-                }
-            },
+            stack,
+            usize::default(),
+            Vec::default(),
+            NextLevelIterator::empty(&indexed_dag.automaton),
+        )
+    }
 
-            // `curr_next_level` is initialized empty, thus theses values will
-            // be replaced before the first iteration.
-            curr_next_level: NextLevelIterator::empty(&indexed_dag.automaton),
-            curr_level: usize::default(),
-            curr_mapping: Vec::default(),
+    /// Build an iterator from the state of a running enumeration.
+    fn resume(
+        indexed_dag: &'i IndexedDag<'t>,
+        stack: Vec<(usize, BitSet, Vec<(&'i Marker, usize)>)>,
+        curr_level: usize,
+        curr_mapping: Vec<(&'i Marker, usize)>,
+        curr_next_level: NextLevelIterator<'i>,
+    ) -> IndexedDagIterator<'i, 't> {
+        IndexedDagIterator {
+            indexed_dag,
+            stack,
+            curr_level,
+            curr_mapping,
+            curr_next_level,
             num_vars: indexed_dag.automaton.num_vars(),
         }
     }
@@ -234,7 +1303,7 @@ impl<'i, 't> Iterator for IndexedDagIterator<'i, 't> {
     fn next(&mut self) -> Option<Mapping<'t>> {
         loop {
             // First, consume curr_next_level.
-            while let Some((s_p, mut new_gamma)) = self.curr_next_level.next() {
+            for (s_p, mut new_gamma) in self.curr_next_level.by_ref() {
                 if new_gamma.is_empty() {
                     continue;
                 }
@@ -261,18 +1330,10 @@ impl<'i, 't> Iterator for IndexedDagIterator<'i, 't> {
                 }
 
                 if self.curr_level == 0 {
-                    if new_gamma.contains(self.indexed_dag.automaton.get_initial()) {
-                        // Re-align level indexes with utf8 coding
-                        let aligned_markers = new_mapping
-                            .into_iter()
-                            .map(|(marker, pos)| (marker.clone(), pos));
-
-                        // Create the new mapping
-                        return Some(Mapping::from_markers(
-                            self.indexed_dag.text,
-                            aligned_markers,
-                            self.num_vars,
-                        ));
+                    if new_gamma.contains(self.indexed_dag.automaton.get_initial())
+                        && self.indexed_dag.is_selected(&new_mapping, None)
+                    {
+                        return Some(self.indexed_dag.build_mapping(new_mapping, self.num_vars));
                     }
                 } else if let Some(jump_level) = self
                     .indexed_dag
@@ -281,7 +1342,9 @@ impl<'i, 't> Iterator for IndexedDagIterator<'i, 't> {
                     .unwrap()
                     .jump(self.curr_level, &mut new_gamma)
                 {
-                    self.stack.push((jump_level, new_gamma, new_mapping));
+                    if self.indexed_dag.is_selected(&new_mapping, Some(jump_level)) {
+                        self.stack.push((jump_level, new_gamma, new_mapping));
+                    }
                 }
             }
 
@@ -299,6 +1362,420 @@ impl<'i, 't> Iterator for IndexedDagIterator<'i, 't> {
     }
 }
 
+//  ____                 _                      _
+// |  _ \ __ _ _ __   __| | ___  _ __ ___      / \   ___ ___ ___  ___ ___
+// | |_) / _` | '_ \ / _` |/ _ \| '_ ` _ \    / _ \ / __/ __/ _ \/ __/ __|
+// |  _ < (_| | | | | (_| | (_) | | | | | |  / ___ \ (_| (_|  __/\__ \__ \
+// |_| \_\__,_|_| |_|\__,_|\___/|_| |_| |_| /_/   \_\___\___\___||___/___/
+//
+
+/// Access to the mappings of an index by their position in the enumeration
+/// order, which allows to resume the enumeration or to draw mappings
+/// uniformly at random.
+///
+/// For each node explored by the enumeration, the number of mappings that
+/// can be completed from each of its successors is stored, which allows to
+/// descend from the root towards the mapping of any position. Counts saturate
+/// at `u128::MAX`, in which case positions past this limit can't be reached
+/// and sampling is not exactly uniform anymore.
+pub struct RandomAccess<'i, 't> {
+    indexed_dag: &'i IndexedDag<'t>,
+    ids: HashMap<NodeKey<'i>, usize>,
+    nodes: Vec<CountedNode<'i>>,
+    total: u128,
+    fingerprint: u64,
+}
+
+/// A node explored by the enumeration: its level, its set of states and the
+/// markers already assigned to variables constrained by a string-equality
+/// selection.
+type NodeKey<'i> = (usize, BitSet, Vec<(&'i Marker, usize)>);
+
+/// A node of the index together with the number of mappings that can be
+/// completed from it.
+struct CountedNode<'i> {
+    level: usize,
+    gamma: BitSet,
+    assigned: Vec<(&'i Marker, usize)>,
+    /// For each successor of the node, number of mappings completed from it
+    /// and from the successors following it, which are explored before it.
+    counts: Vec<u128>,
+    /// First successor of the node if it assigns no marker, it is explored
+    /// last thus a chain of such successors can be followed without leaving
+    /// anything on the stack of the enumeration.
+    tail: Option<usize>,
+    /// A node further along the chain of tails, which are skew-binary jump
+    /// pointers: any node of the chain is reached in a logarithmic number of
+    /// jumps.
+    jump: usize,
+    /// Number of tails following this node.
+    depth: usize,
+}
+
+impl CountedNode<'_> {
+    fn count(&self) -> u128 {
+        self.counts.first().copied().unwrap_or(0)
+    }
+}
+
+impl<'i, 't> RandomAccess<'i, 't> {
+    fn init(indexed_dag: &'i IndexedDag<'t>) -> io::Result<RandomAccess<'i, 't>> {
+        let mut ids: HashMap<NodeKey<'i>, usize> = HashMap::new();
+        let mut nodes: Vec<CountedNode<'i>> = Vec::new();
+        let mut total = 0;
+
+        if let Some((root_level, root_gamma)) = indexed_dag.root() {
+            // List nodes from the top level to the bottom one, such that
+            // successors of a node are listed after it.
+            let mut listed = Vec::new();
+            let mut pending: BTreeMap<usize, HashSet<_>> = BTreeMap::new();
+            pending
+                .entry(root_level)
+                .or_default()
+                .insert((root_gamma.clone(), Vec::new()));
+
+            while let Some((level, keys)) = pending.pop_last() {
+                for (gamma, assigned) in keys {
+                    for (s_p, next) in indexed_dag.successors(level, gamma.clone(), &assigned) {
+                        if let Some((next_level, next_gamma)) = next {
+                            let next_assigned = indexed_dag.assigned_after(&assigned, level, &s_p);
+                            (pending.entry(next_level).or_default())
+                                .insert((next_gamma, next_assigned));
+                        }
+                    }
+
+                    listed.push((level, gamma, assigned));
+                }
+            }
+
+            for (level, gamma, assigned) in listed.into_iter().rev() {
+                let mut counts = Vec::new();
+                let mut tail = None;
+
+                let successors = indexed_dag.successors(level, gamma.clone(), &assigned);
+
+                for (i, (s_p, next)) in successors.enumerate() {
+                    let count = match next {
+                        None => 1,
+                        Some((next_level, next_gamma)) => {
+                            let next_assigned = indexed_dag.assigned_after(&assigned, level, &s_p);
+                            let id = ids[&(next_level, next_gamma, next_assigned)];
+
+                            if i == 0 && s_p.is_empty() {
+                                tail = Some(id);
+                            }
+
+                            nodes[id].count()
+                        }
+                    };
+
+                    counts.push(count);
+                }
+
+                for i in (1..counts.len()).rev() {
+                    counts[i - 1] = counts[i - 1].saturating_add(counts[i]);
+                }
+
+                let (jump, depth) = match tail {
+                    None => (nodes.len(), 0),
+                    Some(tail) => {
+                        let jump = nodes[tail].jump;
+
+                        match nodes[tail].depth - nodes[jump].depth
+                            == nodes[jump].depth - nodes[nodes[jump].jump].depth
+                        {
+                            true => (nodes[jump].jump, nodes[tail].depth + 1),
+                            false => (tail, nodes[tail].depth + 1),
+                        }
+                    }
+                };
+
+                ids.insert((level, gamma.clone(), assigned.clone()), nodes.len());
+
+                nodes.push(CountedNode {
+                    level,
+                    gamma,
+                    assigned,
+                    counts,
+                    tail,
+                    jump,
+                    depth,
+                });
+            }
+
+            total = nodes[ids[&(root_level, root_gamma, Vec::new())]].count();
+        }
+
+        // Identify the index a cursor was built from.
+        let fingerprint = [
+            indexed_dag.automaton.get_nb_states() as u64,
+            total as u64,
+            (total >> 64) as u64,
+        ]
+        .iter()
+        .fold(text_checksum(indexed_dag.text)?, |hash, value| {
+            (hash ^ value).wrapping_mul(0x0100_0000_01b3)
+        });
+
+        Ok(RandomAccess {
+            indexed_dag,
+            ids,
+            nodes,
+            total,
+            fingerprint,
+        })
+    }
+
+    /// Number of distinct mappings, saturating at `u128::MAX`.
+    pub fn total(&self) -> u128 {
+        self.total
+    }
+
+    /// Get the mapping at given position in the enumeration order.
+    pub fn get(&self, index: u128) -> Option<Mapping<'t>> {
+        self.iter_from(index).next()
+    }
+
+    /// Enumerate the mappings starting from given position in the enumeration
+    /// order. Reaching this position doesn't depend on the length of the
+    /// text, the index is descended from the root to this position with a
+    /// binary search over the successors of each node.
+    pub fn iter_from(&self, index: u128) -> ResumedIterator<'i, 't> {
+        let inner = if index < self.total {
+            self.locate(index)
+        } else {
+            IndexedDagIterator::resume(
+                self.indexed_dag,
+                Vec::new(),
+                0,
+                Vec::new(),
+                NextLevelIterator::empty(&self.indexed_dag.automaton),
+            )
+        };
+
+        ResumedIterator {
+            inner,
+            position: index.min(self.total),
+            fingerprint: self.fingerprint,
+        }
+    }
+
+    /// Resume an enumeration from the position stored in a cursor, which
+    /// fails if the cursor was not built over the same index.
+    pub fn resume(&self, cursor: &Cursor) -> Result<ResumedIterator<'i, 't>, InvalidCursor> {
+        if cursor.fingerprint != self.fingerprint {
+            return Err(InvalidCursor);
+        }
+
+        Ok(self.iter_from(cursor.position))
+    }
+
+    /// Draw a mapping uniformly at random, or `None` if there is no mapping.
+    pub fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Option<Mapping<'t>> {
+        if self.total == 0 {
+            return None;
+        }
+
+        self.get(rng.gen_range(0..self.total))
+    }
+
+    /// Draw `amount` distinct mappings uniformly at random, or all of them if
+    /// there are less than `amount` mappings. Mappings are returned in the
+    /// order they would be enumerated.
+    pub fn sample_distinct<R: Rng + ?Sized>(&self, amount: u128, rng: &mut R) -> Vec<Mapping<'t>> {
+        let amount = amount.min(self.total);
+
+        // Robert Floyd's algorithm to pick a subset of indexes.
+        let mut indexes = BTreeSet::new();
+
+        for upper in self.total - amount..self.total {
+            let index = rng.gen_range(0..=upper);
+
+            if !indexes.insert(index) {
+                indexes.insert(upper);
+            }
+        }
+
+        indexes
+            .into_iter()
+            .filter_map(|index| self.get(index))
+            .collect()
+    }
+
+    /// Rebuild the state of an enumeration that is about to output the
+    /// mapping at given position, which must be less than the total number of
+    /// mappings.
+    ///
+    /// The successor of each node leading to this position is found by binary
+    /// search over the counts, and chains of successors that assign no marker
+    /// are skipped with jump pointers, thus the path from the root is not
+    /// followed one level at a time.
+    fn locate(&self, mut index: u128) -> IndexedDagIterator<'i, 't> {
+        let indexed_dag = self.indexed_dag;
+        let jump = indexed_dag.jump.as_ref().unwrap();
+        let (root_level, root_gamma) = indexed_dag.root().unwrap();
+        let mut id = self.ids[&(root_level, root_gamma, Vec::new())];
+        let mut mapping = Vec::new();
+        let mut stack = Vec::new();
+
+        loop {
+            // The number of mappings left to enumerate from a node is kept
+            // along a chain of tails.
+            let remaining = self.nodes[id].count() - index;
+
+            loop {
+                let node = &self.nodes[id];
+
+                id = if self.nodes[node.jump].count() >= remaining && node.jump != id {
+                    node.jump
+                } else {
+                    match node.tail {
+                        Some(tail) if self.nodes[tail].count() >= remaining => tail,
+                        _ => break,
+                    }
+                };
+            }
+
+            let node = &self.nodes[id];
+            index = node.count() - remaining;
+
+            if node.level == 0 {
+                break;
+            }
+
+            // The enumeration pushes successors on a stack, thus they are
+            // explored in reverse order.
+            let chosen = node.counts.partition_point(|&count| count > index) - 1;
+            index -= node.counts.get(chosen + 1).copied().unwrap_or(0);
+
+            // Successors explored after the chosen one are still waiting on
+            // the stack.
+            let pos = jump.get_pos(node.level);
+            let mut successors = indexed_dag
+                .successors(node.level, node.gamma.clone(), &node.assigned)
+                .map(|(s_p, next)| {
+                    let (next_level, next_gamma) = next.unwrap();
+                    let assigned = indexed_dag.assigned_after(&node.assigned, node.level, &s_p);
+                    let mut new_mapping = mapping.clone();
+                    new_mapping.extend(s_p.into_iter().map(|marker| (marker, pos)));
+                    ((next_level, next_gamma, assigned), new_mapping)
+                });
+
+            stack.extend(
+                successors
+                    .by_ref()
+                    .take(chosen)
+                    .map(|((level, gamma, _), mapping)| (level, gamma, mapping)),
+            );
+
+            let (next, new_mapping) = successors.next().unwrap();
+            id = self.ids[&next];
+            mapping = new_mapping;
+        }
+
+        // On the last level, mappings are output in the order of the
+        // iterator, hence the first ones are skipped. There are at most as
+        // many as sets of markers that can be assigned at once.
+        let mut next_level = indexed_dag.next_level(self.nodes[id].gamma.clone());
+        let initial = indexed_dag.automaton.get_initial();
+
+        while index > 0 {
+            let (s_p, new_gamma) = next_level.next().unwrap();
+
+            if !new_gamma.is_empty() && new_gamma.contains(initial) {
+                let mut new_mapping = mapping.clone();
+                new_mapping.extend(s_p.into_iter().map(|marker| (marker, jump.get_pos(0))));
+
+                if indexed_dag.is_selected(&new_mapping, None) {
+                    index -= 1;
+                }
+            }
+        }
+
+        IndexedDagIterator::resume(indexed_dag, stack, 0, mapping, next_level)
+    }
+}
+
+/// Enumeration of the mappings of an index from a given position, which
+/// keeps track of its position to be resumed later.
+pub struct ResumedIterator<'i, 't> {
+    inner: IndexedDagIterator<'i, 't>,
+    position: u128,
+    fingerprint: u64,
+}
+
+impl<'i, 't> ResumedIterator<'i, 't> {
+    /// A cursor pointing to the next mapping of the enumeration.
+    pub fn cursor(&self) -> Cursor {
+        Cursor {
+            position: self.position,
+            fingerprint: self.fingerprint,
+        }
+    }
+}
+
+impl<'i, 't> Iterator for ResumedIterator<'i, 't> {
+    type Item = Mapping<'t>;
+
+    fn next(&mut self) -> Option<Mapping<'t>> {
+        let mapping = self.inner.next()?;
+        self.position += 1;
+        Some(mapping)
+    }
+}
+
+/// An opaque position in the enumeration of an index, it can be serialized to
+/// resume the enumeration later.
+///
+/// A cursor can also be written as a plain string token through `Display` and
+/// read back with `FromStr`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Hash, Serialize, Deserialize)]
+pub struct Cursor {
+    position: u128,
+    fingerprint: u64,
+}
+
+impl Cursor {
+    /// Number of mappings enumerated before this cursor.
+    pub fn position(&self) -> u128 {
+        self.position
+    }
+}
+
+impl fmt::Display for Cursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:032x}{:016x}", self.position, self.fingerprint)
+    }
+}
+
+impl str::FromStr for Cursor {
+    type Err = InvalidCursor;
+
+    fn from_str(token: &str) -> Result<Cursor, InvalidCursor> {
+        if token.len() != 48 || !token.is_ascii() {
+            return Err(InvalidCursor);
+        }
+
+        Ok(Cursor {
+            position: u128::from_str_radix(&token[..32], 16).map_err(|_| InvalidCursor)?,
+            fingerprint: u64::from_str_radix(&token[32..], 16).map_err(|_| InvalidCursor)?,
+        })
+    }
+}
+
+/// Error returned when a cursor can't be read or doesn't belong to the index
+/// it is resumed on.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct InvalidCursor;
+
+impl fmt::Display for InvalidCursor {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "the cursor does not belong to this index")
+    }
+}
+
+impl error::Error for InvalidCursor {}
+
 //  _   _           _   _                   _
 // | \ | | _____  _| |_| |    _____   _____| |
 // |  \| |/ _ \ \/ / __| |   / _ \ \ / / _ \ |
@@ -474,13 +1951,13 @@ impl<'a> Iterator for NextLevelIterator<'a> {
                     new_s_p.insert(next_marker);
                     new_s_m.remove(next_marker);
                     let mut new_markers = markers.clone();
-                    new_markers.push(&self.expected_markers[depth]);
+                    new_markers.push(self.expected_markers[depth]);
                     self.stack.push((new_s_p, new_s_m, new_markers));
                 } else {
                     // Overwise, the other branch has to be feasible.
                     s_m.remove(next_marker);
                     s_p.insert(next_marker);
-                    markers.push(&self.expected_markers[depth]);
+                    markers.push(self.expected_markers[depth]);
                     gamma2 = None;
                 }
             }
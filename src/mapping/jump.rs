@@ -1,18 +1,65 @@
 use bit_set::BitSet;
 use std::cmp::max;
+use std::io;
+use std::io::prelude::*;
+use std::ops::Range;
 
 use super::super::matrix::Matrix;
+use super::super::parallel::{map_parallel, map_parallel_owned};
+use super::super::persist::{Persist, invalid_data};
 use super::levelset::LevelSet;
 
+/// Maximal number of levels of the DAG in a segment of the index.
+pub const SEGMENT_LEN: usize = 1024;
+
+/// Jump level of a vertex inside of a segment that jumps before the base of
+/// the segment.
+const BEFORE_BASE: usize = usize::MAX - 1;
+
 /// Holds for some level the id,
 /// the jump target levels for all nodes, and
 /// a set of matrices together with the target levels
+///
+/// A level inside of a segment is relative to the first level of its segment.
 struct Level {
     id: usize,
+    /// Distance to the first level of the segment, which is 0 at the
+    /// boundaries of segments.
+    base: usize,
     jl: Vec<usize>,
     reach: Vec<(usize, Matrix)>,
 }
 
+impl Level {
+    fn empty() -> Level {
+        Level {
+            id: 0,
+            base: 0,
+            jl: Vec::new(),
+            reach: Vec::new(),
+        }
+    }
+
+    /// Make a level inside of a segment relative to the base of the segment,
+    /// given the number and the id of the base.
+    fn make_relative(&mut self, level_no: usize, base: usize, base_id: usize) {
+        self.base = level_no - base;
+        self.id -= base_id;
+
+        for jl in &mut self.jl {
+            *jl = match *jl {
+                usize::MAX => usize::MAX,
+                jl if jl < base => BEFORE_BASE,
+                jl => jl - base,
+            };
+        }
+
+        for (target, _) in &mut self.reach {
+            *target -= base;
+        }
+    }
+}
+
 //      _
 //     | |_   _ _ __ ___  _ __
 //  _  | | | | | '_ ` _ \| '_ \
@@ -55,14 +102,26 @@ pub struct Jump {
     dag_mem_before_trunk: usize,
 
     /// stores the offset between unicode charcter points and the position in the input string.
-    /// is always 0 if the input is ASCII
+    /// is always 0 if the input is ASCII or read as raw bytes
     offset: usize,
+
+    /// Levels of the index at the boundaries of segments, together with the
+    /// vertices of the DAG reached at this level before trimming.
+    boundaries: Vec<(usize, BitSet)>,
+
+    /// Vertices reached before trimming at the levels of the DAG that will be
+    /// boundaries of segments, used while the index is built.
+    checkpoints: Vec<(usize, BitSet)>,
+
+    /// Number of the first level of `levels`, which only holds the levels
+    /// from an edit while they are built again.
+    levels_start: usize,
 }
 
 impl Jump {
     pub fn new<T>(
         initial_level: T,
-        nonjump_adj: &Vec<Vec<usize>>,
+        nonjump_adj: &[Vec<usize>],
         jump_vertices: &BitSet,
         num_levels: usize,
         num_vertices: usize,
@@ -83,6 +142,9 @@ impl Jump {
             dag_mem_before_trunk: 0,
             last_level_was_jump_target: true,
             offset: 0,
+            boundaries: Vec::new(),
+            checkpoints: Vec::new(),
+            levels_start: 0,
         };
 
         for state in initial_level {
@@ -91,10 +153,16 @@ impl Jump {
 
         // Init first level
         jump.extend_level(0, nonjump_adj);
+        jump.checkpoints.push((0, jump.dag_bitmap.get_level(0)));
 
         jump
     }
 
+    /// Get the vertices of a level of the DAG.
+    pub fn get_level(&self, level: usize) -> BitSet {
+        self.dag_bitmap.get_level(level)
+    }
+
     pub fn get_last_level(&self) -> usize {
         self.last_level
     }
@@ -103,14 +171,32 @@ impl Jump {
         self.levels.len()
     }
 
+    pub fn num_vertices(&self) -> usize {
+        self.num_vertices
+    }
+
     pub fn get_pos(&self, level: usize) -> usize {
-        self.levels[level].id
+        match self.levels[level].base {
+            0 => self.levels[level].id,
+            base => self.levels[level - base].id + self.levels[level].id,
+        }
+    }
+
+    /// Make room for `num_levels` levels of the DAG after the last level of
+    /// the index, so that text can be appended to an index that is already
+    /// built. These levels are then filled with `init_next_level` and indexed
+    /// with `init_reach` as usual, but the last level of the index must not be
+    /// trimmed anymore.
+    pub fn extend_levels(&mut self, num_levels: usize) {
+        self.dag_bitmap.truncate(self.last_level + 1);
+        self.dag_bitmap.grow(num_levels);
+        self.offset = self.levels[self.last_level].id - self.last_level;
     }
 
     /// Compute next level given the adjacency list of jumpable edges from
     /// current level to the next one and adjacency list of non-jumpable
     /// edges inside the next level.
-    pub fn init_next_level(&mut self, jump_adj: &Vec<Vec<usize>>) {
+    pub fn init_next_level(&mut self, jump_adj: &[Vec<usize>]) {
         let dag_bitmap = &mut self.dag_bitmap;
 
         let last_level = self.last_level;
@@ -127,15 +213,18 @@ impl Jump {
         }
 
         // If at some point the next level is not reached, the output will be empty
-        // anyway.
-        if dag_bitmap.get_level(next_level).is_empty() {
-            return;
-        }
-
+        // anyway, which is then reported by `is_disconnected`.
         self.last_level = next_level;
     }
 
-    pub fn trim_last_level(&mut self, final_states: &BitSet, nonjump_adj: &Vec<Vec<usize>>) {
+    pub fn trim_last_level(&mut self, final_states: &BitSet, nonjump_adj: &[Vec<usize>]) {
+        let keep = Jump::final_vertices(final_states, nonjump_adj);
+        self.dag_bitmap.keep_only(self.last_level, &keep);
+    }
+
+    /// Get the vertices of the last level from which a final state can be
+    /// reached.
+    pub fn final_vertices(final_states: &BitSet, nonjump_adj: &[Vec<usize>]) -> BitSet {
         let mut keep = final_states.clone();
         for source in 0..nonjump_adj.len() {
             for &target in &nonjump_adj[source] {
@@ -145,10 +234,10 @@ impl Jump {
             }
         }
 
-        self.dag_bitmap.keep_only(self.last_level, &keep);
+        keep
     }
 
-    pub fn trim_level(&mut self, level: usize, rev_jump_adj: &Vec<Vec<usize>>) {
+    pub fn trim_level(&mut self, level: usize, rev_jump_adj: &[Vec<usize>]) {
         let dag_bitmap = &mut self.dag_bitmap;
         let next_level = dag_bitmap.get_level(level);
         let mut keep = BitSet::with_capacity(self.num_vertices);
@@ -172,43 +261,54 @@ impl Jump {
     /// A relevent level has a node from which there is a path to gamma and
     /// that has an ingoing assignation.
     pub fn jump(&self, level_id: usize, gamma: &mut BitSet) -> Option<usize> {
-        let mut level = &self.levels[level_id];
         self.dag_bitmap.vertices_to_indices(level_id, gamma);
-        let jump_level = gamma
-            .iter()
-            .filter_map(|vertex| {
-                if level.jl[vertex] < std::usize::MAX {
-                    Some(level.jl[vertex])
-                } else {
-                    None
-                }
-            })
-            .max();
-
-        if jump_level == None {
-            return None;
-        }
-
+        let (mut jump_level, mut exact) = self.jump_level(level_id, gamma)?;
         let mut current_level = level_id;
 
-        while current_level > jump_level.unwrap() {
-            if let Some((l, matrix)) = level
-                .reach
-                .iter()
-                .find(|&&(id, _)| id >= jump_level.unwrap())
-            {
+        while current_level > jump_level {
+            let level = &self.levels[current_level];
+            let base = if level.base == 0 {
+                0
+            } else {
+                current_level - level.base
+            };
+
+            if let Some((l, matrix)) = level.reach.iter().find(|&&(l, _)| base + l >= jump_level) {
                 matrix.col_mul_inplace(gamma);
-                current_level = *l;
-                level = &self.levels[current_level];
+                current_level = base + l;
             } else {
                 panic!("No suitable matrix found for jump.");
             }
+
+            // The jump level is only known once the base of the segment is
+            // reached if it is before the base.
+            if !exact {
+                (jump_level, exact) = self.jump_level(current_level, gamma)?;
+            }
         }
 
-        self.dag_bitmap
-            .indices_to_vertices(jump_level.unwrap(), gamma);
+        self.dag_bitmap.indices_to_vertices(jump_level, gamma);
+
+        Some(jump_level)
+    }
 
-        jump_level
+    /// Get the furthest jump level of vertices in gamma, given by their index
+    /// in a level, and whether it is exact: the level before the base of a
+    /// segment is returned for vertices that jump before the base.
+    fn jump_level(&self, level_id: usize, gamma: &BitSet) -> Option<(usize, bool)> {
+        let level = &self.levels[level_id];
+        let jl = gamma
+            .iter()
+            .map(|vertex| level.jl[vertex])
+            .filter(|&jl| jl != usize::MAX)
+            .map(|jl| if jl == BEFORE_BASE { None } else { Some(jl) })
+            .max()?;
+
+        match (level.base, jl) {
+            (0, Some(jl)) => Some((jl, true)),
+            (base, Some(jl)) => Some((level_id - base + jl, true)),
+            (base, None) => Some((level_id - base - 1, false)),
+        }
     }
 
     /// Get the vertices that are in the final layer
@@ -222,7 +322,7 @@ impl Jump {
 
     /// Extend current level by reading non-jumpable edges inside the given
     /// level.
-    fn extend_level(&mut self, level: usize, nonjump_adj: &Vec<Vec<usize>>) {
+    fn extend_level(&mut self, level: usize, nonjump_adj: &[Vec<usize>]) {
         let dag_bitmap = &mut self.dag_bitmap;
         let old_level = dag_bitmap.get_level(level).clone();
 
@@ -237,13 +337,13 @@ impl Jump {
         &self,
         curr_level: &BitSet,
         prev_level: &BitSet,
-        jump_adj: &Vec<Vec<usize>>,
-        nonjump_adj: &Vec<Vec<usize>>,
-        jl: &Vec<usize>,
-        t_to_i: &Vec<usize>,
+        jump_adj: &[Vec<usize>],
+        nonjump_adj: &[Vec<usize>],
+        jl: &[usize],
+        t_to_i: &[usize],
+        prev_level_no: usize,
     ) -> Vec<usize> {
         let mut nonjump_vertices = BitSet::with_capacity(self.num_vertices);
-        let prev_level_no = self.levels.len() - 1;
 
         for source in prev_level.iter() {
             for &target in &nonjump_adj[source] {
@@ -251,7 +351,7 @@ impl Jump {
             }
         }
 
-        let mut new_jl = vec![std::usize::MAX; curr_level.len()];
+        let mut new_jl = vec![usize::MAX; curr_level.len()];
 
         // Register jumpable transitions from this level to the next one
         for (source_index, source) in prev_level.iter().enumerate() {
@@ -262,11 +362,11 @@ impl Jump {
 
             for &target in &jump_adj[source] {
                 let target_index = t_to_i[target];
-                if target_index != std::usize::MAX {
+                if target_index != usize::MAX {
                     if nonjump_vertices.contains(source) {
                         new_jl[target_index] = prev_level_no;
                     } else {
-                        if new_jl[target_index] == std::usize::MAX {
+                        if new_jl[target_index] == usize::MAX {
                             new_jl[target_index] = source_jl;
                         } else {
                             new_jl[target_index] = max(source_jl, new_jl[target_index]);
@@ -283,10 +383,30 @@ impl Jump {
         &self,
         curr_level: &BitSet,
         prev_level: &BitSet,
-        jump_adj: &Vec<Vec<usize>>,
-        t_to_i: &Vec<usize>,
+        jump_adj: &[Vec<usize>],
+        t_to_i: &[usize],
     ) -> (Matrix, Matrix) {
-        // Compute the adjacency between current level and the previous one.
+        let new_reach_t = self.transition_matrix(curr_level, prev_level, jump_adj, t_to_i);
+
+        // compute new_reach to point to reach_level
+        let new_reach = if self.last_level_was_jump_target {
+            new_reach_t.transpose()
+        } else {
+            &self.reach_matrix * &new_reach_t
+        };
+
+        (new_reach, new_reach_t)
+    }
+
+    /// Compute the adjacency between a level and the previous one, where rows
+    /// are the vertices of the current level.
+    fn transition_matrix(
+        &self,
+        curr_level: &BitSet,
+        prev_level: &BitSet,
+        jump_adj: &[Vec<usize>],
+        t_to_i: &[usize],
+    ) -> Matrix {
         let prev_level_len = prev_level.len();
         let mut prev_level_iter = prev_level.iter();
         let mut new_reach_t = Matrix::new(curr_level.len(), prev_level_len);
@@ -296,7 +416,7 @@ impl Jump {
         for id_source in 0..prev_level_len {
             let source = prev_level_iter.next().unwrap();
             for &target in &jump_adj[source] {
-                if t_to_i[target] != std::usize::MAX {
+                if t_to_i[target] != usize::MAX {
                     targets.insert(t_to_i[target]);
                 }
             }
@@ -307,23 +427,105 @@ impl Jump {
             targets.clear();
         }
 
-        // compute new_reach to point to reach_level
-        let new_reach = if self.last_level_was_jump_target {
-            new_reach_t.transpose()
-        } else {
-            &self.reach_matrix * &new_reach_t
-        };
+        new_reach_t
+    }
 
-        (new_reach, new_reach_t)
+    /// Get the levels a level of the index keeps reach matrices to, given its
+    /// jump levels: the furthest one and the ones that are a multiple of the
+    /// jump distance.
+    fn sublevels(&self, jl: &[usize]) -> Vec<usize> {
+        //all reachable levels
+        let mut rlev = jl.to_vec();
+
+        rlev.sort();
+        rlev.dedup();
+
+        if rlev[rlev.len() - 1] == usize::MAX {
+            rlev.pop();
+        }
+
+        let last = rlev[rlev.len() - 1];
+
+        rlev.retain(|&x| (x == last) || (x % self.jump_distance == 0));
+        rlev
+    }
+
+    /// Map the vertices of a level to their index in the level.
+    fn vertex_indices(&self, level: &BitSet) -> Vec<usize> {
+        let mut t_to_i = vec![usize::MAX; self.num_vertices];
+
+        for (i, q) in level.iter().enumerate() {
+            t_to_i[q] = i;
+        }
+
+        t_to_i
     }
 
-    fn init_levels(&mut self) {
-        self.levels = Vec::new();
-        self.levels.push(Level {
+    /// Initialize the first level of the index, this must be called once the
+    /// DAG has been trimmed and before the first call to `init_reach`.
+    pub fn init_levels(&mut self) {
+        let first_level = self.dag_bitmap.get_level(0);
+        let forward = self
+            .take_checkpoint(0)
+            .unwrap_or_else(|| first_level.clone());
+
+        self.levels = vec![Level {
             id: 0,
-            jl: vec![0; self.dag_bitmap.get_level(0).len()],
+            base: 0,
+            jl: vec![0; first_level.len()],
             reach: Vec::new(),
-        })
+        }];
+
+        self.boundaries = vec![(0, forward)];
+    }
+
+    /// Get the levels of the DAG after `first` and up to `last` that are
+    /// boundaries of segments, they split these levels into segments of
+    /// similar lengths of at most `SEGMENT_LEN` levels.
+    pub fn checkpoint_levels(first: usize, last: usize) -> Vec<usize> {
+        let len = last - first;
+        let num_segments = len.div_ceil(SEGMENT_LEN);
+
+        (1..=num_segments)
+            .map(|segment| first + len * segment / num_segments)
+            .collect()
+    }
+
+    /// Save the vertices reached before trimming at a level of the DAG that
+    /// will be a boundary of segments.
+    pub fn add_checkpoint(&mut self, level: usize, vertices: BitSet) {
+        let pos = self
+            .checkpoints
+            .partition_point(|&(other, _)| other < level);
+        self.checkpoints.insert(pos, (level, vertices));
+    }
+
+    /// Save the vertices reached at the levels of the DAG after `first` that
+    /// will be boundaries of segments, this must be called before trimming.
+    pub fn record_checkpoints(&mut self, first: usize) {
+        for level in Jump::checkpoint_levels(first, self.last_level) {
+            let vertices = self.dag_bitmap.get_level(level);
+            self.add_checkpoint(level, vertices);
+        }
+    }
+
+    fn take_checkpoint(&mut self, level: usize) -> Option<BitSet> {
+        let pos = self
+            .checkpoints
+            .binary_search_by_key(&level, |&(other, _)| other)
+            .ok()?;
+
+        Some(self.checkpoints.remove(pos).1)
+    }
+
+    /// Initialize the first level of an index whose DAG is not stored in the
+    /// structure, `push_reach` must then be called for each following level.
+    pub fn init_levels_with(&mut self, first_level: &BitSet, last_level: usize) {
+        self.dag_mem_before_trunk = LevelSet::memory_usage(last_level + 1, self.num_vertices);
+        self.dag_bitmap = LevelSet::new(0, self.num_vertices);
+        self.dag_bitmap.push_level(first_level);
+        self.last_level = last_level;
+        self.init_levels();
     }
 
     /// Compute reach and rlevel, that is the effective jump points to all levels
@@ -331,18 +533,66 @@ impl Jump {
     pub fn init_reach(
         &mut self,
         level: usize,
-        character: char,
-        jump_adj: &Vec<Vec<usize>>,
-        nonjump_adj: &Vec<Vec<usize>>,
+        width: usize,
+        jump_adj: &[Vec<usize>],
+        nonjump_adj: &[Vec<usize>],
     ) {
-        if level == 1 {
-            self.init_levels();
+        let curr_level = self.dag_bitmap.get_level(level);
+        let prev_level = self.dag_bitmap.get_level(level - 1);
+
+        if self.init_reach_between(
+            level,
+            width,
+            &curr_level,
+            &prev_level,
+            jump_adj,
+            nonjump_adj,
+        ) {
+            // we remove all levels that cannot be jumped to
+            let target = self.levels.len() - 1;
+            self.dag_bitmap.move_level(level, target);
+
+            if level == self.last_level {
+                self.dag_mem_before_trunk = self.dag_bitmap.get_memory_usage();
+                self.last_level = target;
+                self.dag_bitmap.truncate(target + 1);
+            }
         }
+    }
 
-        let prev_level_no = self.levels.len() - 1;
+    /// Same as `init_reach` for a DAG whose levels are not stored in the
+    /// structure, the level is only stored if it can be jumped to.
+    pub fn push_reach(
+        &mut self,
+        level: usize,
+        width: usize,
+        curr_level: &BitSet,
+        prev_level: &BitSet,
+        jump_adj: &[Vec<usize>],
+        nonjump_adj: &[Vec<usize>],
+    ) {
+        if self.init_reach_between(level, width, curr_level, prev_level, jump_adj, nonjump_adj) {
+            self.dag_bitmap.push_level(curr_level);
 
-        let curr_level = self.dag_bitmap.get_level(level);
-        let prev_level = self.dag_bitmap.get_level(level - 1);
+            if level == self.last_level {
+                self.last_level = self.levels.len() - 1;
+            }
+        }
+    }
+
+    /// Compute the level of the index for given level of the DAG, returns
+    /// `true` if it is added to the index.
+    fn init_reach_between(
+        &mut self,
+        level: usize,
+        width: usize,
+        curr_level: &BitSet,
+        prev_level: &BitSet,
+        jump_adj: &[Vec<usize>],
+        nonjump_adj: &[Vec<usize>],
+    ) -> bool {
+        let checkpoint = self.take_checkpoint(level);
+        let prev_level_no = self.levels_start + self.levels.len() - 1;
         let last_level = self.levels.last().unwrap();
 
         let jl = if self.last_level_was_jump_target {
@@ -351,32 +601,30 @@ impl Jump {
             &self.last_jl
         };
 
-        let mut t_to_i = vec![std::usize::MAX; self.num_vertices];
-
-        for (i, q) in curr_level.iter().enumerate() {
-            t_to_i[q] = i;
-        }
-
-        let new_jl = self.compute_jl(&curr_level, &prev_level, jump_adj, nonjump_adj, jl, &t_to_i);
+        let t_to_i = self.vertex_indices(curr_level);
+        let new_jl = self.compute_jl(
+            curr_level,
+            prev_level,
+            jump_adj,
+            nonjump_adj,
+            jl,
+            &t_to_i,
+            prev_level_no,
+        );
 
         let (new_reach, mut new_reach_t) =
-            self.compute_reach(&curr_level, &prev_level, jump_adj, &t_to_i);
+            self.compute_reach(curr_level, prev_level, jump_adj, &t_to_i);
 
         // no rlevel will point to this level
-        if curr_level.is_disjoint(&self.jump_vertices) && (level < self.last_level) {
+        if curr_level.is_disjoint(&self.jump_vertices)
+            && (level < self.last_level)
+            && checkpoint.is_none()
+        {
             self.reach_matrix = new_reach;
             self.last_jl = new_jl;
             self.last_level_was_jump_target = false;
-            self.offset += character.len_utf8() - 1;
-            return;
-        }
-
-        // we remove all levels that cannot be jumped to
-        self.dag_bitmap.move_level(level, prev_level_no + 1);
-        if level == self.last_level {
-            self.dag_mem_before_trunk = self.dag_bitmap.get_memory_usage();
-            self.last_level = prev_level_no + 1;
-            self.dag_bitmap.truncate(prev_level_no + 2);
+            self.offset += width - 1;
+            return false;
         }
 
         // if necessary, update new_reach_t
@@ -384,53 +632,101 @@ impl Jump {
             new_reach_t = new_reach.transpose();
         }
 
-        //all reachable levels
-        let mut rlev = new_jl.clone();
+        let rlev = self.sublevels(&new_jl);
+        let base = self.boundaries.last().unwrap().0;
+        let before_base = new_jl.iter().any(|&jl| jl < base);
 
-        rlev.sort();
-        rlev.dedup();
+        // Levels before the base of the segment are reached through it.
+        let mut targets: Vec<_> = rlev.iter().copied().filter(|&l| l >= base).collect();
 
-        if rlev[rlev.len() - 1] == std::usize::MAX {
-            rlev.pop();
+        if before_base && targets.first() != Some(&base) {
+            targets.insert(0, base);
         }
 
-        let last = rlev[rlev.len() - 1];
-
-        rlev.retain(|&x| (x == last) || (x % self.jump_distance == 0));
-
         // Compute by a dynamic algorithm the adjacency of current level with all its
         // sublevels.
         let mut matrix_iterator = last_level.reach.iter();
 
-        let mut matrices = Vec::with_capacity(rlev.len());
+        let mut matrices = Vec::with_capacity(targets.len());
 
-        for sublevel in rlev {
+        for sublevel in targets {
             if sublevel == prev_level_no {
                 continue;
             } else {
+                // The matrix may be missing if the previous level has vertices
+                // that were not trimmed, for example if text was appended after
+                // it was indexed. Jumps then go through intermediate levels.
                 if let Some((_, matrix)) = matrix_iterator.find(|&&(l, _)| l == sublevel) {
                     matrices.push((sublevel, matrix * &new_reach_t));
-                } else {
-                    panic!(
-                        "Matrix not found for sublevel {} level: {}",
-                        sublevel, level
-                    );
                 }
             }
         }
         matrices.push((prev_level_no, new_reach));
 
-        self.offset += character.len_utf8() - 1;
+        let boundary =
+            checkpoint.or_else(|| (level == self.last_level).then(|| curr_level.clone()));
+
+        // Boundaries of segments point to the levels of previous segments the
+        // base of their segment points to.
+        let to_base = matrices.iter().find(|&&(l, _)| l == base && before_base);
+
+        if let (Some(_), Some((_, to_base))) = (&boundary, to_base) {
+            let before = self.compose_before_base(base, to_base, &rlev);
+            matrices.splice(0..0, before);
+        }
+
+        self.offset += width - 1;
 
         let new_level = Level {
             id: level + self.offset,
+            base: 0,
             jl: new_jl,
             reach: matrices,
         };
 
         self.levels.push(new_level);
 
+        if let Some(forward) = boundary {
+            self.close_segment(forward);
+        }
+
         self.last_level_was_jump_target = true;
+        true
+    }
+
+    /// Compute the reach matrices of a boundary of segments to the levels of
+    /// previous segments, given the base of its segment, its reach matrix to
+    /// the base and the levels it keeps a reach matrix to.
+    fn compose_before_base(
+        &self,
+        base: usize,
+        to_base: &Matrix,
+        rlev: &[usize],
+    ) -> Vec<(usize, Matrix)> {
+        let to_base_t = to_base.transpose();
+
+        self.levels[base - self.levels_start]
+            .reach
+            .iter()
+            .filter(|(l, _)| *l < base && rlev.binary_search(l).is_ok())
+            .map(|(l, matrix)| (*l, matrix * &to_base_t))
+            .collect()
+    }
+
+    /// Make the last level of the index a boundary of segments, given the
+    /// vertices reached at this level before trimming. The levels inside of
+    /// the segment it closes are made relative to its base.
+    fn close_segment(&mut self, forward: BitSet) {
+        let start = self.levels_start;
+        let last = start + self.levels.len() - 1;
+        let base = self.boundaries.last().unwrap().0;
+        let base_id = self.levels[base - start].id;
+
+        for level_no in base + 1..last {
+            self.levels[level_no - start].make_relative(level_no, base, base_id);
+        }
+
+        self.boundaries.push((last, forward));
     }
 
     pub fn get_statistics(&self) -> (usize, usize, f64, usize, usize, f64) {
@@ -466,7 +762,7 @@ impl Jump {
             count,
             used_count,
             total_size as f64 / count as f64,
-            max_size as usize,
+            max_size,
         )
     }
 
@@ -508,6 +804,929 @@ impl Jump {
     }
 }
 
+//  ____                 _ _      _
+// |  _ \ __ _ _ __ __ _| | | ___| |
+// | |_) / _` | '__/ _` | | |/ _ \ |
+// |  __/ (_| | | | (_| | | |  __/ |
+// |_|   \__,_|_|  \__,_|_|_|\___|_|
+//
+// The first levels of the DAG and their trimming can be computed over
+// consecutive chunks of the text in parallel. Each chunk first computes the
+// relation between states at its boundaries, these relations are composed to
+// get the states at the beginning of each chunk, from which chunks can then
+// fill their levels independently.
+//
+// Reach matrices of the index are split the same way: each chunk computes the
+// matrices that point inside of it together with the reach matrices from its
+// first level, which are then composed with the matrices of the previous
+// chunks at their boundary.
+
+impl Jump {
+    /// Compute the levels following the last one, `chunks` being consecutive
+    /// chunks of the text, each given as a function iterating over the
+    /// adjacency lists used by `init_next_level` for each of its characters.
+    pub fn init_next_levels_parallel<'a, F, I>(&mut self, chunks: &[F])
+    where
+        F: Fn() -> I + Sync,
+        I: Iterator<Item = &'a [Vec<usize>]>,
+    {
+        let num_vertices = self.num_vertices;
+        let relations = map_parallel(chunks, |chunk| transfer_relation(chunk(), num_vertices));
+
+        // Compose relations to get the first level of each chunk.
+        let mut starts = vec![self.dag_bitmap.get_level(self.last_level)];
+
+        for (_, relation) in &relations[..relations.len() - 1] {
+            let start = relation.apply(starts.last().unwrap());
+            starts.push(start);
+        }
+
+        let mut ranges = Vec::with_capacity(chunks.len());
+        let mut first = self.last_level + 1;
+
+        for (len, _) in &relations {
+            ranges.push(first..first + len);
+            first += len;
+        }
+
+        let views = self.dag_bitmap.split_mut(&ranges);
+        let tasks: Vec<_> = chunks.iter().zip(starts).zip(views).zip(&ranges).collect();
+
+        map_parallel_owned(tasks, |(((chunk, start), mut view), range)| {
+            let mut vertices = start;
+
+            for (level, adj) in range.clone().zip(chunk()) {
+                vertices = successors(adj, &vertices, num_vertices);
+                view.set_level(level, &vertices);
+            }
+        });
+
+        self.last_level = first - 1;
+    }
+
+    /// Trim all levels but the last one, which is equivalent to calling
+    /// `trim_level` from the last level to the first one. `chunks` are
+    /// consecutive chunks of the text, each given as a function iterating
+    /// backward over the adjacency lists used by `trim_level`.
+    pub fn trim_levels_parallel<'a, F, I>(&mut self, chunks: &[F])
+    where
+        F: Fn() -> I + Sync,
+        I: Iterator<Item = &'a [Vec<usize>]>,
+    {
+        let num_vertices = self.num_vertices;
+        let relations = map_parallel(chunks, |chunk| transfer_relation(chunk(), num_vertices));
+
+        // Compose relations from the end of the text to get the vertices to
+        // keep at the end of each chunk.
+        let mut ends = vec![self.dag_bitmap.get_level(self.last_level)];
+
+        for (_, relation) in relations[1..].iter().rev() {
+            let end = relation.apply(ends.last().unwrap());
+            ends.push(end);
+        }
+
+        ends.reverse();
+
+        let mut ranges = Vec::with_capacity(chunks.len());
+        let mut first = 0;
+
+        for (len, _) in &relations {
+            ranges.push(first..first + len);
+            first += len;
+        }
+
+        assert_eq!(first, self.last_level, "chunks must cover the whole text");
+        let views = self.dag_bitmap.split_mut(&ranges);
+        let tasks: Vec<_> = chunks.iter().zip(ends).zip(views).zip(&ranges).collect();
+
+        map_parallel_owned(tasks, |(((chunk, end), mut view), range)| {
+            let mut keep = end;
+
+            for (level, rev_adj) in range.clone().rev().zip(chunk()) {
+                keep = successors(rev_adj, &keep, num_vertices);
+                keep.intersect_with(&view.get_level(level));
+                view.set_level(level, &keep);
+            }
+        });
+    }
+
+    /// Build the levels of the index from the trimmed DAG, which is equivalent
+    /// to calling `init_levels` and then `init_reach` for each level. `chunks`
+    /// are consecutive chunks of the text, each given as a function iterating
+    /// over the width of its symbols and the adjacency lists of jumpable edges
+    /// used by `init_reach`.
+    pub fn init_reach_parallel<'a, F, I>(&mut self, chunks: &[F], nonjump_adj: &[Vec<usize>])
+    where
+        F: Fn() -> I + Sync,
+        I: Iterator<Item = (usize, &'a [Vec<usize>])>,
+    {
+        self.init_levels();
+        let (starts, plans) = self.plan_levels(chunks, nonjump_adj);
+        let mut first = 1;
+        let mut tasks = Vec::with_capacity(chunks.len());
+
+        for ((chunk, start), levels) in chunks.iter().zip(starts).zip(plans) {
+            let num_levels = levels.len();
+            tasks.push((chunk, start, first, levels));
+            first += num_levels;
+        }
+
+        let this = &*self;
+        let mut segments = map_parallel_owned(tasks, |(chunk, start, first, levels)| {
+            this.init_segment(chunk(), start, first, levels)
+        });
+
+        // Complete the last level of each chunk in order, it is the boundary
+        // the levels of next chunks are completed from.
+        let mut lasts: Vec<_> = segments
+            .iter_mut()
+            .map(|segment| segment.levels.pop())
+            .collect();
+
+        let mut boundaries = Vec::with_capacity(segments.len());
+        let mut base = None;
+        let mut tail: Option<Matrix> = None;
+
+        for (i, segment) in segments.iter_mut().enumerate() {
+            let segment_tail = segment.tail.take();
+            let (before, after) = lasts.split_at_mut(i);
+
+            match &mut after[0] {
+                Some(last) => {
+                    let base_level = base.and_then(|j: usize| before[j].as_ref());
+                    last.complete(segment.first, base_level, tail.as_ref());
+                    boundaries.push((base, tail.take()));
+                    base = Some(i);
+                    tail = segment_tail;
+                }
+                None => {
+                    // The boundary of a chunk without any level of the index
+                    // is not used, the reach matrix goes through it.
+                    boundaries.push((base, None));
+                    tail = match (tail, segment_tail) {
+                        (Some(tail), Some(segment_tail)) => Some(product(&tail, &segment_tail)),
+                        (tail, segment_tail) => segment_tail.or(tail),
+                    };
+                }
+            }
+        }
+
+        let lasts_ref = &lasts;
+        let tasks: Vec<_> = segments.into_iter().zip(boundaries).collect();
+        let segments = map_parallel_owned(tasks, |(mut segment, (base, tail))| {
+            let base_level = base.and_then(|j| lasts_ref[j].as_ref());
+
+            for level in &mut segment.levels {
+                level.complete(segment.first, base_level, tail.as_ref());
+            }
+
+            segment.levels
+        });
+
+        let mut levels: Vec<_> = segments
+            .into_iter()
+            .zip(lasts)
+            .flat_map(|(levels, last)| levels.into_iter().chain(last))
+            .collect();
+
+        // Boundaries of segments point to the levels of previous segments the
+        // base of their segment points to, which is complete beforehand.
+        for i in 0..levels.len() {
+            let (before, after) = levels.split_at_mut(i);
+            let level = &mut after[0];
+
+            if level.boundary && level.targets[0] < level.base {
+                let base = &before[level.base - 1];
+                let pos = level.targets.binary_search(&level.base).unwrap();
+                let to_base_t = level.reach[pos].as_ref().unwrap().transpose();
+
+                for (&sublevel, matrix) in level.targets.iter().zip(&mut level.reach) {
+                    if sublevel < level.base {
+                        let pos = base.targets.binary_search(&sublevel).unwrap();
+                        *matrix = base.reach[pos].as_ref().map(|matrix| matrix * &to_base_t);
+                    }
+                }
+            }
+        }
+
+        // Only keep the levels of the DAG that can be jumped to.
+        for level in levels {
+            let target = self.levels.len();
+            self.dag_bitmap.move_level(level.level, target);
+            self.levels.push(level.into_level());
+        }
+
+        for segment in self.boundaries.windows(2) {
+            let (base, last) = (segment[0].0, segment[1].0);
+            let base_id = self.levels[base].id;
+
+            for level_no in base + 1..last {
+                self.levels[level_no].make_relative(level_no, base, base_id);
+            }
+        }
+
+        if self.levels.len() > 1 {
+            self.dag_mem_before_trunk = self.dag_bitmap.get_memory_usage();
+            self.last_level = self.levels.len() - 1;
+            self.dag_bitmap.truncate(self.levels.len());
+        }
+
+        self.last_level_was_jump_target = true;
+    }
+
+    /// Compute the jump levels of each level of the DAG and the levels each
+    /// level of the index keeps a reach matrix to, which only requires to
+    /// follow the edges of the DAG. Returns the level of the DAG each chunk
+    /// starts from and the levels of the index of each chunk.
+    fn plan_levels<'a, F, I>(
+        &mut self,
+        chunks: &[F],
+        nonjump_adj: &[Vec<usize>],
+    ) -> (Vec<usize>, Vec<Vec<PendingLevel>>)
+    where
+        F: Fn() -> I,
+        I: Iterator<Item = (usize, &'a [Vec<usize>])>,
+    {
+        let mut starts = Vec::with_capacity(chunks.len());
+        let mut plans = Vec::with_capacity(chunks.len());
+        let mut prev_level = self.dag_bitmap.get_level(0);
+        let mut jl = self.levels[0].jl.clone();
+        let mut targets = Vec::new();
+        let mut base = 0;
+        let mut base_targets = Vec::new();
+        let mut num_levels = 1;
+        let mut level = 0;
+
+        for chunk in chunks {
+            let mut pending = Vec::new();
+            starts.push(level);
+
+            for (width, jump_adj) in chunk() {
+                level += 1;
+                self.offset += width - 1;
+
+                let curr_level = self.dag_bitmap.get_level(level);
+                let t_to_i = self.vertex_indices(&curr_level);
+                let prev_level_no = num_levels - 1;
+                jl = self.compute_jl(
+                    &curr_level,
+                    &prev_level,
+                    jump_adj,
+                    nonjump_adj,
+                    &jl,
+                    &t_to_i,
+                    prev_level_no,
+                );
+
+                let checkpoint = self.take_checkpoint(level);
+
+                // no rlevel will point to this level
+                if curr_level.is_disjoint(&self.jump_vertices)
+                    && (level < self.last_level)
+                    && checkpoint.is_none()
+                {
+                    prev_level = curr_level;
+                    continue;
+                }
+
+                let boundary =
+                    checkpoint.or_else(|| (level == self.last_level).then(|| curr_level.clone()));
+
+                prev_level = curr_level;
+
+                // Same targets as `init_reach_between`.
+                let rlev = self.sublevels(&jl);
+                let before_base = jl.iter().any(|&jl| jl < base);
+                let mut sublevels: Vec<_> = rlev.iter().copied().filter(|&l| l >= base).collect();
+
+                if before_base && sublevels.first() != Some(&base) {
+                    sublevels.insert(0, base);
+                }
+
+                sublevels.retain(|&sublevel| {
+                    sublevel != prev_level_no && targets.binary_search(&sublevel).is_ok()
+                });
+
+                sublevels.push(prev_level_no);
+
+                if boundary.is_some() && sublevels[0] == base && before_base {
+                    let before = base_targets
+                        .iter()
+                        .copied()
+                        .filter(|&l| l < base && rlev.binary_search(&l).is_ok());
+
+                    sublevels.splice(0..0, before.collect::<Vec<_>>());
+                }
+
+                targets = sublevels.clone();
+                num_levels += 1;
+
+                pending.push(PendingLevel {
+                    level,
+                    id: level + self.offset,
+                    base,
+                    boundary: boundary.is_some(),
+                    jl: jl.clone(),
+                    reach: sublevels.iter().map(|_| None).collect(),
+                    targets: sublevels,
+                    from_start: None,
+                });
+
+                if let Some(forward) = boundary {
+                    base = num_levels - 1;
+                    base_targets = targets.clone();
+                    self.boundaries.push((base, forward));
+                }
+            }
+
+            plans.push(pending);
+        }
+
+        (starts, plans)
+    }
+
+    /// Compute the reach matrices of the levels of the index built from a
+    /// chunk that point inside of the chunk, the chunk starting after given
+    /// level of the DAG and its first level of the index being `first`.
+    fn init_segment<'a>(
+        &self,
+        chunk: impl Iterator<Item = (usize, &'a [Vec<usize>])>,
+        start: usize,
+        first: usize,
+        mut levels: Vec<PendingLevel>,
+    ) -> Segment {
+        let mut prev_level = self.dag_bitmap.get_level(start);
+        let mut reach_matrix: Option<Matrix> = None;
+        let mut next = 0;
+
+        for (level, (_, jump_adj)) in (start + 1..).zip(chunk) {
+            let curr_level = self.dag_bitmap.get_level(level);
+            let t_to_i = self.vertex_indices(&curr_level);
+            let step = self.transition_matrix(&curr_level, &prev_level, jump_adj, &t_to_i);
+            prev_level = curr_level;
+
+            let new_reach = match &reach_matrix {
+                None => step.transpose(),
+                Some(matrix) => matrix * &step,
+            };
+
+            // no rlevel will point to this level
+            if levels.get(next).map(|pending| pending.level) != Some(level) {
+                reach_matrix = Some(new_reach);
+                continue;
+            }
+
+            let new_reach_t = match reach_matrix.take() {
+                None => step,
+                Some(_) => new_reach.transpose(),
+            };
+
+            let (done, rest) = levels.split_at_mut(next);
+            let curr = &mut rest[0];
+            let index = first + next;
+            next += 1;
+
+            let prev = match done.last() {
+                Some(prev) => prev,
+                None => {
+                    // The first level only points before the chunk.
+                    curr.from_start = Some(new_reach);
+                    continue;
+                }
+            };
+
+            for (&sublevel, matrix) in curr.targets.iter().zip(&mut curr.reach) {
+                if sublevel >= first && sublevel >= curr.base && sublevel + 1 < index {
+                    let pos = prev.targets.binary_search(&sublevel).unwrap();
+                    *matrix = prev.reach[pos].as_ref().map(|matrix| matrix * &new_reach_t);
+                }
+            }
+
+            // Reach matrices from the start of the chunk are only required
+            // while some matrices point before the chunk.
+            if curr.targets.iter().any(|&l| l >= curr.base && l < first) {
+                curr.from_start = prev.from_start.as_ref().map(|matrix| matrix * &new_reach_t);
+            }
+
+            *curr.reach.last_mut().unwrap() = Some(new_reach);
+        }
+
+        Segment {
+            first,
+            levels,
+            tail: reach_matrix,
+        }
+    }
+}
+
+/// Level of the index whose reach matrices are computed in parallel, the
+/// levels it keeps a reach matrix to are known beforehand.
+struct PendingLevel {
+    /// Level of the DAG.
+    level: usize,
+    id: usize,
+    /// Base of the segment of the level, matrices to levels before the base
+    /// are composed once all other matrices are computed.
+    base: usize,
+    boundary: bool,
+    jl: Vec<usize>,
+    targets: Vec<usize>,
+    reach: Vec<Option<Matrix>>,
+    /// Reach matrix from the first level of the chunk, kept until the
+    /// matrices that point before the chunk are computed.
+    from_start: Option<Matrix>,
+}
+
+impl PendingLevel {
+    /// Compute the reach matrices that point before the chunk of the level,
+    /// given the last level of the index before the chunk (`None` for the
+    /// first level of the index) and the reach matrix from this level to the
+    /// start of the chunk (`None` if the chunk starts from this level).
+    fn complete(&mut self, first: usize, base: Option<&PendingLevel>, tail: Option<&Matrix>) {
+        let from_start = match self.from_start.take() {
+            None => return,
+            Some(from_start) => from_start,
+        };
+
+        let from_base = match tail {
+            None => from_start,
+            Some(tail) => product(tail, &from_start),
+        };
+
+        let base_no = first - 1;
+        let from_base_t = from_base.transpose();
+
+        for (&sublevel, matrix) in self.targets.iter().zip(&mut self.reach) {
+            if sublevel < base_no && sublevel >= self.base {
+                let base = base.unwrap();
+                let pos = base.targets.binary_search(&sublevel).unwrap();
+                *matrix = base.reach[pos].as_ref().map(|matrix| matrix * &from_base_t);
+            }
+        }
+
+        if let Ok(pos) = self.targets.binary_search(&base_no) {
+            self.reach[pos] = Some(from_base);
+        }
+    }
+
+    fn into_level(self) -> Level {
+        let reach = self
+            .targets
+            .into_iter()
+            .zip(self.reach)
+            .map(|(sublevel, matrix)| (sublevel, matrix.expect("a reach matrix is missing")))
+            .collect();
+
+        Level {
+            id: self.id,
+            base: 0,
+            jl: self.jl,
+            reach,
+        }
+    }
+}
+
+/// Levels of the index built from a chunk of the text.
+struct Segment {
+    /// Number of the first level of the index built from the chunk.
+    first: usize,
+    levels: Vec<PendingLevel>,
+    /// Reach matrix from the last level of the index built from the chunk,
+    /// or from the start of the chunk, to the end of the chunk if it can't be
+    /// jumped to.
+    tail: Option<Matrix>,
+}
+
+/// Compose two reach matrices.
+fn product(left: &Matrix, right: &Matrix) -> Matrix {
+    left * &right.transpose()
+}
+
+/// Relation between vertices at the boundaries of a chunk of the DAG, for
+/// each vertex of the last level it holds the vertices of the first level it
+/// can be reached from.
+struct TransferRelation {
+    sources: Vec<BitSet>,
+}
+
+impl TransferRelation {
+    /// Get the vertices of the last level reached from a set of vertices of
+    /// the first level.
+    fn apply(&self, vertices: &BitSet) -> BitSet {
+        self.sources
+            .iter()
+            .enumerate()
+            .filter(|(_, sources)| !sources.is_disjoint(vertices))
+            .map(|(target, _)| target)
+            .collect()
+    }
+}
+
+/// Compute the relation between the boundaries of a chunk given by its
+/// adjacency lists, together with its number of levels.
+fn transfer_relation<'a>(
+    chunk: impl Iterator<Item = &'a [Vec<usize>]>,
+    num_vertices: usize,
+) -> (usize, TransferRelation) {
+    let mut len = 0;
+    let mut sources: Vec<BitSet> = (0..num_vertices)
+        .map(|vertex| {
+            let mut set = BitSet::with_capacity(num_vertices);
+            set.insert(vertex);
+            set
+        })
+        .collect();
+
+    let mut next_sources = sources.clone();
+
+    for adj in chunk {
+        for set in &mut next_sources {
+            set.clear();
+        }
+
+        for (source, targets) in adj.iter().enumerate() {
+            if sources[source].is_empty() {
+                continue;
+            }
+
+            for &target in targets {
+                next_sources[target].union_with(&sources[source]);
+            }
+        }
+
+        std::mem::swap(&mut sources, &mut next_sources);
+        len += 1;
+    }
+
+    (len, TransferRelation { sources })
+}
+
+/// Get the set of targets of edges from a set of vertices.
+pub fn successors(adj: &[Vec<usize>], vertices: &BitSet, num_vertices: usize) -> BitSet {
+    let mut result = BitSet::with_capacity(num_vertices);
+
+    for source in vertices.iter() {
+        for &target in &adj[source] {
+            result.insert(target);
+        }
+    }
+
+    result
+}
+
+//  _   _           _       _
+// | | | |_ __   __| | __ _| |_ ___
+// | | | | '_ \ / _` |/ _` | __/ _ \
+// | |_| | |_) | (_| | (_| | ||  __/
+//  \___/| .__/ \__,_|\__,_|\__\___|
+//       |_|
+//
+// The index is split into segments of at most `SEGMENT_LEN` levels of the
+// DAG. Levels at the boundaries of segments are always kept in the index,
+// together with the vertices reached at this level before trimming. Levels
+// inside of a segment only point to levels of their segment and are stored
+// relatively to its first level, the base: a jump before the base goes
+// through it. Only the boundaries point to levels of previous segments, with
+// reach matrices composed from the ones of the base of their segment.
+//
+// When the text is edited, the DAG is built again over the segments around
+// the edit until it reaches the same vertices as before at a boundary on both
+// sides. Levels of following segments are unchanged as they are relative, the
+// reach matrices of following boundaries are composed again while they
+// change.
+
+impl Jump {
+    pub fn num_boundaries(&self) -> usize {
+        self.boundaries.len()
+    }
+
+    /// Get the position in the text of a boundary of segments.
+    pub fn get_boundary_pos(&self, boundary: usize) -> usize {
+        self.levels[self.boundaries[boundary].0].id
+    }
+
+    /// Get the vertices reached at a boundary of segments before trimming.
+    pub fn get_boundary_forward(&self, boundary: usize) -> &BitSet {
+        &self.boundaries[boundary].1
+    }
+
+    /// Get the vertices of the DAG at a boundary of segments.
+    pub fn get_boundary_level(&self, boundary: usize) -> BitSet {
+        self.dag_bitmap.get_level(self.boundaries[boundary].0)
+    }
+
+    /// Get the range of boundaries of the smallest range of segments that
+    /// covers a range of the text, the index must hold several boundaries.
+    pub fn segments_around(&self, range: &Range<usize>) -> Range<usize> {
+        let pos = |&(level, _): &(usize, BitSet)| self.levels[level].id;
+        let last = self.boundaries.len() - 1;
+        let start = self.boundaries.partition_point(|b| pos(b) <= range.start) - 1;
+        let end = self.boundaries.partition_point(|b| pos(b) < range.end);
+        let start = start.min(last - 1);
+
+        start..max(end.min(last), start + 1)
+    }
+
+    /// Replace the segments between two boundaries with the levels of the DAG
+    /// built over the new text between these boundaries, given before and
+    /// after trimming, which start and end at the boundaries. The vertices of
+    /// the first boundary are kept unless it is the first level of the index,
+    /// the last one must be unchanged unless it is the last level.
+    pub fn replace_segments(
+        &mut self,
+        segments: Range<usize>,
+        symbols: &[(usize, &[Vec<usize>])],
+        forward: &[BitSet],
+        trimmed: &[BitSet],
+        nonjump_adj: &[Vec<usize>],
+    ) {
+        let first = self.boundaries[segments.start].0;
+        let last = self.boundaries[segments.end].0;
+        let last_pos = self.levels[last].id;
+        let following = self.boundaries.split_off(segments.end + 1);
+        self.boundaries.truncate(segments.start + 1);
+
+        // Levels from the first boundary are built apart from other levels.
+        let base_level = std::mem::replace(&mut self.levels[first], Level::empty());
+        let mut levels = std::mem::replace(&mut self.levels, vec![base_level]);
+        self.levels_start = first;
+
+        let first_level = if first == 0 {
+            self.levels[0].jl = vec![0; trimmed[0].len()];
+            trimmed[0].clone()
+        } else {
+            self.dag_bitmap.get_level(first)
+        };
+
+        self.last_level = first + symbols.len();
+        self.offset = self.levels[0].id - first;
+        self.last_level_was_jump_target = true;
+
+        for level in Jump::checkpoint_levels(first, self.last_level) {
+            self.add_checkpoint(level, forward[level - first].clone());
+        }
+
+        let mut stored = Vec::new();
+        let mut prev_level = &first_level;
+
+        for (level, &(width, jump_adj)) in (first + 1..).zip(symbols) {
+            let curr_level = &trimmed[level - first];
+
+            if self.init_reach_between(level, width, curr_level, prev_level, jump_adj, nonjump_adj)
+            {
+                stored.push(curr_level.clone());
+            }
+
+            prev_level = curr_level;
+        }
+
+        if first == 0 {
+            stored.insert(0, first_level);
+            self.dag_bitmap.splice(0..last + 1, &stored);
+        } else {
+            self.dag_bitmap.splice(first + 1..last + 1, &stored);
+        }
+
+        // Levels of following segments are kept, only their boundaries are
+        // updated.
+        let new_last = first + self.levels.len() - 1;
+        let new_pos = self.levels.last().unwrap().id;
+        let mut built = std::mem::take(&mut self.levels).into_iter();
+        levels[first] = built.next().unwrap();
+        levels.splice(first + 1..last + 1, built);
+        self.levels = levels;
+        self.levels_start = 0;
+
+        let renumber = |level: usize| match level {
+            level if level <= first => Some(level),
+            level if level >= last => Some(level - last + new_last),
+            _ => None,
+        };
+
+        self.last_level = self.levels.len() - 1;
+        let mut old_base = last;
+        let mut settled = false;
+
+        for (old_level, forward) in following {
+            let level_no = renumber(old_level).unwrap();
+            let mut level = std::mem::replace(&mut self.levels[level_no], Level::empty());
+            level.id = level.id - last_pos + new_pos;
+            settled = self.shift_boundary(&mut level, old_base, renumber, settled);
+            self.levels[level_no] = level;
+            self.boundaries.push((level_no, forward));
+            old_base = old_level;
+        }
+    }
+
+    /// Update a boundary of segments that follows replaced levels of the
+    /// index, given the previous base of its segment and the new number of
+    /// the levels that are kept. Its reach matrices to previous segments are
+    /// composed again from the base of its segment, unless its previous
+    /// boundary is settled: unchanged up to the numbering of levels. Returns
+    /// whether this boundary is settled.
+    fn shift_boundary(
+        &self,
+        level: &mut Level,
+        old_base: usize,
+        renumber: impl Fn(usize) -> Option<usize>,
+        settled: bool,
+    ) -> bool {
+        let shifted_jl: Option<Vec<_>> = level
+            .jl
+            .iter()
+            .map(|&jl| match jl {
+                usize::MAX => Some(jl),
+                jl => renumber(jl),
+            })
+            .collect();
+
+        let shifted_targets: Option<Vec<_>> =
+            level.reach.iter().map(|&(l, _)| renumber(l)).collect();
+
+        if let (true, Some(jl), Some(targets)) = (settled, &shifted_jl, shifted_targets) {
+            level.jl = jl.clone();
+
+            for ((l, _), target) in level.reach.iter_mut().zip(targets) {
+                *l = target;
+            }
+
+            return true;
+        }
+
+        let base = self.boundaries.last().unwrap().0;
+        let num_before = level
+            .reach
+            .iter()
+            .take_while(|&&(l, _)| l < old_base)
+            .count();
+        let old_before: Vec<_> = level.reach.drain(..num_before).collect();
+
+        for (l, _) in &mut level.reach {
+            *l = renumber(*l).unwrap();
+        }
+
+        // Vertices that jump before the base only follow paths without
+        // assignations from the base.
+        let to_base = level
+            .reach
+            .iter()
+            .find(|&&(l, _)| l == base)
+            .map(|(_, m)| m);
+        let base_jl = &self.levels[base].jl;
+        let jl: Vec<_> = level
+            .jl
+            .iter()
+            .enumerate()
+            .map(|(vertex, &jl)| match (jl, to_base) {
+                (usize::MAX, _) => usize::MAX,
+                (jl, _) if jl >= old_base => renumber(jl).unwrap(),
+                (_, Some(to_base)) => (0..to_base.get_height())
+                    .filter(|&source| to_base[(source, vertex)])
+                    .map(|source| base_jl[source])
+                    .filter(|&jl| jl != usize::MAX)
+                    .max()
+                    .unwrap_or(usize::MAX),
+                (_, None) => usize::MAX,
+            })
+            .collect();
+
+        let before = match to_base {
+            Some(to_base) if jl.iter().any(|&jl| jl < base) => {
+                self.compose_before_base(base, to_base, &self.sublevels(&jl))
+            }
+            _ => Vec::new(),
+        };
+
+        let settled = shifted_jl.as_ref() == Some(&jl)
+            && old_before.len() == before.len()
+            && old_before
+                .iter()
+                .zip(&before)
+                .all(|((l, matrix), (new_l, new_matrix))| {
+                    renumber(*l) == Some(*new_l) && matrix == new_matrix
+                });
+
+        level.jl = jl;
+        level.reach.splice(0..0, before);
+        settled
+    }
+}
+
+impl Persist for Level {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.id.persist(output)?;
+        self.base.persist(output)?;
+        self.jl.persist(output)?;
+        self.reach.persist(output)
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<Level> {
+        Ok(Level {
+            id: usize::restore(input)?,
+            base: usize::restore(input)?,
+            jl: Vec::restore(input)?,
+            reach: Vec::restore(input)?,
+        })
+    }
+}
+
+/// Only the structures required for enumeration are persisted, thus a restored
+/// jump function can't be extended with new levels.
+impl Persist for Jump {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.num_vertices.persist(output)?;
+        self.jump_distance.persist(output)?;
+        self.last_level.persist(output)?;
+        self.offset.persist(output)?;
+        self.dag_mem_before_trunk.persist(output)?;
+        self.jump_vertices.persist(output)?;
+        self.dag_bitmap.persist(output)?;
+        self.levels.persist(output)?;
+        self.boundaries.persist(output)
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<Jump> {
+        let jump = Jump {
+            num_vertices: usize::restore(input)?,
+            jump_distance: usize::restore(input)?,
+            last_level: usize::restore(input)?,
+            offset: usize::restore(input)?,
+            dag_mem_before_trunk: usize::restore(input)?,
+            jump_vertices: BitSet::restore(input)?,
+            dag_bitmap: LevelSet::restore(input)?,
+            levels: Vec::restore(input)?,
+            boundaries: Vec::restore(input)?,
+            reach_matrix: Matrix::new(1, 1),
+            last_jl: Vec::new(),
+            last_level_was_jump_target: true,
+            checkpoints: Vec::new(),
+            levels_start: 0,
+        };
+
+        // Check that the jump function won't point out of its bounds.
+        if jump.levels.is_empty()
+            || jump.last_level + 1 != jump.levels.len()
+            || jump.dag_bitmap.num_levels() < jump.levels.len()
+        {
+            return Err(invalid_data("inconsistent number of levels"));
+        }
+
+        let widths: Vec<_> = (0..jump.levels.len())
+            .map(|level_id| jump.dag_bitmap.get_level(level_id).len())
+            .collect();
+
+        // Levels inside of a segment must be relative to the previous boundary.
+        let mut boundaries = jump.boundaries.iter().map(|&(level_id, _)| level_id);
+        let mut base = None;
+        let mut next_boundary = boundaries.next();
+
+        if next_boundary != Some(0) || jump.boundaries.last().map(|b| b.0) != Some(jump.last_level)
+        {
+            return Err(invalid_data("inconsistent boundaries of segments"));
+        }
+
+        for (level_id, level) in jump.levels.iter().enumerate() {
+            let offset = if Some(level_id) == next_boundary {
+                base = next_boundary;
+                next_boundary = boundaries.next();
+
+                if level.base != 0 || next_boundary.is_some_and(|next| next <= level_id) {
+                    return Err(invalid_data("inconsistent boundaries of segments"));
+                }
+
+                0
+            } else {
+                match base {
+                    Some(base) if level.base != 0 && base + level.base == level_id => base,
+                    _ => return Err(invalid_data("inconsistent boundaries of segments")),
+                }
+            };
+
+            let valid_target = |target: usize| {
+                offset
+                    .checked_add(target)
+                    .is_some_and(|target| target < level_id)
+                    || level_id == 0
+            };
+
+            if level.jl.len() != widths[level_id]
+                || level.jl.iter().any(|&jl| {
+                    jl != usize::MAX && !(jl == BEFORE_BASE && offset > 0) && !valid_target(jl)
+                })
+                || level.reach.iter().any(|(target, matrix)| {
+                    !valid_target(*target)
+                        || matrix.get_width() != widths[level_id]
+                        || matrix.get_height() != widths[offset + *target]
+                })
+            {
+                return Err(invalid_data("inconsistent level"));
+            }
+        }
+
+        Ok(jump)
+    }
+}
+
 /// iterates over all matrices for statistical reasons
 struct MatrixIterator<'a> {
     level_iterator: std::slice::Iter<'a, Level>,
@@ -515,7 +1734,7 @@ struct MatrixIterator<'a> {
 }
 
 impl<'a> MatrixIterator<'a> {
-    fn init(jump: &'a Jump) -> MatrixIterator {
+    fn init(jump: &'a Jump) -> MatrixIterator<'a> {
         let mut level_iterator = jump.levels.iter();
         let matrix_iterator = level_iterator.next().unwrap().reach.iter();
 
@@ -1,22 +1,28 @@
 use bit_set::BitSet;
 use bit_vec::BitVec;
+use std::env;
 use std::fmt;
+use std::fs::{self, File, OpenOptions};
+use std::io;
+use std::io::prelude::*;
+use std::io::{BufReader, SeekFrom};
+use std::ops::Range;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
 
-use std::cell::Cell;
-use std::cell::RefCell;
+use super::super::persist::{Persist, invalid_data};
 
 /// Represent the partitioning into levels of a product graph.
 ///
 /// A same vertex can be store in several levels, and this level hierarchy can
-/// be accessed rather efficiently.
+/// be accessed rather efficiently. Read accesses don't rely on any shared
+/// buffer, so that the enumeration can run from several threads.
 pub struct LevelSet {
     num_vertices: usize,
     effective_level_size: usize,
     /// Index level contents: `level id` -> `vertex id's list`.
     levels: BitVec,
-    temp_level: RefCell<BitVec>,
-    temp_level_no: Cell<usize>,
-    temp_levelset: RefCell<BitSet>,
 }
 
 impl LevelSet {
@@ -27,9 +33,6 @@ impl LevelSet {
             num_vertices,
             effective_level_size,
             levels: BitVec::<u32>::from_elem(effective_level_size * 32 * num_levels, false),
-            temp_level: RefCell::new(BitVec::from_elem(effective_level_size * 32, false)),
-            temp_level_no: Cell::new(0),
-            temp_levelset: RefCell::new(BitSet::with_capacity(num_vertices)),
         }
     }
 
@@ -64,29 +67,27 @@ impl LevelSet {
         BitSet::from_bit_vec(levelset)
     }
 
-    fn set_temp(&self, level: usize) {
+    /// Copy a level, padded to a multiple of 32 vertices.
+    fn get_level_vec(&self, level: usize) -> BitVec {
+        let mut level_vec = BitVec::from_elem(self.effective_level_size * 32, false);
+
         unsafe {
-            if self.temp_level_no.get() != level {
-                let levels_storage = self.levels.storage();
-                let mut temp = self.temp_level.borrow_mut();
-                let temp_storage = temp.storage_mut();
-
-                self.temp_level_no.set(level);
-                for i in 0..self.effective_level_size {
-                    temp_storage[i] = levels_storage[level * self.effective_level_size + i];
-                }
+            let levels_storage = self.levels.storage();
+            let level_storage = level_vec.storage_mut();
+            for i in 0..self.effective_level_size {
+                level_storage[i] = levels_storage[level * self.effective_level_size + i];
             }
         }
+
+        level_vec
     }
 
     pub fn indices_to_vertices(&self, level: usize, indices: &mut BitSet) {
-        let mut temp_indices = self.temp_levelset.borrow_mut();
-        temp_indices.clone_from(indices);
+        let temp_indices = indices.clone();
         indices.clear();
         let vertices = indices;
-        self.set_temp(level);
-        let level_vec = &self.temp_level.borrow();
-        let mut level_iter = level_vec.iter().enumerate().filter(|&(_, x)| x == true);
+        let level_vec = self.get_level_vec(level);
+        let mut level_iter = level_vec.iter().enumerate().filter(|&(_, x)| x);
 
         let mut last = 0;
 
@@ -117,14 +118,12 @@ impl LevelSet {
     }
 
     pub fn vertices_to_indices(&self, level: usize, vertices: &mut BitSet) {
-        let mut temp_vertices = self.temp_levelset.borrow_mut();
-        temp_vertices.clone_from(vertices);
+        let temp_vertices = vertices.clone();
         vertices.clear();
         let indices = vertices;
         let mut count = 0;
 
-        self.set_temp(level);
-        let level_vec = &self.temp_level.borrow();
+        let level_vec = self.get_level_vec(level);
         let mut vertex = 0;
         let mut level_iter = level_vec.iter().map(|x| {
             if x {
@@ -153,9 +152,320 @@ impl LevelSet {
             .set(level * self.effective_level_size * 32 + vertex, true);
     }
 
+    /// Add empty levels after the last one.
+    pub fn grow(&mut self, num_levels: usize) {
+        self.levels
+            .grow(num_levels * self.effective_level_size * 32, false);
+    }
+
+    /// Add a level after the last one.
+    pub fn push_level(&mut self, vertices: &BitSet) {
+        let level = self.num_levels();
+        self.grow(1);
+
+        for vertex in vertices.iter() {
+            self.register(level, vertex);
+        }
+    }
+
+    /// Replace a range of levels by given levels.
+    pub fn splice(&mut self, range: Range<usize>, levels: &[BitSet]) {
+        let level_size = self.effective_level_size;
+        let num_levels = self.num_levels();
+        let new_num_levels = num_levels - range.len() + levels.len();
+
+        if new_num_levels > num_levels {
+            self.grow(new_num_levels - num_levels);
+        }
+
+        unsafe {
+            let levels_storage = self.levels.storage_mut();
+            levels_storage.copy_within(
+                range.end * level_size..num_levels * level_size,
+                (range.start + levels.len()) * level_size,
+            );
+
+            for (level, vertices) in (range.start..).zip(levels) {
+                let vertices_storage = vertices.get_ref().storage();
+
+                for i in 0..level_size {
+                    levels_storage[level * level_size + i] =
+                        vertices_storage.get(i).copied().unwrap_or(0);
+                }
+            }
+        }
+
+        self.levels
+            .truncate(new_num_levels * self.effective_level_size * 32);
+    }
+
+    /// Split the level set into mutable views over disjoint ranges of levels,
+    /// which must be sorted, so that they can be filled from several threads.
+    pub fn split_mut(&mut self, ranges: &[Range<usize>]) -> Vec<LevelsMut<'_>> {
+        let effective_level_size = self.effective_level_size;
+        let num_vertices = self.num_vertices;
+        let mut storage = unsafe { &mut self.levels.storage_mut()[..] };
+        let mut offset = 0;
+        let mut views = Vec::with_capacity(ranges.len());
+
+        for range in ranges {
+            let (_, rest) = storage.split_at_mut((range.start - offset) * effective_level_size);
+            let (view, rest) = rest.split_at_mut(range.len() * effective_level_size);
+            storage = rest;
+            offset = range.end;
+
+            views.push(LevelsMut {
+                first: range.start,
+                num_vertices,
+                effective_level_size,
+                storage: view,
+            });
+        }
+
+        views
+    }
+
+    pub fn num_levels(&self) -> usize {
+        self.levels.len() / (self.effective_level_size * 32)
+    }
+
     pub fn get_memory_usage(&self) -> usize {
         self.levels.capacity() / 8
     }
+
+    /// Memory used by a level set with given dimensions, in bytes.
+    pub fn memory_usage(num_levels: usize, num_vertices: usize) -> usize {
+        num_levels * (((num_vertices - 1) / 32) + 1) * 4
+    }
+}
+
+/// Mutable view over a range of levels of a `LevelSet`.
+pub struct LevelsMut<'a> {
+    first: usize,
+    num_vertices: usize,
+    effective_level_size: usize,
+    storage: &'a mut [u32],
+}
+
+impl<'a> LevelsMut<'a> {
+    fn get_storage(&self, level: usize) -> Range<usize> {
+        let start = (level - self.first) * self.effective_level_size;
+        start..start + self.effective_level_size
+    }
+
+    pub fn get_level(&self, level: usize) -> BitSet {
+        let mut levelset = BitVec::from_elem(self.num_vertices, false);
+
+        unsafe {
+            levelset
+                .storage_mut()
+                .copy_from_slice(&self.storage[self.get_storage(level)]);
+        }
+
+        BitSet::from_bit_vec(levelset)
+    }
+
+    /// Replace the content of a level, vertices must be smaller than the
+    /// number of vertices of the level set.
+    pub fn set_level(&mut self, level: usize, vertices: &BitSet) {
+        let range = self.get_storage(level);
+        let level_storage = &mut self.storage[range];
+        let vertices_storage = vertices.get_ref().storage();
+
+        for (i, word) in level_storage.iter_mut().enumerate() {
+            *word = vertices_storage.get(i).copied().unwrap_or(0);
+        }
+    }
+}
+
+//  _                   _ _____ _ _
+// | |    _____   _____| |  ___(_) | ___
+// | |   / _ \ \ / / _ \ | |_  | | |/ _ \
+// | |__|  __/\ V /  __/ |  _| | | |  __/
+// |_____\___| \_/ \___|_|_|   |_|_|\___|
+//
+
+/// Number of levels read or written at once in a `LevelFile`.
+static LEVELS_PER_BLOCK: usize = 4096;
+
+/// Levels of a product graph stored in a temporary file rather than in
+/// memory, together with the symbol read to reach each level. The file is
+/// removed once the structure is dropped.
+pub struct LevelFile {
+    file: File,
+    path: PathBuf,
+    num_vertices: usize,
+    effective_level_size: usize,
+    num_levels: usize,
+    /// Levels that are not written to the file yet.
+    pending: Vec<u8>,
+}
+
+impl LevelFile {
+    /// Create an empty level file in the temporary directory of the system.
+    pub fn create(num_vertices: usize) -> io::Result<LevelFile> {
+        static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "enum-spanner-{}-{}.levels",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        let file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create_new(true)
+            .open(&path)?;
+
+        Ok(LevelFile {
+            file,
+            path,
+            num_vertices,
+            effective_level_size: ((num_vertices - 1) / 32) + 1,
+            num_levels: 0,
+            pending: Vec::new(),
+        })
+    }
+
+    pub fn num_levels(&self) -> usize {
+        self.num_levels
+    }
+
+    /// Size of a level in the file, in bytes.
+    fn record_size(&self) -> usize {
+        4 * (self.effective_level_size + 1)
+    }
+
+    /// Add a level after the last one.
+    pub fn push(&mut self, symbol: char, vertices: &BitSet) -> io::Result<()> {
+        let mut record = Vec::with_capacity(self.record_size());
+        self.encode(symbol, vertices, &mut record);
+        self.pending.extend_from_slice(&record);
+        self.num_levels += 1;
+
+        if self.pending.len() >= LEVELS_PER_BLOCK * self.record_size() {
+            self.flush()?;
+        }
+
+        Ok(())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(&self.pending)?;
+        self.pending.clear();
+        Ok(())
+    }
+
+    /// Read the levels from the first one to the last one.
+    pub fn for_each(&mut self, mut func: impl FnMut(usize, char, BitSet)) -> io::Result<()> {
+        self.flush()?;
+        self.file.seek(SeekFrom::Start(0))?;
+        let mut input = BufReader::new(&self.file);
+        let mut record = vec![0; self.record_size()];
+
+        for level in 0..self.num_levels {
+            input.read_exact(&mut record)?;
+            let (symbol, vertices) = self.decode(&record)?;
+            func(level, symbol, vertices);
+        }
+
+        Ok(())
+    }
+
+    /// Update the levels from the last one to the first one.
+    pub fn update_rev(&mut self, mut func: impl FnMut(usize, char, &mut BitSet)) -> io::Result<()> {
+        self.flush()?;
+        let record_size = self.record_size();
+        let mut end = self.num_levels;
+
+        while end > 0 {
+            let start = end.saturating_sub(LEVELS_PER_BLOCK);
+            let mut block = vec![0; (end - start) * record_size];
+            self.file
+                .seek(SeekFrom::Start((start * record_size) as u64))?;
+            self.file.read_exact(&mut block)?;
+
+            for level in (start..end).rev() {
+                let offset = (level - start) * record_size;
+                let record = &mut block[offset..offset + record_size];
+                let (symbol, mut vertices) = self.decode(record)?;
+                func(level, symbol, &mut vertices);
+
+                let mut updated = Vec::with_capacity(record_size);
+                self.encode(symbol, &vertices, &mut updated);
+                record.copy_from_slice(&updated);
+            }
+
+            self.file
+                .seek(SeekFrom::Start((start * record_size) as u64))?;
+            self.file.write_all(&block)?;
+            end = start;
+        }
+
+        Ok(())
+    }
+
+    fn encode(&self, symbol: char, vertices: &BitSet, output: &mut Vec<u8>) {
+        let storage = vertices.get_ref().storage();
+        output.extend_from_slice(&u32::from(symbol).to_le_bytes());
+
+        for i in 0..self.effective_level_size {
+            let word = storage.get(i).copied().unwrap_or(0);
+            output.extend_from_slice(&word.to_le_bytes());
+        }
+    }
+
+    fn decode(&self, record: &[u8]) -> io::Result<(char, BitSet)> {
+        let mut words = record
+            .chunks_exact(4)
+            .map(|word| u32::from_le_bytes([word[0], word[1], word[2], word[3]]));
+
+        let symbol = char::from_u32(words.next().unwrap())
+            .ok_or_else(|| invalid_data("invalid symbol in level file"))?;
+
+        let mut vertices = BitVec::from_elem(self.num_vertices, false);
+
+        unsafe {
+            for (stored, word) in vertices.storage_mut().iter_mut().zip(words) {
+                *stored = word;
+            }
+        }
+
+        Ok((symbol, BitSet::from_bit_vec(vertices)))
+    }
+}
+
+impl Drop for LevelFile {
+    fn drop(&mut self) {
+        fs::remove_file(&self.path).ok();
+    }
+}
+
+impl Persist for LevelSet {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.num_vertices.persist(output)?;
+        self.levels.persist(output)
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<LevelSet> {
+        let num_vertices = usize::restore(input)?;
+
+        if num_vertices == 0 {
+            return Err(invalid_data("a level set must contain vertices"));
+        }
+
+        let mut levelset = LevelSet::new(0, num_vertices);
+        let levels = BitVec::restore(input)?;
+
+        if levels.len() % (levelset.effective_level_size * 32) != 0 {
+            return Err(invalid_data("level set is not aligned on levels"));
+        }
+
+        levelset.levels = levels;
+        Ok(levelset)
+    }
 }
 
 impl fmt::Debug for LevelSet {
@@ -164,6 +474,6 @@ impl fmt::Debug for LevelSet {
             writeln!(f, "level {}: {:?}", level, self.get_level(level))?;
         }
 
-        writeln!(f, "")
+        writeln!(f)
     }
 }
@@ -1,19 +1,208 @@
 pub mod indexed_dag;
 
+mod equality;
 mod jump;
 mod levelset;
+mod sliding_window;
+mod text_file;
 
+use std::borrow::Cow;
 use std::cmp;
 use std::fmt;
 use std::hash::{Hash, Hasher};
+use std::io;
 use std::ops::Range;
-use std::rc::Rc;
+use std::str;
+use std::sync::Arc;
 
+use serde::{Deserialize, Serialize};
+
+pub use equality::StringEquality;
 pub use indexed_dag::IndexedDag;
+pub use sliding_window::SlidingWindow;
+pub use text_file::TextFile;
 
 pub trait SpannerEnumerator<'t> {
-    fn preprocess(&mut self);
+    /// Prepare the enumeration, which fails if the text can't be read.
+    fn preprocess(&mut self) -> io::Result<()>;
     fn iter<'i>(&'i self) -> Box<dyn Iterator<Item = Mapping<'t>> + 'i>;
+
+    /// Count the number of distinct mappings, saturating at `u128::MAX`. By
+    /// default all the mappings are enumerated.
+    fn count(&self) -> u128 {
+        self.iter().count() as u128
+    }
+}
+
+//  _____         _
+// |_   _|____  _| |_
+//   | |/ _ \ \/ / __|
+//   | |  __/>  <| |_
+//   |_|\___/_/\_\\__|
+//
+
+/// A text over which mappings are enumerated, it is either read as a sequence
+/// of unicode characters or as a sequence of raw bytes. Positions are always
+/// offsets in bytes.
+///
+/// A text can also be stored in a file, which is then read lazily.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Text<'t> {
+    Unicode(&'t str),
+    Bytes(&'t [u8]),
+    File(&'t TextFile),
+}
+
+impl<'t> Text<'t> {
+    /// Length of the text in bytes.
+    pub fn len(&self) -> usize {
+        match self {
+            Text::Unicode(text) => text.len(),
+            Text::Bytes(text) => text.len(),
+            Text::File(text) => text.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Check if the text is read as a sequence of unicode characters.
+    pub fn is_unicode(&self) -> bool {
+        match self {
+            Text::Unicode(_) => true,
+            Text::Bytes(_) => false,
+            Text::File(text) => text.is_unicode(),
+        }
+    }
+
+    /// Read a range of bytes of the text, which is read from the file for a
+    /// text stored in a file.
+    pub fn read_bytes(&self, range: Range<usize>) -> io::Result<Cow<'t, [u8]>> {
+        Ok(match self {
+            Text::Unicode(text) => Cow::Borrowed(&text.as_bytes()[range]),
+            Text::Bytes(text) => Cow::Borrowed(&text[range]),
+            Text::File(text) => Cow::Owned(text.read(range)?),
+        })
+    }
+
+    /// Get a range of bytes of the text.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the text is stored in a file that can't be read, which is
+    /// reported by `read_bytes` instead.
+    pub fn get_bytes(&self, range: Range<usize>) -> Cow<'t, [u8]> {
+        self.read_bytes(range).expect("could not read the text")
+    }
+
+    /// Read the whole text by blocks.
+    pub(crate) fn for_each_block(&self, mut func: impl FnMut(&[u8])) -> io::Result<()> {
+        match self {
+            Text::Unicode(text) => func(text.as_bytes()),
+            Text::Bytes(text) => func(text),
+            Text::File(text) => text.for_each_block(func)?,
+        }
+
+        Ok(())
+    }
+
+    /// Read the symbols of a range of the text together with their width in
+    /// bytes, an error is reported if the range splits a character.
+    pub(crate) fn read_symbols(&self, range: Range<usize>) -> io::Result<Vec<(char, usize)>> {
+        let bytes = self.read_bytes(range)?;
+
+        if !self.is_unicode() {
+            return Ok(bytes.iter().map(|&x| (char::from(x), 1)).collect());
+        }
+
+        let text = str::from_utf8(&bytes)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        Ok(text.chars().map(|x| (x, x.len_utf8())).collect())
+    }
+
+    /// Split the text into at most `n` consecutive chunks of similar sizes,
+    /// without splitting characters. A text stored in a file is not split.
+    pub fn split(&self, n: usize) -> Vec<Text<'t>> {
+        if let Text::File(_) = self {
+            return vec![*self];
+        }
+
+        let len = self.len();
+        let mut chunks = Vec::with_capacity(n);
+        let mut start = 0;
+
+        for i in 1..=n {
+            let mut end = cmp::max(start, len / n * i + cmp::min(len % n, i));
+
+            if let Text::Unicode(text) = self {
+                while !text.is_char_boundary(end) {
+                    end += 1;
+                }
+            }
+
+            if end > start {
+                chunks.push(match self {
+                    Text::Unicode(text) => Text::Unicode(&text[start..end]),
+                    Text::Bytes(text) => Text::Bytes(&text[start..end]),
+                    Text::File(_) => unreachable!(),
+                });
+            }
+
+            start = end;
+        }
+
+        if chunks.is_empty() {
+            chunks.push(*self);
+        }
+
+        chunks
+    }
+
+    /// Iterate over the symbols of the text together with their width in
+    /// bytes, a raw byte is read as the character with the same code point.
+    ///
+    /// # Panics
+    ///
+    /// Panics for a text stored in a file, which must be read with
+    /// `TextFile::for_each_symbol`.
+    pub(crate) fn symbols(&self) -> Box<dyn DoubleEndedIterator<Item = (char, usize)> + 't> {
+        match *self {
+            Text::Unicode(text) => Box::new(text.chars().map(|x| (x, x.len_utf8()))),
+            Text::Bytes(text) => Box::new(text.iter().map(|&x| (char::from(x), 1))),
+            Text::File(_) => panic!("symbols of a text file can only be read as a stream"),
+        }
+    }
+}
+
+impl<'t> From<&'t str> for Text<'t> {
+    fn from(text: &'t str) -> Text<'t> {
+        Text::Unicode(text)
+    }
+}
+
+impl<'t> From<&'t String> for Text<'t> {
+    fn from(text: &'t String) -> Text<'t> {
+        Text::Unicode(text)
+    }
+}
+
+impl<'t> From<&'t [u8]> for Text<'t> {
+    fn from(text: &'t [u8]) -> Text<'t> {
+        Text::Bytes(text)
+    }
+}
+
+impl<'t> From<&'t Vec<u8>> for Text<'t> {
+    fn from(text: &'t Vec<u8>) -> Text<'t> {
+        Text::Bytes(text)
+    }
+}
+
+impl<'t> From<&'t TextFile> for Text<'t> {
+    fn from(text: &'t TextFile) -> Text<'t> {
+        Text::File(text)
+    }
 }
 
 //  __  __                   _
@@ -26,7 +215,7 @@ pub trait SpannerEnumerator<'t> {
 /// Map a set of variables to spans [i, i'> over a text.
 #[derive(Clone, Debug, Eq, PartialEq)]
 pub struct Mapping<'t> {
-    text: &'t str,
+    text: Text<'t>,
     maps: Vec<Option<(Variable, Range<usize>)>>,
 }
 
@@ -46,28 +235,91 @@ impl<'t> Mapping<'t> {
     }
 
     pub fn iter_groups(&self) -> impl Iterator<Item = (&str, Range<usize>)> {
-        self.maps.iter().filter_map(|x| match x {
-            Some((key, range)) => Some((key.get_name(), range.clone())),
-            None => None,
+        self.maps.iter().filter_map(|x| {
+            x.as_ref()
+                .map(|(key, range)| (key.get_name(), range.clone()))
         })
     }
 
-    pub fn iter_groups_text(&self) -> impl Iterator<Item = (&str, &str)> {
-        self.maps.iter().filter_map(move |x| match x {
-            Some((key, range)) => Some((key.get_name(), &self.text[range.clone()])),
-            None => None,
+    /// Iterate over the text assigned to each group, invalid UTF-8 sequences
+    /// of a text made of raw bytes are replaced with `U+FFFD`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the text is stored in a file that can't be read, which is
+    /// reported by `read_groups_text` instead.
+    pub fn iter_groups_text(&self) -> impl Iterator<Item = (&str, Cow<'t, str>)> {
+        self.iter_groups_bytes()
+            .map(|(name, bytes)| (name, lossy_text(bytes)))
+    }
+
+    /// Iterate over the bytes assigned to each group, which are read from the
+    /// file for a text stored in a file.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the text is stored in a file that can't be read, which is
+    /// reported by `read_groups_bytes` instead.
+    pub fn iter_groups_bytes(&self) -> impl Iterator<Item = (&str, Cow<'t, [u8]>)> {
+        let text = self.text;
+
+        self.maps.iter().filter_map(move |x| {
+            x.as_ref()
+                .map(|(key, range)| (key.get_name(), text.get_bytes(range.clone())))
         })
     }
 
+    /// Read the text assigned to each group, like `iter_groups_text` but an
+    /// error is reported if the text is stored in a file that can't be read.
+    pub fn read_groups_text(&self) -> io::Result<Vec<(&str, Cow<'t, str>)>> {
+        Ok(self
+            .read_groups_bytes()?
+            .into_iter()
+            .map(|(name, bytes)| (name, lossy_text(bytes)))
+            .collect())
+    }
+
+    /// Read the bytes assigned to each group, like `iter_groups_bytes` but an
+    /// error is reported if the text is stored in a file that can't be read.
+    pub fn read_groups_bytes(&self) -> io::Result<Vec<(&str, Cow<'t, [u8]>)>> {
+        self.maps
+            .iter()
+            .flatten()
+            .map(|(key, range)| Ok((key.get_name(), self.text.read_bytes(range.clone())?)))
+            .collect()
+    }
+
+    pub fn get_text(&self) -> Text<'t> {
+        self.text
+    }
+
+    /// Get the pattern the mapping was found by, when several patterns are
+    /// compiled into a single automaton. A mapping that doesn't assign any
+    /// variable can't be told apart.
+    pub fn get_pattern(&self) -> Option<usize> {
+        self.maps
+            .iter()
+            .flatten()
+            .map(|(var, _)| var.get_pattern())
+            .next()
+    }
+
     /// Return a canonical mapping for a classic semantic with no group, which
     /// will assign the whole match to a group called "match".
-    pub fn from_single_match(text: &'t str, range: Range<usize>) -> Mapping<'t> {
+    pub fn from_single_match(text: impl Into<Text<'t>>, range: Range<usize>) -> Mapping<'t> {
         let maps: Vec<Option<(Variable, Range<usize>)>> =
             vec![Some((Variable::new("match".to_string(), 0), range))];
-        Mapping { text, maps }
+        Mapping {
+            text: text.into(),
+            maps,
+        }
     }
 
-    pub fn from_markers<T>(text: &'t str, marker_assigns: T, num_vars: usize) -> Mapping<'t>
+    pub fn from_markers<T>(
+        text: impl Into<Text<'t>>,
+        marker_assigns: T,
+        num_vars: usize,
+    ) -> Mapping<'t>
     where
         T: Iterator<Item = (Marker, usize)>,
     {
@@ -75,7 +327,7 @@ impl<'t> Mapping<'t> {
 
         for (marker, pos) in marker_assigns {
             let span = match &maps[marker.variable().get_id()] {
-                None => std::usize::MAX..std::usize::MAX,
+                None => usize::MAX..usize::MAX,
                 Some((_, x)) => x.clone(),
             };
 
@@ -88,19 +340,25 @@ impl<'t> Mapping<'t> {
             ));
         }
 
-        Mapping { text, maps }
+        Mapping {
+            text: text.into(),
+            maps,
+        }
+    }
+}
+
+/// Read bytes as text, invalid UTF-8 sequences are replaced with `U+FFFD`.
+fn lossy_text(bytes: Cow<[u8]>) -> Cow<str> {
+    match bytes {
+        Cow::Borrowed(bytes) => String::from_utf8_lossy(bytes),
+        Cow::Owned(bytes) => Cow::Owned(String::from_utf8_lossy(&bytes).into_owned()),
     }
 }
 
 impl<'t> fmt::Display for Mapping<'t> {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        for span in self.maps.iter() {
-            match span {
-                Some((var, range)) => {
-                    write!(f, "{}: ({}, {}) ", var, range.start, range.end)?;
-                }
-                None => {}
-            }
+        for (var, range) in self.maps.iter().flatten() {
+            write!(f, "{}: ({}, {}) ", var, range.start, range.end)?;
         }
 
         Ok(())
@@ -108,7 +366,7 @@ impl<'t> fmt::Display for Mapping<'t> {
 }
 
 impl<'t> std::hash::Hash for Mapping<'t> {
-    fn hash<'m, H: Hasher>(&'m self, state: &mut H) {
+    fn hash<H: Hasher>(&self, state: &mut H) {
         for assignment in &self.maps {
             assignment.hash(state);
         }
@@ -122,15 +380,32 @@ impl<'t> std::hash::Hash for Mapping<'t> {
 //    \_/ \__,_|_|  |_|\__,_|_.__/|_|\___|
 //
 
-#[derive(Clone, Debug, PartialOrd, Ord)]
+#[derive(Clone, Debug, PartialOrd, Ord, Serialize, Deserialize)]
 pub struct Variable {
     id: usize,
     name: String,
+    #[serde(default, skip_serializing_if = "is_first_pattern")]
+    pattern: usize,
+}
+
+fn is_first_pattern(pattern: &usize) -> bool {
+    *pattern == 0
 }
 
 impl Variable {
     pub fn new(name: String, id: usize) -> Variable {
-        Variable { id, name }
+        Variable {
+            id,
+            name,
+            pattern: 0,
+        }
+    }
+
+    /// Choose the pattern the variable belongs to, when several patterns are
+    /// compiled into a single automaton.
+    pub fn with_pattern(mut self, pattern: usize) -> Variable {
+        self.pattern = pattern;
+        self
     }
 
     pub fn get_name(&self) -> &str {
@@ -140,6 +415,10 @@ impl Variable {
     pub fn get_id(&self) -> usize {
         self.id
     }
+
+    pub fn get_pattern(&self) -> usize {
+        self.pattern
+    }
 }
 
 impl Hash for Variable {
@@ -169,8 +448,8 @@ impl fmt::Display for Variable {
 //
 #[derive(Clone, Eq, Hash, PartialEq, PartialOrd, Ord)]
 pub enum Marker {
-    Open(Rc<Variable>),
-    Close(Rc<Variable>),
+    Open(Arc<Variable>),
+    Close(Arc<Variable>),
 }
 
 impl Marker {
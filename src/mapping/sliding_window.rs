@@ -0,0 +1,352 @@
+use bit_set::BitSet;
+use std::io;
+use std::io::prelude::*;
+use std::iter;
+use std::str;
+
+use super::super::automaton::Automaton;
+use super::indexed_dag::{IndexedDag, TrimmingStrategy};
+use super::jump::successors;
+use super::{Mapping, SpannerEnumerator, Text};
+
+/// Default number of levels of the DAG whose mappings are enumerated at once.
+const BLOCK_LEN: usize = 1 << 16;
+
+/// Minimum ratio between the default length of a block and the maximum length
+/// of a match, as the text around a block is indexed again with the next ones.
+const BLOCK_RATIO: usize = 4;
+
+/// Size of the chunks read from the input.
+const READ_SIZE: usize = 1 << 16;
+
+//  ____  _ _     _ _              __        ___           _
+// / ___|| (_) __| (_)_ __   __ _  \ \      / (_)_ __   __| | _____      __
+// \___ \| | |/ _` | | '_ \ / _` |  \ \ /\ / /| | '_ \ / _` |/ _ \ \ /\ / /
+//  ___) | | | (_| | | | | | (_| |   \ V  V / | | | | | (_| | (_) \ V  V /
+// |____/|_|_|\__,_|_|_| |_|\__, |    \_/\_/  |_|_| |_|\__,_|\___/ \_/\_/
+//                          |___/
+
+/// Enumerate the mappings of an automaton over a stream of unbounded length,
+/// given a maximum length of matches.
+///
+/// The text is split into blocks, the mappings whose main span ends in a block
+/// are enumerated from an index built over a window that spans the maximum
+/// length of a match on both sides of the block. Runs of the automaton entering
+/// a window are computed from the beginning of the stream, and runs leaving it
+/// must be in a state from which any text is accepted, thus each mapping is
+/// enumerated once the stream has passed its window and the output is the same
+/// as over the whole text. Only the text of the current window is kept.
+///
+/// The length of a match is counted in characters, from the first assignation
+/// of a variable until the last one and until the rest of the text doesn't
+/// matter anymore, see `Automaton::max_match_len`. Longer mappings are missed.
+pub struct SlidingWindow {
+    automaton: Automaton,
+    unicode: bool,
+    max_len: Option<usize>,
+    block_len: Option<usize>,
+}
+
+impl SlidingWindow {
+    /// Read the stream as a sequence of unicode characters, an error is
+    /// reported while reading it if it is not valid UTF-8.
+    pub fn unicode(automaton: Automaton) -> SlidingWindow {
+        SlidingWindow::new(automaton, true)
+    }
+
+    /// Read the stream as a sequence of raw bytes.
+    pub fn bytes(automaton: Automaton) -> SlidingWindow {
+        SlidingWindow::new(automaton, false)
+    }
+
+    fn new(automaton: Automaton, unicode: bool) -> SlidingWindow {
+        SlidingWindow {
+            max_len: automaton.max_match_len(unicode),
+            automaton,
+            unicode,
+            block_len: None,
+        }
+    }
+
+    /// Choose the maximum length of a match, which is required if the length
+    /// of matches of the automaton is not bounded.
+    pub fn with_max_len(mut self, max_len: usize) -> SlidingWindow {
+        self.max_len = Some(max_len);
+        self
+    }
+
+    /// Get the maximum length of a match, which is derived from the automaton
+    /// unless it was given.
+    pub fn get_max_len(&self) -> Option<usize> {
+        self.max_len
+    }
+
+    /// Choose the number of characters of the blocks, the text kept in memory
+    /// is about the size of a block plus twice the maximum length of a match.
+    /// The index of each block is built over all this text, thus blocks much
+    /// shorter than the maximum length of a match index the same text many
+    /// times.
+    pub fn with_block_len(mut self, block_len: usize) -> SlidingWindow {
+        assert!(block_len > 0, "blocks must not be empty");
+        self.block_len = Some(block_len);
+        self
+    }
+
+    /// Get the number of characters of the blocks, unless it was given it is
+    /// derived from the maximum length of a match such that the text around a
+    /// block is shorter than the block.
+    pub fn get_block_len(&self) -> Option<usize> {
+        self.block_len.or_else(|| {
+            self.max_len
+                .map(|max_len| BLOCK_LEN.max(max_len.saturating_mul(BLOCK_RATIO)))
+        })
+    }
+
+    /// Read the whole stream and call the callback for each mapping, together
+    /// with the offset of the text of the mapping in the stream: offsets of
+    /// the mapping are relative to its text, which is only the window it was
+    /// found in.
+    pub fn for_each<R, F>(&self, mut input: R, mut callback: F) -> io::Result<()>
+    where
+        R: Read,
+        F: FnMut(usize, Mapping),
+    {
+        let max_len = self.max_len.ok_or_else(|| {
+            io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "the length of matches is not bounded, a maximum length must be given",
+            )
+        })?;
+
+        let block_len = self.get_block_len().unwrap();
+        let mut window = Window::new(self.automaton.clone(), self.unicode, max_len);
+        let mut chunk = vec![0; READ_SIZE];
+
+        loop {
+            let read = match input.read(&mut chunk) {
+                Ok(0) => break,
+                Ok(read) => read,
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err),
+            };
+
+            window.push(&chunk[..read])?;
+
+            while window.last_level() >= window.block_start + block_len + max_len {
+                let block_end = window.block_start + block_len;
+                window.enumerate(block_end, &mut callback);
+            }
+        }
+
+        window.finish(&mut callback)
+    }
+}
+
+/// Text of the stream that is kept in memory, from the first level of the
+/// window of the next block.
+struct Window {
+    automaton: Automaton,
+    universal: BitSet,
+    unicode: bool,
+    max_len: usize,
+
+    /// Bytes of the text, starting at `offset` in the stream.
+    buffer: Vec<u8>,
+    offset: usize,
+    /// Bytes of an incomplete character at the end of the buffer.
+    carry: Vec<u8>,
+    /// Offsets in the buffer of each level, from `first_level`.
+    levels: Vec<usize>,
+    first_level: usize,
+    /// States of runs that reach `first_level` without assigning any variable.
+    prefix: BitSet,
+    /// Set once the mapping that doesn't assign any variable is enumerated.
+    empty_mapping: bool,
+    /// First level of the next block.
+    block_start: usize,
+}
+
+impl Window {
+    fn new(automaton: Automaton, unicode: bool, max_len: usize) -> Window {
+        let universal = automaton.universal_states(unicode);
+        let prefix = iter::once(automaton.get_initial()).collect();
+
+        Window {
+            automaton,
+            universal,
+            unicode,
+            max_len,
+            buffer: Vec::new(),
+            offset: 0,
+            carry: Vec::new(),
+            levels: vec![0],
+            first_level: 0,
+            prefix,
+            empty_mapping: false,
+            block_start: 0,
+        }
+    }
+
+    fn last_level(&self) -> usize {
+        self.first_level + self.levels.len() - 1
+    }
+
+    /// Add bytes read from the stream, an incomplete character at the end is
+    /// kept until the next bytes are read.
+    fn push(&mut self, bytes: &[u8]) -> io::Result<()> {
+        if !self.unicode {
+            for &byte in bytes {
+                self.buffer.push(byte);
+                self.levels.push(self.buffer.len());
+            }
+
+            return Ok(());
+        }
+
+        self.carry.extend_from_slice(bytes);
+
+        let valid = match str::from_utf8(&self.carry) {
+            Ok(valid) => valid,
+            Err(err) if err.error_len().is_none() => {
+                str::from_utf8(&self.carry[..err.valid_up_to()]).unwrap()
+            }
+            Err(err) => return Err(io::Error::new(io::ErrorKind::InvalidData, err)),
+        };
+
+        for x in valid.chars() {
+            let mut encoded = [0; 4];
+            self.buffer
+                .extend_from_slice(x.encode_utf8(&mut encoded).as_bytes());
+            self.levels.push(self.buffer.len());
+        }
+
+        let len = valid.len();
+        self.carry.drain(..len);
+        Ok(())
+    }
+
+    /// Enumerate the mappings that end in the levels from `block_start` to
+    /// `block_end` excluded, which requires the text to be read until
+    /// `block_end + max_len`, then move to the next block.
+    fn enumerate(&mut self, block_end: usize, callback: &mut impl FnMut(usize, Mapping)) {
+        let window_end = block_end + self.max_len;
+        let finals = self.universal.clone();
+        self.enumerate_window(window_end, block_end, &finals, callback);
+        self.block_start = block_end;
+
+        // Only keep the text required by the window of the next block.
+        let first_level = self.block_start.saturating_sub(self.max_len);
+        self.advance(first_level, callback);
+    }
+
+    /// Enumerate the mappings of the last block, at the end of the stream.
+    fn finish(mut self, callback: &mut impl FnMut(usize, Mapping)) -> io::Result<()> {
+        if !self.carry.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "stream ends with an incomplete UTF-8 sequence",
+            ));
+        }
+
+        let last_level = self.last_level();
+        let finals = self.automaton.finals.clone();
+        self.enumerate_window(last_level, last_level + 1, &finals, callback);
+        self.advance(last_level, callback);
+
+        if !self.empty_mapping && !self.prefix.is_disjoint(&self.automaton.finals) {
+            self.emit_empty_mapping(callback);
+        }
+
+        Ok(())
+    }
+
+    /// Enumerate the mappings of the current block over the window that ends
+    /// at `window_end`, where runs must be in a state of `finals`.
+    fn enumerate_window(
+        &self,
+        window_end: usize,
+        block_end: usize,
+        finals: &BitSet,
+        callback: &mut impl FnMut(usize, Mapping),
+    ) {
+        if self.prefix.is_empty() {
+            return;
+        }
+
+        let pos = |level: usize| self.levels[level - self.first_level] - self.levels[0];
+        let bytes = &self.buffer[self.levels[0]..self.levels[window_end - self.first_level]];
+        let text = match self.unicode {
+            true => Text::Unicode(str::from_utf8(bytes).unwrap()),
+            false => Text::Bytes(bytes),
+        };
+
+        let block_start = pos(self.block_start);
+        let block_end = match block_end > window_end {
+            true => usize::MAX,
+            false => pos(block_end),
+        };
+
+        let automaton = self.automaton.with_boundaries(&self.prefix, finals);
+        let mut index = IndexedDag::new(automaton, text, 1, TrimmingStrategy::FullTrimming, false);
+        // Texts stored in memory can always be read.
+        index.preprocess().unwrap();
+
+        // Mappings are enumerated by decreasing end of their main span.
+        let offset = self.offset + self.levels[0];
+        let mappings = index
+            .iter()
+            .filter(|mapping| mapping.main_span().is_some())
+            .skip_while(|mapping| mapping.main_span().unwrap().end >= block_end)
+            .take_while(|mapping| mapping.main_span().unwrap().end >= block_start);
+
+        for mapping in mappings {
+            callback(offset, mapping);
+        }
+    }
+
+    /// Drop the text before a level, while following the runs that don't
+    /// assign any variable.
+    fn advance(&mut self, level: usize, callback: &mut impl FnMut(usize, Mapping)) {
+        let num_vertices = self.automaton.get_nb_states();
+
+        for i in 0..level - self.first_level {
+            if !self.empty_mapping && !self.prefix.is_disjoint(&self.universal) {
+                self.emit_empty_mapping(callback);
+            }
+
+            let bytes = &self.buffer[self.levels[i]..self.levels[i + 1]];
+            let symbol = match self.unicode {
+                true => str::from_utf8(bytes).unwrap().chars().next().unwrap(),
+                false => char::from(bytes[0]),
+            };
+
+            let adj_for_char = self.automaton.get_adj_for_char(symbol);
+            self.prefix = successors(adj_for_char, &self.prefix, num_vertices);
+        }
+
+        let start = self.levels[level - self.first_level];
+        self.buffer.drain(..start);
+        self.levels.drain(..level - self.first_level);
+
+        for offset in &mut self.levels {
+            *offset -= start;
+        }
+
+        self.offset += start;
+        self.first_level = level;
+    }
+
+    fn emit_empty_mapping(&mut self, callback: &mut impl FnMut(usize, Mapping)) {
+        let num_vars = self.automaton.num_vars();
+        let text = match self.unicode {
+            true => Text::Unicode(""),
+            false => Text::Bytes(&[]),
+        };
+
+        self.empty_mapping = true;
+        callback(
+            self.offset,
+            Mapping::from_markers(text, iter::empty(), num_vars),
+        );
+    }
+}
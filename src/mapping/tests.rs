@@ -1,47 +1,63 @@
-use std::collections::HashSet;
+use std::collections::{HashMap, HashSet};
+use std::io;
+use std::io::prelude::*;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::mpsc;
 
-use super::super::automaton::Automaton;
+use rand::SeedableRng;
+use rand::rngs::StdRng;
+
+use super::super::automaton::{Automaton, validity};
+use super::super::naive::naive;
 use super::super::regex;
-use super::{naive, Mapping};
+use super::indexed_dag::{Cursor, IndexedDag, Order, TrimmingStrategy};
+use super::{Mapping, SlidingWindow, SpannerEnumerator, StringEquality, TextFile};
 
 /// Build a HashSet collecting results of naive algorithm.
-fn naive_results<'t>(regex: &Automaton, text: &'t str) -> HashSet<Mapping<'t>> {
-    naive::NaiveEnum::new(regex, text).collect()
+fn naive_results<'t>(regex: &'t Automaton, text: &'t str) -> HashSet<Mapping<'t>> {
+    naive::NaiveEnum::new(regex, text).iter().collect()
 }
 
 /// Build a HashSet collecting results of default algorithm.
 fn default_results<'t>(regex: &Automaton, text: &'t str) -> HashSet<Mapping<'t>> {
-    regex::compile_matches(regex.clone(), text, 1)
-        .iter()
-        .collect()
+    let mut matches = IndexedDag::new(
+        regex.clone(),
+        text,
+        1,
+        TrimmingStrategy::FullTrimming,
+        false,
+    );
+    matches.preprocess().unwrap();
+    matches.iter().collect()
 }
 
 #[test]
 fn block_a() {
-    let regex = regex::compile(r"^(.*[^a])?(?P<block_a>a+)([^a].*)?$");
+    let regex = regex::compile(r"^(.*[^a])?(?P<block_a>a+)([^a].*)?$").unwrap();
     let texts = ["a", "aaaaaaaaaaaaa", "bbbabb", "aaaabbaaababbbb"];
 
-    for text in texts.into_iter() {
+    for text in texts.iter() {
         assert_eq!(naive_results(&regex, text), default_results(&regex, text));
     }
 }
 
 #[test]
 fn sep_email() {
-    let regex = regex::compile(r"\w+@\w+");
+    let regex = regex::compile(r"\w+@\w+").unwrap();
     let texts = ["a bba a@b b@a aaa@bab abbababaa@@@babbabb"];
 
-    for text in texts.into_iter() {
+    for text in texts.iter() {
         assert_eq!(naive_results(&regex, text), default_results(&regex, text));
     }
 }
 
 #[test]
 fn substrings() {
-    let regex = regex::compile(r".*");
+    let regex = regex::compile(r".*").unwrap();
     let texts = ["abcdefghijklmnopqrstuvwxyz"];
 
-    for text in texts.into_iter() {
+    for text in texts.iter() {
         assert_eq!(naive_results(&regex, text), default_results(&regex, text));
     }
 }
@@ -49,30 +65,1530 @@ fn substrings() {
 #[test]
 fn ordered_blocks() {
     let regex =
-        regex::compile(r"^(.*[^a])?(?P<block_a>a+)([^a].*[^b]|[^ab])?(?P<block_b>b+)([^b].*)?$");
+        regex::compile(r"^(.*[^a])?(?P<block_a>a+)([^a].*[^b]|[^ab])?(?P<block_b>b+)([^b].*)?$")
+            .unwrap();
     let texts = ["ab", "aaaabbbb", "bbbaaababaaaaaabbbbabbbababbababbabb"];
 
-    for text in texts.into_iter() {
+    for text in texts.iter() {
+        assert_eq!(naive_results(&regex, text), default_results(&regex, text));
+    }
+}
+
+#[test]
+fn disconnected() {
+    let regex = regex::compile(r"^(?P<x>a*)b*$").unwrap();
+    let texts = ["", "a", "ba", "abab", "abbb"];
+
+    for text in texts.iter() {
         assert_eq!(naive_results(&regex, text), default_results(&regex, text));
     }
 }
 
 #[test]
 fn mixed_emails() {
-    let regex = regex::compile(r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)");
+    let regex = regex::compile(r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)").unwrap();
     let texts = ["aaaa@aaa.aa", "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a"];
 
-    for text in texts.into_iter() {
+    for text in texts.iter() {
         assert_eq!(naive_results(&regex, text), default_results(&regex, text));
     }
 }
 
 #[test]
 fn some_utf8() {
-    let regex = regex::compile(r"e{3}|ê{3}");
+    let regex = regex::compile(r"e{3}|ê{3}").unwrap();
     let texts = ["êêeeeêê", "êê", "êêêêê", "eêêêeêêêe", "eeeêeee", "eeeêêeee"];
 
-    for text in texts.into_iter() {
+    for text in texts.iter() {
         assert_eq!(naive_results(&regex, text), default_results(&regex, text));
     }
 }
+
+#[test]
+fn persisted_index() {
+    let regex = regex::compile(r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)").unwrap();
+    let texts = ["", "aa@aa", "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a", "êê@ê.ê"];
+
+    for &text in texts.iter() {
+        let mut matches = IndexedDag::new(
+            regex.clone(),
+            text,
+            2,
+            TrimmingStrategy::FullTrimming,
+            false,
+        );
+        matches.preprocess().unwrap();
+
+        let mut buffer = Vec::new();
+        matches.save(&mut buffer).unwrap();
+        let loaded = IndexedDag::load(&mut buffer.as_slice(), text, false).unwrap();
+
+        assert_eq!(
+            matches.iter().collect::<Vec<_>>(),
+            loaded.iter().collect::<Vec<_>>()
+        );
+        assert!(IndexedDag::load(&mut buffer.as_slice(), "other text", false).is_err());
+    }
+}
+
+#[test]
+fn persisted_automaton() {
+    let text = "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a êê@ê.ê";
+    let regex = regex::compile(r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)").unwrap();
+
+    let mut buffer = Vec::new();
+    regex.save(&mut buffer).unwrap();
+    let loaded = Automaton::load(buffer.as_slice()).unwrap();
+    assert_eq!(
+        default_results(&regex, text),
+        default_results(&loaded, text)
+    );
+
+    // Markers may only name their variable.
+    let written = r#"{
+        "nb_states": 4,
+        "finals": [3],
+        "transitions": [
+            [0, {"Class": [["\u0000", "\udbff\udfff"]]}, 0],
+            [0, {"Open": "x"}, 1],
+            [1, {"Class": [["a", "z"]]}, 2],
+            [2, {"Class": [["a", "z"]]}, 2],
+            [2, {"Close": "x"}, 3],
+            [3, {"Class": [["\u0000", "\udbff\udfff"]]}, 3]
+        ]
+    }"#;
+    let written = Automaton::load(written.as_bytes()).unwrap();
+    let text = "ab cd ê";
+    assert_eq!(
+        default_results(&written, text),
+        naive_results(&regex::compile(r"(?P<x>[a-z]+)").unwrap(), text)
+    );
+
+    let invalid = [
+        r#"{"nb_states": 0, "finals": [], "transitions": []}"#,
+        r#"{"nb_states": 2, "finals": [2], "transitions": []}"#,
+        r#"{"nb_states": 2, "finals": [1], "transitions": [[0, {"Open": {"id": 1, "name": "x"}}, 1]]}"#,
+        r#"{"nb_states": 3, "finals": [2], "transitions": [
+            [0, {"Open": {"id": 0, "name": "x"}}, 1],
+            [1, {"Open": {"id": 0, "name": "y"}}, 2]
+        ]}"#,
+    ];
+
+    for automaton in invalid.iter() {
+        let err = Automaton::load(automaton.as_bytes()).unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+    }
+}
+
+#[test]
+fn normalization() {
+    let regexes = [
+        r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)",
+        r"(?P<x>a*)(?P<y>b*)",
+        r"(?P<x>a)?b",
+    ];
+    let text = "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a aabb ab";
+
+    for regex in regexes.iter() {
+        let automaton = regex::compile(regex).unwrap();
+        let normalized = automaton.normalize().unwrap();
+        assert!(normalized.is_normalized());
+        assert_eq!(
+            default_results(&automaton, text),
+            default_results(&normalized, text)
+        );
+
+        // The index normalizes automata whose states are numbered backward.
+        let rename = |state: usize| match state {
+            0 => 0,
+            _ => automaton.nb_states - state,
+        };
+        let backward = Automaton::new(
+            automaton.nb_states,
+            automaton
+                .transitions
+                .iter()
+                .map(|(source, label, target)| (rename(*source), label.clone(), rename(*target))),
+            automaton.finals.iter().map(rename),
+        );
+        assert!(!backward.is_normalized());
+        assert_eq!(
+            default_results(&automaton, text),
+            default_results(&backward, text)
+        );
+    }
+
+    // States are numbered in any order, markers are assigned in any order and
+    // assignations may form cycles, which only allow runs assigning a marker
+    // twice.
+    let written = r#"{
+        "nb_states": 6,
+        "finals": [5],
+        "transitions": [
+            [0, {"Class": [["\u0000", "\udbff\udfff"]]}, 0],
+            [0, {"Open": {"id": 1, "name": "y"}}, 3],
+            [3, {"Open": {"id": 0, "name": "x"}}, 4],
+            [4, {"Close": {"id": 0, "name": "x"}}, 2],
+            [2, {"Open": {"id": 1, "name": "y"}}, 0],
+            [2, {"Literal": "a"}, 1],
+            [1, {"Close": {"id": 1, "name": "y"}}, 5],
+            [5, {"Class": [["\u0000", "\udbff\udfff"]]}, 5]
+        ]
+    }"#;
+    let written = Automaton::load(written.as_bytes()).unwrap();
+    assert!(written.is_normalized());
+
+    let text = "aba";
+    assert_eq!(
+        default_results(&written, text),
+        default_results(&regex::compile(r"(?P<y>(?P<x>)a)").unwrap(), text)
+    );
+
+    // Variables misused across characters are only found along whole runs,
+    // such as a variable closed and opened again.
+    let reopened = r#"{
+        "nb_states": 3,
+        "finals": [2],
+        "transitions": [
+            [0, {"Close": "x"}, 1],
+            [1, {"Literal": "a"}, 1],
+            [1, {"Open": "x"}, 2]
+        ]
+    }"#;
+    let reopened = Automaton::load(reopened.as_bytes()).unwrap();
+    assert!(reopened.check_sequential().is_err());
+    assert!(reopened.normalize().unwrap().check_sequential().is_err());
+    assert!(reopened.sequential().unwrap().check_sequential().is_ok());
+    assert!(default_results(&reopened, "aa").is_empty());
+
+    // Valid runs are kept, along with runs that don't assign the variable.
+    let written = |misused: &str| {
+        let written = format!(
+            r#"{{
+                "nb_states": 5,
+                "finals": [0, 3, 4],
+                "transitions": [
+                    [0, {{"Open": "x"}}, 1],
+                    [1, {{"Literal": "a"}}, 2],
+                    [2, {{"Close": "x"}}, 3],
+                    [3, {{"Literal": "b"}}, 3]{}
+                ]
+            }}"#,
+            misused
+        );
+        Automaton::load(written.as_bytes()).unwrap()
+    };
+    let misused = written(r#", [3, {"Open": "x"}, 4], [4, {"Literal": "a"}, 4]"#);
+    assert!(misused.check_sequential().is_err());
+
+    for text in &["", "a", "ab", "abba", "aba"] {
+        assert_eq!(
+            default_results(&misused, text),
+            default_results(&written(""), text)
+        );
+    }
+}
+
+#[test]
+fn validity() {
+    use validity::Violation;
+
+    let violation = |result: Result<(), _>| {
+        result.map_err(|run: validity::InvalidRun| {
+            (run.variable.get_name().to_string(), run.violation)
+        })
+    };
+
+    let functional = regex::compile(r"(?P<x>a)(?P<y>b)").unwrap();
+    assert!(functional.check_functional().is_ok());
+
+    let optional = regex::compile(r"(?P<x>a)?b").unwrap();
+    assert!(optional.check_sequential().is_ok());
+    assert_eq!(
+        violation(optional.check_functional()),
+        Err(("x".to_string(), Violation::NotAssigned))
+    );
+
+    let several = regex::compile_many(&[r"(?P<x>a)", r"(?P<x>b)"]).unwrap();
+    assert!(several.check_sequential().is_ok());
+    assert!(several.check_functional().is_err());
+
+    let repeated = regex::compile(r"((?P<x>a)b)*").unwrap();
+    let run = repeated.check_sequential().unwrap_err();
+    assert_eq!(run.violation, Violation::OpenedTwice);
+    assert_eq!(run.transitions.first().unwrap().0, 0);
+    assert_eq!(
+        run.transitions
+            .last()
+            .unwrap()
+            .1
+            .get_marker()
+            .unwrap()
+            .to_string(),
+        "⊢x"
+    );
+
+    // States that don't lead to a final state are ignored.
+    let load = |automaton: &str| Automaton::load(automaton.as_bytes()).unwrap();
+    let not_closed = load(
+        r#"{"nb_states": 4, "finals": [2], "transitions": [
+            [0, {"Open": "x"}, 1],
+            [1, {"Literal": "a"}, 2],
+            [0, {"Close": "y"}, 3]
+        ]}"#,
+    );
+    let run = not_closed.check_sequential().unwrap_err();
+    assert_eq!(
+        run.to_string(),
+        "variable x is never closed along the run 0 -⊢x-> 1 -'a'-> 2"
+    );
+
+    let closed_before_opened = load(
+        r#"{"nb_states": 6, "finals": [5], "transitions": [
+            [0, {"Close": "x"}, 1],
+            [1, {"Literal": "a"}, 2],
+            [2, {"Open": "x"}, 3],
+            [3, {"Literal": "b"}, 4],
+            [4, {"Close": "x"}, 5]
+        ]}"#,
+    );
+    assert_eq!(
+        violation(closed_before_opened.check_sequential()),
+        Err(("x".to_string(), Violation::ClosedBeforeOpened))
+    );
+}
+
+#[test]
+fn count_without_enumeration() {
+    let cases = [
+        (r".*", "abcdefghijklmnopqrstuvwxyz"),
+        (r"\w+@\w+", "a bba a@b b@a aaa@bab abbababaa@@@babbabb"),
+        (
+            r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)",
+            "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a",
+        ),
+        (r"e{3}|ê{3}", "eêêêeêêêe"),
+        (r"a+", ""),
+        (r"a+", "bbbb"),
+    ];
+
+    for &(regex, text) in cases.iter() {
+        let automaton = regex::compile(regex).unwrap();
+
+        for &jump_distance in [1, 3].iter() {
+            let mut matches = IndexedDag::new(
+                automaton.clone(),
+                text,
+                jump_distance,
+                TrimmingStrategy::FullTrimming,
+                false,
+            );
+            matches.preprocess().unwrap();
+
+            assert_eq!(matches.count(), matches.iter().count() as u128);
+        }
+    }
+}
+
+#[test]
+fn several_patterns() {
+    let regexes = [
+        r"(?P<action>[Ss]aw|[Ww]atch)",
+        r"(?P<login>\w+)@(?P<server>\w+)",
+        r"\w+@\w+",
+        r"(?P<action>\w)",
+        r"(?P<x>a)?b",
+    ];
+    let text = "a saw b@a aaa@bab watched abb";
+
+    let automaton = regex::compile_many(&regexes).unwrap();
+    assert_eq!(automaton.num_patterns(), regexes.len());
+
+    let mut matches = IndexedDag::new(
+        automaton.clone(),
+        text,
+        1,
+        TrimmingStrategy::FullTrimming,
+        false,
+    );
+    matches.preprocess().unwrap();
+
+    let results: Vec<_> = matches.iter().collect();
+    let counts = matches.count_by_pattern();
+    assert_eq!(counts.values().sum::<u128>(), results.len() as u128);
+
+    // Each mapping is tagged with its pattern, mappings of distinct patterns
+    // with the same spans are not merged.
+    for (pattern, regex) in regexes.iter().enumerate() {
+        let expected: HashSet<_> = default_results(&regex::compile(regex).unwrap(), text)
+            .iter()
+            .map(|mapping| mapping.to_string())
+            .collect();
+
+        let found: HashSet<_> = results
+            .iter()
+            .filter(|mapping| mapping.get_pattern() == Some(pattern))
+            .map(|mapping| mapping.to_string())
+            .collect();
+
+        assert_eq!(
+            found,
+            expected.into_iter().filter(|m| !m.is_empty()).collect()
+        );
+        assert_eq!(counts[&Some(pattern)], found.len() as u128);
+    }
+
+    // The empty mapping of the last pattern doesn't assign any variable.
+    assert_eq!(counts[&None], 1);
+
+    // Patterns are kept when the automaton is persisted.
+    let mut buffer = Vec::new();
+    matches.save(&mut buffer).unwrap();
+    let loaded = IndexedDag::load(&mut buffer.as_slice(), text, false).unwrap();
+    assert_eq!(loaded.num_patterns(), regexes.len());
+    assert_eq!(loaded.count_by_pattern(), counts);
+
+    match regex::compile_many(&["a", "(b"]) {
+        Err(regex::Error::InPattern(1, _)) => {}
+        _ => panic!("the invalid pattern should be reported"),
+    }
+}
+
+#[test]
+fn count_large() {
+    // Every substring of the text is matched: n * (n + 1) / 2 + n + 1 mappings
+    // when counting empty spans at each position.
+    let text = "a".repeat(2000);
+    let automaton = regex::compile(r".*").unwrap();
+    let mut matches = IndexedDag::new(automaton, &text, 1, TrimmingStrategy::FullTrimming, false);
+    matches.preprocess().unwrap();
+
+    let n = text.len() as u128;
+    assert_eq!(matches.count(), n * (n + 1) / 2 + n + 1);
+}
+
+#[test]
+fn sample_distinct() {
+    let regex = regex::compile(r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)").unwrap();
+    let texts = ["", "aa@aa", "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a"];
+    let mut rng = StdRng::seed_from_u64(42);
+
+    for &text in texts.iter() {
+        let mut matches = IndexedDag::new(
+            regex.clone(),
+            text,
+            2,
+            TrimmingStrategy::FullTrimming,
+            false,
+        );
+        matches.preprocess().unwrap();
+
+        let all: Vec<_> = matches.iter().collect();
+        let sampler = matches.random_access().unwrap();
+        assert_eq!(sampler.total(), all.len() as u128);
+
+        // Asking for too many samples returns all mappings in order.
+        assert_eq!(sampler.sample_distinct(u128::MAX, &mut rng), all);
+
+        let samples = sampler.sample_distinct(5, &mut rng);
+        assert_eq!(samples.len(), all.len().min(5));
+        assert_eq!(samples.iter().collect::<HashSet<_>>().len(), samples.len());
+
+        for _ in 0..10 {
+            match sampler.sample(&mut rng) {
+                None => assert!(all.is_empty()),
+                Some(mapping) => assert!(all.contains(&mapping)),
+            }
+        }
+    }
+}
+
+#[test]
+fn sample_uniform() {
+    let regex = regex::compile(r"\w+").unwrap();
+    let text = "abcd efg";
+    let mut matches = IndexedDag::new(regex, text, 1, TrimmingStrategy::FullTrimming, false);
+    matches.preprocess().unwrap();
+
+    let sampler = matches.random_access().unwrap();
+    let mut rng = StdRng::seed_from_u64(0);
+    let mut occurrences = HashMap::new();
+
+    for _ in 0..16000 {
+        *occurrences
+            .entry(sampler.sample(&mut rng).unwrap())
+            .or_insert(0) += 1;
+    }
+
+    // There are 16 mappings, each expected to be drawn 1000 times.
+    assert_eq!(occurrences.len(), 16);
+    assert!(occurrences.values().all(|&n| n > 800 && n < 1200));
+}
+
+#[test]
+fn random_access() {
+    let regex = regex::compile(r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)").unwrap();
+    let texts = ["", "aa@aa", "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a"];
+
+    for &text in texts.iter() {
+        for &jump_distance in [1, 3].iter() {
+            let mut matches = IndexedDag::new(
+                regex.clone(),
+                text,
+                jump_distance,
+                TrimmingStrategy::FullTrimming,
+                false,
+            );
+            matches.preprocess().unwrap();
+
+            let all: Vec<_> = matches.iter().collect();
+            let random_access = matches.random_access().unwrap();
+
+            for index in 0..=all.len() {
+                assert_eq!(random_access.get(index as u128).as_ref(), all.get(index));
+                assert_eq!(
+                    random_access.iter_from(index as u128).collect::<Vec<_>>(),
+                    &all[index..]
+                );
+            }
+        }
+    }
+
+    // Long chains of levels that assign no marker are skipped at once.
+    let regex = regex::compile(r"(?P<l>\w+)@(?P<s>\w+)").unwrap();
+    let texts = [
+        "ab@c d ".repeat(300),
+        "a@b ".repeat(200) + &"cd ".repeat(300) + "e@@f",
+    ];
+
+    for text in texts.iter() {
+        for &jump_distance in [1, 2].iter() {
+            let mut matches = IndexedDag::new(
+                regex.clone(),
+                text.as_str(),
+                jump_distance,
+                TrimmingStrategy::FullTrimming,
+                false,
+            );
+            matches.preprocess().unwrap();
+
+            let all: Vec<_> = matches.iter().collect();
+            let random_access = matches.random_access().unwrap();
+            assert_eq!(random_access.total(), all.len() as u128);
+
+            for index in 0..all.len() {
+                assert_eq!(random_access.get(index as u128).as_ref(), all.get(index));
+            }
+
+            for index in (0..all.len()).step_by(97) {
+                assert_eq!(
+                    random_access.iter_from(index as u128).collect::<Vec<_>>(),
+                    &all[index..]
+                );
+            }
+        }
+    }
+}
+
+#[test]
+fn resume_from_cursor() {
+    let regex = regex::compile(r"\w+@\w+").unwrap();
+    let text = "a bba a@b b@a aaa@bab abbababaa@@@babbabb";
+    let mut matches = IndexedDag::new(
+        regex.clone(),
+        text,
+        1,
+        TrimmingStrategy::FullTrimming,
+        false,
+    );
+    matches.preprocess().unwrap();
+
+    let all: Vec<_> = matches.iter().collect();
+    let random_access = matches.random_access().unwrap();
+
+    // Read pages of 7 mappings, serializing the cursor between each page.
+    let mut pages = Vec::new();
+    let mut token = random_access.iter_from(0).cursor().to_string();
+
+    loop {
+        let cursor: Cursor = token.parse().unwrap();
+        let json = serde_json::to_string(&cursor).unwrap();
+        let cursor: Cursor = serde_json::from_str(&json).unwrap();
+
+        let mut page_iter = random_access.resume(&cursor).unwrap();
+        let page: Vec<_> = page_iter.by_ref().take(7).collect();
+
+        if page.is_empty() {
+            break;
+        }
+
+        pages.extend(page);
+        token = page_iter.cursor().to_string();
+    }
+
+    assert_eq!(pages, all);
+
+    // A cursor can't be used over another index.
+    let mut other = IndexedDag::new(regex, "a@b", 1, TrimmingStrategy::FullTrimming, false);
+    other.preprocess().unwrap();
+    let cursor = random_access.iter_from(3).cursor();
+    assert!(other.random_access().unwrap().resume(&cursor).is_err());
+    assert!("not a cursor".parse::<Cursor>().is_err());
+}
+
+#[test]
+fn enumeration_order() {
+    let cases = [
+        (r"\w+@\w+", "a bba a@b b@a aaa@bab abbababaa@@@babbabb"),
+        (
+            r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)",
+            "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a",
+        ),
+        (r".*", "abcdêfgh"),
+        (r"a*", "baab"),
+        (r"^(.*[^a])?(?P<block_a>a+)([^a].*)?$", "aaaabbaaababbbb"),
+        (
+            r"(?m)\b(?P<key>\w+)\b: (?P<value>.*)$",
+            "a: b c\nfoo: bar\n:x: y",
+        ),
+    ];
+
+    for &(regex, text) in cases.iter() {
+        let automaton = regex::compile(regex).unwrap();
+        let expected = naive_results(&automaton, text);
+
+        for &order in [Order::Backward, Order::Document].iter() {
+            let mut matches = IndexedDag::new(
+                automaton.clone(),
+                text,
+                2,
+                TrimmingStrategy::FullTrimming,
+                false,
+            )
+            .with_order(order);
+            matches.preprocess().unwrap();
+
+            let results: Vec<_> = matches.iter().collect();
+            let spans: Vec<_> = results.iter().map(|m| m.main_span().unwrap()).collect();
+
+            assert_eq!(results.iter().cloned().collect::<HashSet<_>>(), expected);
+            assert_eq!(results.len(), expected.len());
+            assert_eq!(matches.count(), expected.len() as u128);
+
+            match order {
+                Order::Backward => assert!(spans.windows(2).all(|w| w[0].end >= w[1].end)),
+                Order::Document => assert!(spans.windows(2).all(|w| w[0].start <= w[1].start)),
+            }
+
+            // Other structures built over the index follow the same order.
+            let random_access = matches.random_access().unwrap();
+            assert_eq!(random_access.iter_from(0).collect::<Vec<_>>(), results);
+
+            let mut buffer = Vec::new();
+            matches.save(&mut buffer).unwrap();
+            let loaded = IndexedDag::load(&mut buffer.as_slice(), text, false).unwrap();
+            assert!(loaded.get_order() == order);
+            assert_eq!(loaded.iter().collect::<Vec<_>>(), results);
+        }
+    }
+}
+
+#[test]
+fn parallel_preprocessing() {
+    let cases = [
+        (r"\w+@\w+", "a bba a@b b@a aaa@bab abbababaa@@@babbabb"),
+        (
+            r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)",
+            "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a",
+        ),
+        (r"e{3}|ê{3}", "eêêêeêêêe"),
+        (r"^(.*[^a])?(?P<block_a>a+)([^a].*)?$", "aaaabbaaababbbb"),
+        (r"a+", ""),
+        (r"a+b", "aaaaaaaaaaaa"),
+    ];
+
+    let strategies = [
+        TrimmingStrategy::PartialTrimming,
+        TrimmingStrategy::FullTrimming,
+    ];
+
+    for &(regex, text) in cases.iter() {
+        let automaton = regex::compile(regex).unwrap();
+
+        for &order in [Order::Backward, Order::Document].iter() {
+            for &strategy in strategies.iter() {
+                // The index itself doesn't depend on the number of threads.
+                let run = |threads| {
+                    let mut matches = IndexedDag::new(automaton.clone(), text, 2, strategy, false)
+                        .with_order(order)
+                        .with_threads(threads);
+                    matches.preprocess().unwrap();
+                    let mut buffer = Vec::new();
+                    matches.save(&mut buffer).unwrap();
+                    (matches.iter().collect::<Vec<_>>(), buffer)
+                };
+
+                let expected = run(1);
+
+                for &threads in [2, 3, 7, 64].iter() {
+                    assert_eq!(run(threads), expected);
+                }
+            }
+        }
+    }
+
+    // Raw bytes are split anywhere, including inside UTF-8 sequences.
+    let automaton = regex::compile_bytes(r"(?P<key>\w+)=(?P<value>[^;]*)").unwrap();
+    let text: &[u8] = b"a=\x01\x02;b=\xFF;\xC3\xAA=\xC3\xA9";
+    let run = |threads| {
+        let mut matches = IndexedDag::new(
+            automaton.clone(),
+            text,
+            1,
+            TrimmingStrategy::FullTrimming,
+            false,
+        )
+        .with_threads(threads);
+        matches.preprocess().unwrap();
+        matches.iter().collect::<Vec<_>>()
+    };
+
+    let expected = run(1);
+    assert!(!expected.is_empty());
+
+    for threads in 2..8 {
+        assert_eq!(run(threads), expected);
+    }
+}
+
+#[test]
+fn parallel_enumeration() {
+    let cases = [
+        (r".*", "abcdefghijklmnopqrstuvwxyz"),
+        (r"\w+@\w+", "a bba a@b b@a aaa@bab abbababaa@@@babbabb"),
+        (
+            r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)",
+            "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a",
+        ),
+        (r"e{3}|ê{3}", "eêêêeêêêe"),
+        (r"a+", ""),
+        (r"a+", "bbbb"),
+    ];
+
+    for &(regex, text) in cases.iter() {
+        let automaton = regex::compile(regex).unwrap();
+
+        for &order in [Order::Backward, Order::Document].iter() {
+            let mut matches = IndexedDag::new(
+                automaton.clone(),
+                text,
+                2,
+                TrimmingStrategy::FullTrimming,
+                false,
+            )
+            .with_order(order);
+            matches.preprocess().unwrap();
+            let expected: HashSet<_> = matches.iter().collect();
+
+            for &threads in [1, 2, 5].iter() {
+                let (sender, receiver) = mpsc::channel();
+
+                matches.for_each_parallel(threads, |worker, mapping| {
+                    assert!(worker < threads);
+                    sender.send(mapping).unwrap();
+                });
+
+                drop(sender);
+                let results: Vec<_> = receiver.iter().collect();
+                assert_eq!(results.len(), expected.len());
+                assert_eq!(results.into_iter().collect::<HashSet<_>>(), expected);
+            }
+        }
+    }
+
+    // Enough mappings for the work to be shared between workers.
+    let text = "a".repeat(300);
+    let automaton = regex::compile(r".*").unwrap();
+    let mut matches = IndexedDag::new(automaton, &text, 1, TrimmingStrategy::FullTrimming, false);
+    matches.preprocess().unwrap();
+
+    let per_worker: Vec<_> = (0..4).map(|_| AtomicUsize::new(0)).collect();
+    matches.for_each_parallel(4, |worker, _| {
+        per_worker[worker].fetch_add(1, Ordering::Relaxed);
+    });
+
+    let total: usize = per_worker.iter().map(|n| n.load(Ordering::Relaxed)).sum();
+    assert_eq!(total as u128, matches.count());
+}
+
+#[test]
+fn text_file() {
+    let cases = [
+        (
+            r"\w+@\w+",
+            "a bba a@b b@a aaa@bab abbababaa@@@babbabb".to_string(),
+        ),
+        (
+            r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)",
+            "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a".to_string(),
+        ),
+        (r"e{3}|ê{3}", "eêêêeêêêe".to_string()),
+        (r"a+", "".to_string()),
+        (r"a+b", "aaaaaaaaaaaa".to_string()),
+        // Characters overlap with the blocks the file is read by.
+        (r"(?P<x>êê)e", "ê".repeat(40_000) + "e"),
+    ];
+
+    let strategies = [
+        TrimmingStrategy::PartialTrimming,
+        TrimmingStrategy::FullTrimming,
+    ];
+
+    for (regex, text) in cases.iter() {
+        let automaton = regex::compile(regex).unwrap();
+        let file = TextFile::unicode(io::Cursor::new(text.clone().into_bytes())).unwrap();
+
+        for &order in [Order::Backward, Order::Document].iter() {
+            for &strategy in strategies.iter() {
+                let mut expected =
+                    IndexedDag::new(automaton.clone(), text, 2, strategy, false).with_order(order);
+                expected.preprocess().unwrap();
+
+                let mut matches =
+                    IndexedDag::new(automaton.clone(), &file, 2, strategy, false).with_order(order);
+                matches.preprocess().unwrap();
+
+                let spans = |matches: &IndexedDag| -> Vec<String> {
+                    matches.iter().map(|mapping| mapping.to_string()).collect()
+                };
+
+                assert_eq!(spans(&matches), spans(&expected));
+                assert_eq!(matches.count(), expected.count());
+
+                if text.len() < 100 {
+                    let texts = |matches: &IndexedDag| -> Vec<Vec<(String, String)>> {
+                        matches
+                            .iter()
+                            .map(|mapping| {
+                                mapping
+                                    .iter_groups_text()
+                                    .map(|(name, text)| (name.to_string(), text.into_owned()))
+                                    .collect()
+                            })
+                            .collect()
+                    };
+
+                    assert_eq!(texts(&matches), texts(&expected));
+                }
+            }
+        }
+    }
+
+    // Invalid UTF-8 is reported while preprocessing.
+    let automaton = regex::compile(r"a").unwrap();
+    let file = TextFile::unicode(io::Cursor::new(b"a\xFFa".to_vec())).unwrap();
+    let mut matches = IndexedDag::new(automaton, &file, 1, TrimmingStrategy::FullTrimming, false);
+    assert!(matches.preprocess().is_err());
+
+    // Raw bytes are read in both directions.
+    let automaton = regex::compile_bytes(r"(?P<key>\w+)=(?P<value>[^;]*)").unwrap();
+    let text: &[u8] = b"a=\x01\x02;b=\xFF;\xC3\xAA=\xC3\xA9";
+    let file = TextFile::bytes(io::Cursor::new(text.to_vec())).unwrap();
+
+    for &order in [Order::Backward, Order::Document].iter() {
+        let strategy = TrimmingStrategy::FullTrimming;
+        let mut expected =
+            IndexedDag::new(automaton.clone(), text, 1, strategy, false).with_order(order);
+        expected.preprocess().unwrap();
+
+        let mut matches =
+            IndexedDag::new(automaton.clone(), &file, 1, strategy, false).with_order(order);
+        matches.preprocess().unwrap();
+
+        let spans = |matches: &IndexedDag| -> Vec<Vec<(String, Vec<u8>)>> {
+            matches
+                .iter()
+                .map(|mapping| {
+                    mapping
+                        .iter_groups_bytes()
+                        .map(|(name, bytes)| (name.to_string(), bytes.into_owned()))
+                        .collect()
+                })
+                .collect()
+        };
+
+        assert!(!spans(&expected).is_empty());
+        assert_eq!(spans(&matches), spans(&expected));
+    }
+
+    // Errors while reading the text of a mapping back are reported.
+    let automaton = regex::compile(r"(?P<login>\w+)@(?P<server>\w+)").unwrap();
+    let failing = Arc::new(AtomicBool::new(false));
+    let source = FailingSource {
+        inner: io::Cursor::new(b"a@b c@d".to_vec()),
+        failing: failing.clone(),
+    };
+    let file = TextFile::unicode(source).unwrap();
+    let mut matches = IndexedDag::new(automaton, &file, 1, TrimmingStrategy::FullTrimming, false);
+    matches.preprocess().unwrap();
+    let mapping = matches.iter().next().unwrap();
+    assert_eq!(mapping.read_groups_text().unwrap().len(), 2);
+
+    failing.store(true, Ordering::SeqCst);
+    assert!(mapping.read_groups_text().is_err());
+    assert!(mapping.read_groups_bytes().is_err());
+    assert!(mapping.get_text().read_bytes(0..1).is_err());
+}
+
+/// A source that can't be read anymore once `failing` is set.
+struct FailingSource {
+    inner: io::Cursor<Vec<u8>>,
+    failing: Arc<AtomicBool>,
+}
+
+impl Read for FailingSource {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.failing.load(Ordering::SeqCst) {
+            return Err(io::Error::other("the source was removed"));
+        }
+
+        self.inner.read(buf)
+    }
+}
+
+impl Seek for FailingSource {
+    fn seek(&mut self, pos: io::SeekFrom) -> io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+#[test]
+fn append() {
+    let cases = [
+        (r"^(.*[^a])?(?P<block_a>a+)([^a].*)?$", "aaaabbaaababbbb"),
+        (r"\w+@\w+", "a bba a@b b@a aaa@bab abbababaa@@@babbabb"),
+        (
+            r"(?P<login>\w+(\.\w+)*)@(?P<server>\w+\.\w+)",
+            "aa@aa a@a.a@a.a.a@a.a.a.a@a.a.a.a.a",
+        ),
+        (r"(?P<x>\w+\b)", "êa bê ab"),
+        (r"(?m)^(?P<line>.*)$", "first\nsecond line\n\nlast"),
+    ];
+
+    let strategies = [
+        TrimmingStrategy::NoTrimming,
+        TrimmingStrategy::PartialTrimming,
+    ];
+
+    for (regex, text) in cases.iter() {
+        let automaton = regex::compile(regex).unwrap();
+        let cuts: Vec<_> = (0..=text.len())
+            .filter(|&i| text.is_char_boundary(i))
+            .collect();
+
+        for &jump_distance in [1, 3].iter() {
+            for &strategy in strategies.iter() {
+                let mut expected =
+                    IndexedDag::new(automaton.clone(), *text, jump_distance, strategy, false);
+                expected.preprocess().unwrap();
+                let expected: HashSet<_> = expected.iter().collect();
+
+                for &cut in cuts.iter() {
+                    let mut matches = IndexedDag::new(
+                        automaton.clone(),
+                        &text[..cut],
+                        jump_distance,
+                        strategy,
+                        false,
+                    );
+                    matches.preprocess().unwrap();
+
+                    // Append the remaining text in two steps.
+                    let middle = cuts[(cuts.len() + cuts.binary_search(&cut).unwrap()) / 2];
+                    matches.append(&text[..middle]).unwrap();
+                    let before: Vec<_> = matches.iter().collect();
+                    matches.append(*text).unwrap();
+
+                    let results: Vec<_> = matches.iter().collect();
+                    assert_eq!(results.len(), expected.len());
+                    assert_eq!(results.iter().cloned().collect::<HashSet<_>>(), expected);
+
+                    // Without assertions on the following text, mappings that
+                    // end before the appended text are left unchanged.
+                    let ending_before: Vec<_> = results
+                        .iter()
+                        .filter(|mapping| mapping.main_span().unwrap().end <= middle)
+                        .map(|mapping| mapping.to_string())
+                        .collect();
+
+                    if !regex.contains('$') && !regex.contains(r"\b") {
+                        let before: Vec<_> = before.iter().map(|m| m.to_string()).collect();
+                        assert_eq!(ending_before, before);
+                    }
+
+                    let appended: Vec<_> = matches.iter_ending_after(middle).collect();
+                    assert_eq!(appended.len() + ending_before.len(), results.len());
+                    assert_eq!(appended[..], results[..appended.len()]);
+                }
+            }
+        }
+    }
+
+    // The last level of a fully trimmed index can't be extended.
+    let automaton = regex::compile(r"a+").unwrap();
+    let text = "aaa";
+    let mut matches = IndexedDag::new(
+        automaton.clone(),
+        &text[..1],
+        1,
+        TrimmingStrategy::FullTrimming,
+        false,
+    );
+    matches.preprocess().unwrap();
+    assert!(matches.append(text).is_err());
+
+    let mut matches = IndexedDag::new(
+        automaton,
+        &text[..1],
+        1,
+        TrimmingStrategy::PartialTrimming,
+        false,
+    )
+    .with_order(Order::Document);
+    matches.preprocess().unwrap();
+    assert!(matches.append(text).is_err());
+    assert!(matches.append(&text[..0]).is_err());
+}
+
+#[test]
+fn update() {
+    // Long texts are split into several segments of the index.
+    let long_block = "aab a@b ba\nbêa ".repeat(160);
+    let long_lines = "first\nsecond line\n\nlast".repeat(100);
+    let cases = [
+        (r"^(.*[^a])?(?P<block_a>a+)([^a].*)?$", "aaaabbaaababbbb"),
+        (r"\w+@\w+", "a bba a@b b@a aaa@bab abbababaa@@@babbabb"),
+        (r"(?P<x>\w+\b)", "êa bê ab"),
+        (r"(?m)^(?P<line>.*)$", "first\nsecond line\n\nlast"),
+        (r"\w+@\w+", long_block.as_str()),
+        (r"(?m)^(?P<line>.*)$", long_lines.as_str()),
+    ];
+
+    // Edits are given by a range of characters and the text inserted there,
+    // some of them crossing boundaries of segments in long texts.
+    let long_insert = "a@ba\n".repeat(250);
+    let edits = [
+        (3, 3, "a@b "),
+        (0, 2, ""),
+        (5, 9, "ê\nba"),
+        (1020, 1030, "@"),
+        (1000, 2100, "a\n@"),
+        (1500, 1500, long_insert.as_str()),
+        (0, 0, "aa"),
+        (10000, 10000, "@a"),
+        (2, 10000, "b"),
+        (0, 10000, ""),
+        (0, 0, "a@a"),
+    ];
+
+    let strategies = [
+        TrimmingStrategy::NoTrimming,
+        TrimmingStrategy::PartialTrimming,
+        TrimmingStrategy::FullTrimming,
+    ];
+
+    for (regex, text) in cases.iter() {
+        let automaton = regex::compile(regex).unwrap();
+        let mut versions = vec![text.to_string()];
+        let mut ranges = Vec::new();
+
+        for &(start, end, inserted) in edits.iter() {
+            let prev = versions.last().unwrap();
+            let offset = |i| {
+                prev.char_indices()
+                    .nth(i)
+                    .map_or(prev.len(), |(pos, _)| pos)
+            };
+            let range = offset(start)..offset(end);
+            let mut next = prev.clone();
+            next.replace_range(range.clone(), inserted);
+            versions.push(next);
+            ranges.push(range);
+        }
+
+        for &jump_distance in [1, 3].iter() {
+            for &strategy in strategies.iter() {
+                // The index is built in parallel for some of the cases.
+                let mut matches = IndexedDag::new(
+                    automaton.clone(),
+                    versions[0].as_str(),
+                    jump_distance,
+                    strategy,
+                    false,
+                )
+                .with_threads(jump_distance);
+                matches.preprocess().unwrap();
+
+                for (version, range) in versions[1..].iter().zip(ranges.iter()) {
+                    matches.update(version.as_str(), range.clone()).unwrap();
+
+                    let mut expected = IndexedDag::new(
+                        automaton.clone(),
+                        version.as_str(),
+                        jump_distance,
+                        strategy,
+                        false,
+                    );
+                    expected.preprocess().unwrap();
+                    let expected: HashSet<_> = expected.iter().collect();
+
+                    let results: Vec<_> = matches.iter().collect();
+                    assert_eq!(results.len(), expected.len());
+                    assert_eq!(results.into_iter().collect::<HashSet<_>>(), expected);
+                }
+            }
+        }
+    }
+
+    let automaton = regex::compile(r"a+").unwrap();
+    let mut matches = IndexedDag::new(automaton, "aaa", 1, TrimmingStrategy::NoTrimming, false)
+        .with_order(Order::Document);
+    matches.preprocess().unwrap();
+    assert!(matches.update("aba", 1..2).is_err());
+    assert!(matches.update("aaa", 2..4).is_err());
+}
+
+#[test]
+fn sliding_window() {
+    let cases = [
+        (r"(?P<x>a)(?P<y>b{0,3})", "abbbbaab abab abbb"),
+        (
+            r"\b(?P<word>\w{1,4})\b",
+            "a bba êêê a@b b@a aaa@babbb abbababaa",
+        ),
+        (r"^(?P<first>.{0,3})", "abcdef"),
+        (r"(?P<last>.{0,3})$", "abcdef"),
+        (r"(?m)^(?P<line>.{0,5})$", "first\nline2\n\nlast\n"),
+        (r"(?P<x>a)?b", "abbab"),
+        (r"(?P<x>a)|(?P<y>b)?", "abc"),
+        (r".{0,2}", ""),
+    ];
+
+    let spans = |offset: usize, mapping: &Mapping| -> Vec<(String, usize, usize)> {
+        mapping
+            .iter_groups()
+            .map(|(name, range)| (name.to_string(), range.start + offset, range.end + offset))
+            .collect()
+    };
+
+    for (regex, text) in cases.iter() {
+        let automaton = regex::compile(regex).unwrap();
+        let mut expected = IndexedDag::new(
+            automaton.clone(),
+            *text,
+            1,
+            TrimmingStrategy::FullTrimming,
+            false,
+        );
+        expected.preprocess().unwrap();
+        let mut expected: Vec<_> = expected.iter().map(|mapping| spans(0, &mapping)).collect();
+        expected.sort();
+
+        for &block_len in [1, 2, 5, 100].iter() {
+            let window = SlidingWindow::unicode(automaton.clone()).with_block_len(block_len);
+            assert!(window.get_max_len().is_some());
+
+            let mut results = Vec::new();
+            window
+                .for_each(text.as_bytes(), |offset, mapping| {
+                    results.push(spans(offset, &mapping))
+                })
+                .unwrap();
+
+            results.sort();
+            assert_eq!(results, expected);
+        }
+    }
+
+    // The length of matches must be given if it is not bounded.
+    let text = "a bba a@b b@a aaa@bab abbababaa@@@babbabb";
+    let automaton = regex::compile(r"\w+@\w+").unwrap();
+    let window = SlidingWindow::unicode(automaton.clone());
+    assert_eq!(window.get_max_len(), None);
+    assert!(window.for_each(text.as_bytes(), |_, _| ()).is_err());
+
+    // Blocks are longer than the text indexed again around them by default.
+    assert_eq!(window.get_block_len(), None);
+    let window = window.with_max_len(1 << 20);
+    assert_eq!(window.get_block_len(), Some(4 << 20));
+    assert_eq!(window.with_block_len(3).get_block_len(), Some(3));
+    let window = SlidingWindow::unicode(regex::compile(r"\w{1,4}").unwrap());
+    assert_eq!(window.get_block_len(), Some(1 << 16));
+
+    let mut expected = IndexedDag::new(
+        automaton.clone(),
+        text,
+        1,
+        TrimmingStrategy::FullTrimming,
+        false,
+    );
+    expected.preprocess().unwrap();
+    let mut expected: Vec<_> = expected.iter().map(|mapping| spans(0, &mapping)).collect();
+    expected.sort();
+
+    let mut results = Vec::new();
+    SlidingWindow::unicode(automaton)
+        .with_max_len(20)
+        .with_block_len(3)
+        .for_each(text.as_bytes(), |offset, mapping| {
+            results.push(spans(offset, &mapping))
+        })
+        .unwrap();
+
+    results.sort();
+    assert_eq!(results, expected);
+
+    // Raw bytes, where characters are split between chunks.
+    let automaton = regex::compile_bytes(r"(?P<key>\w{1,3})=(?P<value>[^;]{0,3})").unwrap();
+    let text: &[u8] = b"a=\x01\x02;b=\xFF;\xC3\xAA=\xC3\xA9;abcd=efgh";
+    let mut expected = IndexedDag::new(
+        automaton.clone(),
+        text,
+        1,
+        TrimmingStrategy::FullTrimming,
+        false,
+    );
+    expected.preprocess().unwrap();
+    let mut expected: Vec<_> = expected.iter().map(|mapping| spans(0, &mapping)).collect();
+    expected.sort();
+
+    let mut results = Vec::new();
+    SlidingWindow::bytes(automaton)
+        .with_block_len(2)
+        .for_each(text, |offset, mapping| {
+            results.push(spans(offset, &mapping))
+        })
+        .unwrap();
+
+    results.sort();
+    assert!(!expected.is_empty());
+    assert_eq!(results, expected);
+}
+
+#[test]
+fn spanner_algebra() {
+    use super::super::automaton::algebra::{Error, Expr};
+
+    /// Spans of the groups of each mapping, sorted by the names of groups.
+    fn named_spans(automaton: &Automaton, text: &str) -> HashSet<Vec<(String, (usize, usize))>> {
+        let results = default_results(automaton, text);
+        assert_eq!(results, naive_results(automaton, text));
+
+        results
+            .iter()
+            .map(|mapping| {
+                let mut groups: Vec<_> = mapping
+                    .iter_groups()
+                    .map(|(name, span)| (name.to_string(), (span.start, span.end)))
+                    .collect();
+                groups.sort();
+                groups
+            })
+            .collect()
+    }
+
+    let compile = |regex| regex::compile(regex).unwrap();
+    let text = "a@bc ab@cb a@b abc";
+
+    let union = compile(r"(?P<x>a+)").union(&compile(r"(?P<x>b+)")).unwrap();
+    assert_eq!(
+        named_spans(&union, text),
+        named_spans(&compile(r"(?P<x>a+|b+)"), text)
+    );
+
+    let projection = compile(r"(?P<x>\w+)@(?P<y>\w+)").project(&["y"]).unwrap();
+    assert_eq!(projection.get_variables().len(), 1);
+    assert_eq!(
+        named_spans(&projection, text),
+        named_spans(&compile(r"\w+@(?P<y>\w+)"), text)
+    );
+
+    let join = compile(r"(?P<x>\w+)@(?P<y>\w+)")
+        .join(&compile(r"(?P<y>b\w*)"))
+        .unwrap();
+    assert_eq!(
+        named_spans(&join, text),
+        named_spans(&compile(r"(?P<x>\w+)@(?P<y>b\w*)"), text)
+    );
+
+    // Shared variables may be assigned in distinct orders by both spanners.
+    let join = compile(r"(?P<x>a)(?P<y>b)")
+        .join(&compile(r"(?P<y>b)(?P<z>c)"))
+        .unwrap();
+    assert_eq!(
+        named_spans(&join, text),
+        named_spans(&compile(r"(?P<x>a)(?P<y>b)(?P<z>c)"), text)
+    );
+
+    let join = compile(r"(?P<x>(?P<y>a)\w)")
+        .join(&compile(r"(?P<y>a)"))
+        .unwrap();
+    assert_eq!(
+        named_spans(&join, text),
+        named_spans(&compile(r"(?P<x>(?P<y>a)\w)"), text)
+    );
+
+    // Spanners without shared variables are joined into their product.
+    let join = compile(r"(?P<x>a)").join(&compile(r"(?P<y>b)")).unwrap();
+    assert_eq!(named_spans(&join, "aab").len(), 2);
+
+    let expr: Expr = "project(join(0, union(1, 2)), y)".parse().unwrap();
+    assert_eq!(
+        expr,
+        Expr::Projection(
+            Box::new(Expr::Join(vec![
+                Expr::Spanner(0),
+                Expr::Union(vec![Expr::Spanner(1), Expr::Spanner(2)])
+            ])),
+            vec!["y".to_string()]
+        )
+    );
+
+    let spanners = [
+        compile(r"(?P<x>\w+)@(?P<y>\w+)"),
+        compile(r"(?P<y>a\w*)"),
+        compile(r"(?P<y>\w*c)"),
+    ];
+    assert_eq!(
+        named_spans(&expr.eval(&spanners).unwrap(), text),
+        named_spans(&compile(r"\w+@(?P<y>a\w*|\w*c)"), text)
+    );
+
+    let eval = |expr: &str| expr.parse::<Expr>().and_then(|expr| expr.eval(&spanners));
+    assert!(matches!(
+        eval("union(0, 1)"),
+        Err(Error::DistinctVariables(..))
+    ));
+    assert!(matches!(eval("project(1, x)"), Err(Error::UnknownVariable(name)) if name == "x"));
+    assert!(matches!(eval("join(0, 3)"), Err(Error::UnknownSpanner(3))));
+    assert!(matches!(
+        eval("join(0, 1"),
+        Err(Error::Syntax { position: 9, .. })
+    ));
+    assert!(matches!(
+        eval("union(0, meet(1))"),
+        Err(Error::Syntax { position: 9, .. })
+    ));
+}
+
+#[test]
+fn selection() {
+    let text = "user@host.com a@b.c user@mail.host.com";
+    let automaton = regex::compile(r"(?P<login>\w+)@(?P<host>\w+)(?P<domain>(\.\w+)+)").unwrap();
+    let selected = automaton.project(&["login"]).unwrap();
+
+    let mut matches = IndexedDag::new(selected, text, 1, TrimmingStrategy::FullTrimming, false);
+    matches.preprocess().unwrap();
+    let results: Vec<_> = matches.iter().map(|mapping| mapping.to_string()).collect();
+    assert_eq!(matches.count(), results.len() as u128);
+
+    // Each projected mapping is enumerated once.
+    let distinct: HashSet<_> = results.iter().cloned().collect();
+    assert_eq!(distinct.len(), results.len());
+
+    let expected: HashSet<_> = default_results(&automaton, text)
+        .iter()
+        .map(|mapping| {
+            mapping
+                .iter_groups()
+                .filter(|(name, _)| *name == "login")
+                .map(|(name, span)| format!("{}: ({}, {}) ", name, span.start, span.end))
+                .collect::<String>()
+        })
+        .collect();
+    assert_eq!(distinct, expected);
+    assert!(expected.len() < default_results(&automaton, text).len());
+
+    // Patterns of the mappings are kept.
+    let automaton = regex::compile_many(&[r"(?P<x>a)(?P<y>b)", r"(?P<x>\w)"]).unwrap();
+    let selected = automaton.project(&["x"]).unwrap();
+    assert_eq!(selected.num_patterns(), 2);
+    let mut matches = IndexedDag::new(selected, "ab", 1, TrimmingStrategy::FullTrimming, false);
+    matches.preprocess().unwrap();
+    assert_eq!(
+        matches.count_by_pattern().into_iter().collect::<Vec<_>>(),
+        [(Some(0), 1), (Some(1), 2)]
+    );
+}
+
+#[test]
+fn string_equality() {
+    let text = "<a>x</a> <b>y</c> <ab>z</ab> <abc>t</abd>";
+    let automaton = regex::compile(r"<(?P<open>\w+)>[^<]*</(?P<close>\w+)>").unwrap();
+    let equality = StringEquality::new().with_group(["open", "close"]);
+
+    let mut matches = IndexedDag::new(automaton, text, 1, TrimmingStrategy::FullTrimming, false);
+    matches.preprocess().unwrap();
+
+    let spans = |mapping: &Mapping| -> Vec<_> {
+        mapping
+            .iter_groups()
+            .map(|(_, span)| (span.start, span.end))
+            .collect()
+    };
+
+    let mut results: Vec<_> = equality.filter(matches.iter()).map(|m| spans(&m)).collect();
+    results.sort();
+    assert_eq!(results, [vec![(1, 2), (6, 7)], vec![(19, 21), (25, 27)]]);
+
+    // Same result as comparing the text of each mapping.
+    let mut expected: Vec<_> = matches
+        .iter()
+        .filter(|mapping| {
+            let texts: Vec<_> = mapping.iter_groups_text().map(|(_, text)| text).collect();
+            texts[0] == texts[1]
+        })
+        .map(|m| spans(&m))
+        .collect();
+    expected.sort();
+    assert_eq!(results, expected);
+
+    // Several groups of variables, which are ignored when not assigned.
+    let automaton = regex::compile(r"(?P<x>\w+) (?P<y>\w+)(?: (?P<z>\w+))?").unwrap();
+    let equality = StringEquality::new()
+        .with_group(["x", "y"])
+        .with_group(["y", "z"]);
+    let mut matches = IndexedDag::new(
+        automaton,
+        "ab ab ab b",
+        1,
+        TrimmingStrategy::FullTrimming,
+        false,
+    );
+    matches.preprocess().unwrap();
+    let count = equality
+        .filter(matches.iter())
+        .inspect(|mapping| assert!(equality.is_satisfied(mapping)))
+        .count();
+
+    // "ab ab" at two positions, "ab ab ab" and "b b", but not "ab ab b".
+    assert_eq!(count, 4);
+}
+
+#[test]
+fn indexed_equality() {
+    let cases = [
+        (
+            r"<(?P<open>\w+)>[^<]*</(?P<close>\w+)>",
+            vec![vec!["open", "close"]],
+            "<a>x</a> <b>y</c> <ab>z</ab> <abc>t</abd> <aa>a</a> <ê>é</ê>",
+        ),
+        (
+            r"(?P<x>\w+) (?P<y>\w+)(?: (?P<z>\w+))?",
+            vec![vec!["x", "y"], vec!["y", "z"]],
+            "ab ab ab b aab ab",
+        ),
+        (
+            r"(?P<x>a*)b(?P<y>a*)",
+            vec![vec!["x", "y"]],
+            "aabaa ab aba aaba",
+        ),
+    ];
+
+    for (regex, groups, text) in cases.iter() {
+        let automaton = regex::compile(regex).unwrap();
+        let equality = (groups.iter()).fold(StringEquality::new(), |equality, group| {
+            equality.with_group(group.clone())
+        });
+
+        for &order in [Order::Backward, Order::Document].iter() {
+            let mut all = IndexedDag::new(
+                automaton.clone(),
+                *text,
+                1,
+                TrimmingStrategy::FullTrimming,
+                false,
+            )
+            .with_order(order);
+            all.preprocess().unwrap();
+            let expected: Vec<_> = equality.filter(all.iter()).collect();
+            assert!(!expected.is_empty());
+
+            let mut selected = IndexedDag::new(
+                automaton.clone(),
+                *text,
+                2,
+                TrimmingStrategy::FullTrimming,
+                false,
+            )
+            .with_order(order)
+            .with_equality(equality.clone());
+            selected.preprocess().unwrap();
+
+            assert_eq!(selected.iter().collect::<Vec<_>>(), expected);
+            assert_eq!(selected.count(), expected.len() as u128);
+            assert_eq!(
+                selected.count_by_pattern().values().sum::<u128>(),
+                expected.len() as u128
+            );
+
+            let random_access = selected.random_access().unwrap();
+            assert_eq!(random_access.total(), expected.len() as u128);
+
+            for (index, mapping) in expected.iter().enumerate() {
+                assert_eq!(random_access.get(index as u128).as_ref(), Some(mapping));
+            }
+
+            let (sender, receiver) = mpsc::channel();
+            selected.for_each_parallel(3, |_, mapping| sender.send(mapping).unwrap());
+            drop(sender);
+            assert_eq!(
+                receiver.iter().collect::<HashSet<_>>(),
+                expected.iter().cloned().collect()
+            );
+        }
+    }
+
+    // The selection follows the text appended or edited in the index.
+    let automaton = regex::compile(r"(?P<x>\w+) (?P<y>\w+)").unwrap();
+    let equality = StringEquality::new().with_group(["x", "y"]);
+
+    let expected = |text: &str| {
+        let mut all = IndexedDag::new(
+            automaton.clone(),
+            text,
+            1,
+            TrimmingStrategy::PartialTrimming,
+            false,
+        );
+        all.preprocess().unwrap();
+        equality.filter(all.iter()).count() as u128
+    };
+
+    let texts = [
+        "ab ab".to_string(),
+        "ab ab cb cb ab".to_string(),
+        "ab ab cb cb ab ".repeat(200),
+    ];
+    let edited = format!("{}ab cb b{}", &texts[2][..1500], &texts[2][1510..]);
+
+    let mut selected = IndexedDag::new(
+        automaton.clone(),
+        texts[0].as_str(),
+        1,
+        TrimmingStrategy::PartialTrimming,
+        false,
+    )
+    .with_equality(equality.clone());
+    selected.preprocess().unwrap();
+    assert_eq!(selected.count(), expected(&texts[0]));
+
+    for text in texts[1..].iter() {
+        selected.append(text.as_str()).unwrap();
+        assert_eq!(selected.count(), expected(text));
+    }
+
+    selected.update(edited.as_str(), 1500..1510).unwrap();
+    assert_eq!(selected.count(), expected(&edited));
+    assert_eq!(selected.iter().count() as u128, expected(&edited));
+}
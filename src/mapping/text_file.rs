@@ -0,0 +1,221 @@
+use std::fmt;
+use std::io;
+use std::io::SeekFrom;
+use std::io::prelude::*;
+use std::ops::Range;
+use std::str;
+use std::sync::Mutex;
+
+/// Size of the blocks read from the source at once.
+static BLOCK_SIZE: usize = 1 << 16;
+
+trait Source: Read + Seek + Send {}
+
+impl<T: Read + Seek + Send> Source for T {}
+
+/// A text stored in a seekable source, such as a file, which is never loaded
+/// in memory at once. The source is read as a stream while preprocessing and
+/// the text of a mapping is read back from its offsets when it is accessed.
+pub struct TextFile {
+    source: Mutex<Box<dyn Source>>,
+    len: usize,
+    unicode: bool,
+}
+
+impl TextFile {
+    /// Read the source as a sequence of unicode characters, an error is
+    /// reported while reading it if it is not valid UTF-8.
+    pub fn unicode<R: Read + Seek + Send + 'static>(source: R) -> io::Result<TextFile> {
+        TextFile::new(source, true)
+    }
+
+    /// Read the source as a sequence of raw bytes.
+    pub fn bytes<R: Read + Seek + Send + 'static>(source: R) -> io::Result<TextFile> {
+        TextFile::new(source, false)
+    }
+
+    fn new<R: Read + Seek + Send + 'static>(mut source: R, unicode: bool) -> io::Result<TextFile> {
+        let len = source.seek(SeekFrom::End(0))? as usize;
+
+        Ok(TextFile {
+            source: Mutex::new(Box::new(source)),
+            len,
+            unicode,
+        })
+    }
+
+    /// Only keep the first `len` bytes of the source as the text.
+    pub fn truncate(&mut self, len: usize) {
+        self.len = self.len.min(len);
+    }
+
+    /// Length of the text in bytes.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_unicode(&self) -> bool {
+        self.unicode
+    }
+
+    /// Read a range of bytes of the text.
+    pub fn read(&self, range: Range<usize>) -> io::Result<Vec<u8>> {
+        let mut source = self.source.lock().unwrap();
+        let mut buffer = vec![0; range.len()];
+        source.seek(SeekFrom::Start(range.start as u64))?;
+        source.read_exact(&mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Read the text by blocks, from the start to the end.
+    pub(crate) fn for_each_block(&self, mut func: impl FnMut(&[u8])) -> io::Result<()> {
+        let mut start = 0;
+
+        while start < self.len {
+            let end = (start + BLOCK_SIZE).min(self.len);
+            func(&self.read(start..end)?);
+            start = end;
+        }
+
+        Ok(())
+    }
+
+    /// Read the symbols of the text together with their width in bytes,
+    /// possibly from the end of the text to its start.
+    pub(crate) fn for_each_symbol(
+        &self,
+        reversed: bool,
+        mut func: impl FnMut(char, usize),
+    ) -> io::Result<()> {
+        if !self.unicode {
+            let mut emit = |block: &[u8]| {
+                for &x in block {
+                    func(char::from(x), 1)
+                }
+            };
+
+            return if reversed {
+                self.for_each_block_rev(|block| {
+                    let mut block = block.to_vec();
+                    block.reverse();
+                    emit(&block)
+                })
+            } else {
+                self.for_each_block(emit)
+            };
+        }
+
+        // Bytes of a character that overlaps with the next block.
+        let mut carry = Vec::new();
+        let mut result = Ok(());
+
+        if !reversed {
+            self.for_each_block(|block| {
+                if result.is_err() {
+                    return;
+                }
+
+                carry.extend_from_slice(block);
+
+                let valid = match str::from_utf8(&carry) {
+                    Ok(valid) => valid,
+                    Err(err) if err.error_len().is_none() => {
+                        str::from_utf8(&carry[..err.valid_up_to()]).unwrap()
+                    }
+                    Err(err) => {
+                        result = Err(invalid_utf8(err));
+                        return;
+                    }
+                };
+
+                for x in valid.chars() {
+                    func(x, x.len_utf8());
+                }
+
+                carry.drain(..valid.len());
+            })?;
+        } else {
+            let mut end = self.len;
+
+            self.for_each_block_rev(|block| {
+                if result.is_err() {
+                    return;
+                }
+
+                let start = end - block.len();
+                end = start;
+                carry.splice(0..0, block.iter().copied());
+
+                // Skip bytes of a character that starts in the previous block.
+                let mut boundary = 0;
+
+                if start > 0 {
+                    while boundary < carry.len() && carry[boundary] & 0xC0 == 0x80 {
+                        boundary += 1;
+                    }
+                }
+
+                match str::from_utf8(&carry[boundary..]) {
+                    Ok(valid) => {
+                        for x in valid.chars().rev() {
+                            func(x, x.len_utf8());
+                        }
+                    }
+                    Err(err) => {
+                        result = Err(invalid_utf8(err));
+                        return;
+                    }
+                }
+
+                carry.truncate(boundary);
+            })?;
+        }
+
+        if result.is_ok() && !carry.is_empty() {
+            result = Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "text ends with an incomplete UTF-8 sequence",
+            ));
+        }
+
+        result
+    }
+
+    /// Read the text by blocks, from the end to the start.
+    fn for_each_block_rev(&self, mut func: impl FnMut(&[u8])) -> io::Result<()> {
+        let mut end = self.len;
+
+        while end > 0 {
+            let start = end.saturating_sub(BLOCK_SIZE);
+            func(&self.read(start..end)?);
+            end = start;
+        }
+
+        Ok(())
+    }
+}
+
+fn invalid_utf8(err: str::Utf8Error) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err)
+}
+
+impl PartialEq for TextFile {
+    fn eq(&self, other: &TextFile) -> bool {
+        std::ptr::eq(self, other)
+    }
+}
+
+impl Eq for TextFile {}
+
+impl fmt::Debug for TextFile {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TextFile")
+            .field("len", &self.len)
+            .field("unicode", &self.unicode)
+            .finish()
+    }
+}
@@ -2,21 +2,28 @@ use std::cmp::PartialEq;
 use std::ops::{BitAnd, BitOr, Index, Mul};
 
 use bit_set::BitSet;
-use std::cell::Cell;
 use std::fmt;
+use std::io;
+use std::io::prelude::*;
 use std::mem::{forget, size_of};
 use std::slice;
+use std::sync::atomic::{AtomicU16, Ordering};
+
+use super::persist::{Persist, invalid_data};
 
 /// Naive representation of a matrix as a single consecutive chunk of memory.
+///
+/// The heap storage is owned by the matrix and only mutated through `&mut`, so
+/// that a matrix can be shared between threads.
 pub struct Matrix {
     height: u16,
     width: u16,
-    usage_count: Cell<u16>,
+    usage_count: AtomicU16,
     /// if size<size_of<usize> this holds the matrix. Otherwise it holds a pointer to the matrix.
     data: usize,
 }
 
-impl<'a> Matrix {
+impl Matrix {
     /// Create a matrix filled with false.
     pub fn new(height: usize, width: usize) -> Matrix {
         let padded_width = Matrix::padded_width(width);
@@ -28,12 +35,12 @@ impl<'a> Matrix {
         //			println!("Matrix size: {} {} {} {}", height, width, padded_width, size);
         //		}
 
-        if size <= size_of::<usize>() * 8 {
+        if size <= usize::BITS as usize {
             data = 0;
         } else {
             //			panic!("Matrix size: {}", size);
-            let real_size = (size / (size_of::<usize>() * 8)) + 1;
-            let v: Vec<usize> = vec![0; real_size as usize];
+            let real_size = (size / usize::BITS as usize) + 1;
+            let v: Vec<usize> = vec![0; real_size];
             let data_ptr = v.as_ptr() as *mut usize;
             data = data_ptr as usize;
             forget(v);
@@ -42,7 +49,7 @@ impl<'a> Matrix {
         Matrix {
             width: width as u16,
             height: height as u16,
-            usage_count: Cell::new(0),
+            usage_count: AtomicU16::new(0),
             data,
         }
     }
@@ -68,36 +75,47 @@ impl<'a> Matrix {
 
     fn get_storage<T>(&self) -> &[T] {
         let (_, size) = self.get_width_and_size();
-        let data_ptr: *const T;
-
-        if size <= 64 {
-            data_ptr = &self.data as *const usize as *const T;
+        let data_ptr: *const T = if size <= 64 {
+            &self.data as *const usize as *const T
         } else {
-            data_ptr = self.data as *const usize as *const T;
-        }
-        let data;
-        unsafe {
-            data = slice::from_raw_parts(data_ptr, size as usize / size_of::<T>());
-        }
+            self.data as *const usize as *const T
+        };
 
-        data
+        // `size` is expressed in bits.
+        unsafe { slice::from_raw_parts(data_ptr, size / (8 * size_of::<T>())) }
     }
 
     fn get_storage_mut<T>(&mut self) -> &mut [T] {
         let (_, size) = self.get_width_and_size();
-        let data_ptr: *mut T;
+        let data_ptr: *mut T = if size <= 64 {
+            &mut self.data as *mut usize as *mut T
+        } else {
+            self.data as *mut usize as *mut T
+        };
+
+        // `size` is expressed in bits.
+        unsafe { slice::from_raw_parts_mut(data_ptr, size / (8 * size_of::<T>())) }
+    }
 
-        if size <= 64 {
-            data_ptr = &mut self.data as *mut usize as *mut T;
+    /// Get the words of memory holding the matrix.
+    fn get_words(&self) -> &[usize] {
+        if self.is_heap() {
+            let (_, size) = self.get_width_and_size();
+            let len = (size / usize::BITS as usize) + 1;
+            unsafe { slice::from_raw_parts(self.data as *const usize, len) }
         } else {
-            data_ptr = self.data as *mut usize as *mut T;
-        }
-        let data;
-        unsafe {
-            data = slice::from_raw_parts_mut(data_ptr, size as usize / size_of::<T>());
+            slice::from_ref(&self.data)
         }
+    }
 
-        data
+    fn get_words_mut(&mut self) -> &mut [usize] {
+        if self.is_heap() {
+            let (_, size) = self.get_width_and_size();
+            let len = (size / usize::BITS as usize) + 1;
+            unsafe { slice::from_raw_parts_mut(self.data as *mut usize, len) }
+        } else {
+            slice::from_mut(&mut self.data)
+        }
     }
 
     pub fn get_height(&self) -> usize {
@@ -141,17 +159,14 @@ impl<'a> Matrix {
     }
 
     pub fn col_mul_inplace(&self, column: &mut BitSet) {
-        self.usage_count.set(self.usage_count.get() + 1);
+        self.usage_count.fetch_add(1, Ordering::Relaxed);
         //		println!("col_mul: width: {} height: {}, column_height: {}", self.width, self.height, column.capacity());
 
         let (padded_width, _) = self.get_width_and_size();
         if padded_width <= 64 {
-            let col = column.get_ref().storage()[0] as u64
-                + if column.capacity() > 32 {
-                    (column.get_ref().storage()[1] as u64) << 32
-                } else {
-                    0
-                };
+            let col_storage = column.get_ref().storage();
+            let col = col_storage.first().map(|&x| u64::from(x)).unwrap_or(0)
+                + col_storage.get(1).map(|&x| u64::from(x) << 32).unwrap_or(0);
             column.clear();
             let result = column;
 
@@ -159,7 +174,7 @@ impl<'a> Matrix {
                 8 => self.col_mul(col as u8, result),
                 16 => self.col_mul(col as u16, result),
                 32 => self.col_mul(col as u32, result),
-                64 => self.col_mul(col as u64, result),
+                64 => self.col_mul(col, result),
                 width => panic!("invalid matrix effective width {}", width),
             }
         } else {
@@ -188,7 +203,7 @@ impl<'a> Matrix {
     {
         let storage = self.get_storage::<T>();
         for i in 0..self.height {
-            if (storage[i as usize] & column) != <T as BitAnd>::Output::from(0 as u8) {
+            if (storage[i as usize] & column) != <T as BitAnd>::Output::from(0_u8) {
                 result.insert(i as usize);
             }
         }
@@ -201,7 +216,7 @@ impl<'a> Matrix {
 
         for i in 0..self.height {
             for k in 0..effective_width {
-                if (storage[i as usize * effective_width + k] & column[k as usize]) != 0 {
+                if (storage[i as usize * effective_width + k] & column[k]) != 0 {
                     result.insert(i as usize);
                     break;
                 }
@@ -223,7 +238,7 @@ impl<'a> Matrix {
     }
 
     pub fn get_usage_count(&self) -> usize {
-        self.usage_count.get() as usize
+        self.usage_count.load(Ordering::Relaxed) as usize
     }
 
     pub fn get_memory_usage(&self) -> usize {
@@ -242,9 +257,7 @@ impl<'a> Matrix {
 
         for i in 0..self.height as usize {
             for j in 0..other.height as usize {
-                if (self_storage[i as usize] & other_storage[j as usize])
-                    != <T as BitAnd>::Output::from(0 as u8)
-                {
+                if (self_storage[i] & other_storage[j]) != <T as BitAnd>::Output::from(0_u8) {
                     result.insert(i, j);
                 }
             }
@@ -254,7 +267,45 @@ impl<'a> Matrix {
     fn is_heap(&self) -> bool {
         let (_, size) = self.get_width_and_size();
 
-        size > size_of::<usize>() * 8
+        size > usize::BITS as usize
+    }
+}
+
+impl Persist for Matrix {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.get_height().persist(output)?;
+        self.get_width().persist(output)?;
+
+        for &word in self.get_words() {
+            (word as u64).persist(output)?;
+        }
+
+        Ok(())
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<Matrix> {
+        let height = usize::restore(input)?;
+        let width = usize::restore(input)?;
+
+        if height > u16::MAX as usize || width > u16::MAX as usize {
+            return Err(invalid_data("matrix is too large"));
+        }
+
+        let mut matrix = Matrix::new(height, width);
+
+        for word in matrix.get_words_mut() {
+            *word = u64::restore(input)? as usize;
+        }
+
+        Ok(matrix)
+    }
+}
+
+impl PartialEq for Matrix {
+    fn eq(&self, other: &Matrix) -> bool {
+        self.height == other.height
+            && self.width == other.width
+            && self.get_words() == other.get_words()
     }
 }
 
@@ -264,7 +315,7 @@ impl Drop for Matrix {
             unsafe {
                 let (_, size) = self.get_width_and_size();
                 let ptr = self.data as *mut usize;
-                let len = (size / (size_of::<usize>() * 8)) + 1;
+                let len = (size / usize::BITS as usize) + 1;
                 Vec::from_raw_parts(ptr, len, len);
             }
         }
@@ -305,11 +356,7 @@ impl Index<(usize, usize)> for Matrix {
             }
         };
 
-        if result {
-            &true
-        } else {
-            &false
-        }
+        if result { &true } else { &false }
     }
 }
 
@@ -369,7 +416,7 @@ impl Mul for &Matrix {
 
 impl fmt::Debug for Matrix {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        writeln!(f, "")?;
+        writeln!(f)?;
         for i in 0..self.height as usize {
             for j in 0..self.width as usize {
                 let bit = match self[(i, j)] {
@@ -378,8 +425,11 @@ impl fmt::Debug for Matrix {
                 };
                 write!(f, "{}", bit)?;
             }
-            writeln!(f, "")?;
+            writeln!(f)?;
         }
-        writeln!(f, "")
+        writeln!(f)
     }
 }
+
+#[cfg(test)]
+mod tests;
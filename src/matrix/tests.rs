@@ -0,0 +1,35 @@
+use std::panic::{self, AssertUnwindSafe};
+
+use bit_set::BitSet;
+
+use super::Matrix;
+
+#[test]
+fn column_product() {
+    for &width in [5, 12, 30, 40, 64, 200].iter() {
+        let mut matrix = Matrix::new(3, width);
+        matrix.insert(1, width - 1);
+        matrix.insert(2, 0);
+
+        // The storage of a column may be shorter than its capacity.
+        let mut column = BitSet::new();
+        column.reserve_len(width);
+        column.insert(0);
+        matrix.col_mul_inplace(&mut column);
+        assert_eq!(column.iter().collect::<Vec<_>>(), vec![2]);
+
+        let mut column = BitSet::new();
+        column.insert(width - 1);
+        matrix.col_mul_inplace(&mut column);
+        assert_eq!(column.iter().collect::<Vec<_>>(), vec![1]);
+    }
+}
+
+#[test]
+fn rows_out_of_bounds() {
+    for &width in [5, 12, 30, 64, 200].iter() {
+        let mut matrix = Matrix::new(2, width);
+        let inserted = panic::catch_unwind(AssertUnwindSafe(|| matrix.insert(2, 0)));
+        assert!(inserted.is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use std::collections::HashSet;
+use std::io;
 use std::str::CharIndices;
 
 use super::super::automaton::{Automaton, Label};
@@ -46,7 +47,9 @@ impl<'t> SpannerEnumerator<'t> for NaiveEnum<'t> {
     }
 
     /// Do nothing
-    fn preprocess(&mut self) {}
+    fn preprocess(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 }
 
 impl<'t> Iterator for NaiveEnumIterator<'t> {
@@ -58,7 +61,7 @@ impl<'t> Iterator for NaiveEnumIterator<'t> {
 
             for (label, target) in &self.automaton.get_adj()[state] {
                 match **label {
-                    Label::Atom(ref atom) if curr_char != None => {
+                    Label::Atom(ref atom) if curr_char.is_some() => {
                         if let Some((_, curr_char)) = curr_char {
                             if !atom.is_match(&curr_char) {
                                 continue;
@@ -82,7 +85,7 @@ impl<'t> Iterator for NaiveEnumIterator<'t> {
                 }
             }
 
-            if curr_char == None && self.automaton.finals.contains(state) {
+            if curr_char.is_none() && self.automaton.finals.contains(state) {
                 let mapping = Mapping::from_markers(
                     self.text,
                     assigns
@@ -1,5 +1,6 @@
 use lib_regex;
 
+use std::io;
 use std::ops;
 
 use super::super::mapping::{Mapping, SpannerEnumerator};
@@ -37,7 +38,9 @@ impl<'t> NaiveEnumCubic<'t> {
 }
 
 impl<'t> SpannerEnumerator<'t> for NaiveEnumCubic<'t> {
-    fn preprocess(&mut self) {}
+    fn preprocess(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 
     fn iter<'i>(&'i self) -> Box<dyn Iterator<Item = Mapping<'t>> + 'i> {
         Box::new(NaiveEnumCubicIterator {
@@ -54,7 +57,7 @@ impl<'t> Iterator for NaiveEnumCubicIterator<'t> {
 
     fn next(&mut self) -> Option<Mapping<'t>> {
         while let Some((curr_start, _)) = self.char_iterator_start.next() {
-            while let Some((curr_end, _)) = self.char_iterator_end.next() {
+            for (curr_end, _) in self.char_iterator_end.by_ref() {
                 let is_match = self.regex.is_match(&self.text[curr_start..curr_end]);
 
                 if is_match {
@@ -2,8 +2,10 @@
 //< regex.
 //<
 //< Note that these algorithms are not as powerful as other algorithms of this
-//< project as they can't handle defined groups.
+//< project as they can't handle defined groups. Anchors are evaluated over
+//< each substring: `^` and `$` match at the boundaries of the substring.
 
+use std::io;
 use std::ops;
 
 use super::super::automaton::Automaton;
@@ -38,15 +40,17 @@ pub struct NaiveEnumQuadraticIterator<'t> {
 }
 
 impl<'t> NaiveEnumQuadratic<'t> {
-    pub fn new(regex_str: &str, text: &'t str) -> NaiveEnumQuadratic<'t> {
-        let automaton = regex::compile_raw(regex_str);
+    pub fn new(regex_str: &str, text: &'t str) -> Result<NaiveEnumQuadratic<'t>, regex::Error> {
+        let automaton = regex::compile_raw(regex_str)?;
 
-        NaiveEnumQuadratic { automaton, text }
+        Ok(NaiveEnumQuadratic { automaton, text })
     }
 }
 
 impl<'t> SpannerEnumerator<'t> for NaiveEnumQuadratic<'t> {
-    fn preprocess(&mut self) {}
+    fn preprocess(&mut self) -> io::Result<()> {
+        Ok(())
+    }
 
     fn iter<'i>(&'i self) -> Box<dyn Iterator<Item = Mapping<'t>> + 'i> {
         // Init automata states
@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Condvar, Mutex};
+use std::thread;
+
+/// Apply a function to each input in its own thread.
+pub fn map_parallel<T: Sync, U: Send>(inputs: &[T], func: impl Fn(&T) -> U + Sync) -> Vec<U> {
+    thread::scope(|scope| {
+        let func = &func;
+        let handles: Vec<_> = inputs
+            .iter()
+            .map(|input| scope.spawn(move || func(input)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("a worker thread panicked"))
+            .collect()
+    })
+}
+
+/// Apply a function to each input in its own thread, taking inputs by value.
+pub fn map_parallel_owned<T: Send, U: Send>(
+    inputs: Vec<T>,
+    func: impl Fn(T) -> U + Sync,
+) -> Vec<U> {
+    thread::scope(|scope| {
+        let func = &func;
+        let handles: Vec<_> = inputs
+            .into_iter()
+            .map(|input| scope.spawn(move || func(input)))
+            .collect();
+
+        handles
+            .into_iter()
+            .map(|handle| handle.join().expect("a worker thread panicked"))
+            .collect()
+    })
+}
+
+/// Explore a forest of tasks using several threads, starting from given root
+/// tasks. Each task is expanded by `expand`, which is given the index of the
+/// worker running it and pushes the children of the task to a queue.
+///
+/// Each worker explores its own tasks depth first, the oldest of them being
+/// shared with idle workers, which are the closest to the roots and thus
+/// likely to represent the most work.
+pub fn explore_parallel<T: Send>(
+    roots: Vec<T>,
+    threads: usize,
+    expand: impl Fn(usize, T, &mut VecDeque<T>) + Sync,
+) {
+    assert!(threads > 0, "at least one thread is required");
+
+    let pool = Pool {
+        state: Mutex::new(PoolState {
+            tasks: roots,
+            idle: 0,
+            done: false,
+        }),
+        available: Condvar::new(),
+        hungry: AtomicUsize::new(0),
+        threads,
+    };
+
+    thread::scope(|scope| {
+        let pool = &pool;
+        let expand = &expand;
+        let handles: Vec<_> = (0..threads)
+            .map(|worker| scope.spawn(move || pool.work(worker, expand)))
+            .collect();
+
+        for handle in handles {
+            handle.join().expect("a worker thread panicked");
+        }
+    })
+}
+
+/// Tasks shared between the workers of `explore_parallel`.
+struct Pool<T> {
+    state: Mutex<PoolState<T>>,
+    available: Condvar,
+    /// Number of idle workers, which can be read without locking the state.
+    hungry: AtomicUsize,
+    threads: usize,
+}
+
+struct PoolState<T> {
+    tasks: Vec<T>,
+    idle: usize,
+    done: bool,
+}
+
+impl<T> Pool<T> {
+    fn work(&self, worker: usize, expand: &impl Fn(usize, T, &mut VecDeque<T>)) {
+        // Stop other workers if this one panics.
+        let _guard = AbortOnPanic(self);
+        let mut local = VecDeque::new();
+
+        loop {
+            if let Some(task) = local.pop_back() {
+                expand(worker, task, &mut local);
+
+                if local.len() > 1 && self.hungry.load(Ordering::Relaxed) > 0 {
+                    let mut state = self.state.lock().unwrap();
+                    state.tasks.extend(local.pop_front());
+                    self.available.notify_one();
+                }
+
+                continue;
+            }
+
+            match self.steal() {
+                None => return,
+                Some(task) => local.push_back(task),
+            }
+        }
+    }
+
+    /// Wait for a shared task, or `None` once all workers are idle.
+    fn steal(&self) -> Option<T> {
+        let mut state = self.state.lock().unwrap();
+
+        loop {
+            if let Some(task) = state.tasks.pop() {
+                return Some(task);
+            }
+
+            if state.done {
+                return None;
+            }
+
+            state.idle += 1;
+
+            if state.idle == self.threads {
+                state.done = true;
+                self.available.notify_all();
+                return None;
+            }
+
+            self.hungry.fetch_add(1, Ordering::Relaxed);
+            state = self.available.wait(state).unwrap();
+            self.hungry.fetch_sub(1, Ordering::Relaxed);
+            state.idle -= 1;
+        }
+    }
+}
+
+struct AbortOnPanic<'p, T>(&'p Pool<T>);
+
+impl<'p, T> Drop for AbortOnPanic<'p, T> {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            let mut state = self
+                .0
+                .state
+                .lock()
+                .unwrap_or_else(|poisoned| poisoned.into_inner());
+            state.done = true;
+            self.0.available.notify_all();
+        }
+    }
+}
@@ -0,0 +1,171 @@
+//< Compact binary encoding of the structures built during preprocessing.
+//<
+//< Integers are encoded in little-endian over 64 bits, and sequences are
+//< prefixed with their length.
+
+use std::io;
+use std::io::prelude::*;
+
+use bit_set::BitSet;
+use bit_vec::BitVec;
+
+/// A structure that can be written to and read back from a binary stream.
+pub trait Persist: Sized {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()>;
+    fn restore<R: Read>(input: &mut R) -> io::Result<Self>;
+}
+
+/// Build an error reporting that the input stream is not a valid encoding.
+pub fn invalid_data(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+impl Persist for u64 {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&self.to_le_bytes())
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<u64> {
+        let mut buf = [0; 8];
+        input.read_exact(&mut buf)?;
+        Ok(u64::from_le_bytes(buf))
+    }
+}
+
+impl Persist for usize {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        (*self as u64).persist(output)
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<usize> {
+        let value = u64::restore(input)?;
+
+        // `usize::MAX` is used as a sentinel value, which must be preserved
+        // whatever the size of `usize` is on the machine.
+        if value == u64::MAX {
+            return Ok(usize::MAX);
+        }
+
+        if value > usize::MAX as u64 {
+            return Err(invalid_data("integer does not fit on this platform"));
+        }
+
+        Ok(value as usize)
+    }
+}
+
+impl Persist for u32 {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        output.write_all(&self.to_le_bytes())
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        input.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+}
+
+impl Persist for String {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.len().persist(output)?;
+        output.write_all(self.as_bytes())
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<String> {
+        let len = usize::restore(input)?;
+        let mut bytes = Vec::new();
+        input.take(len as u64).read_to_end(&mut bytes)?;
+
+        if bytes.len() != len {
+            return Err(io::Error::new(
+                io::ErrorKind::UnexpectedEof,
+                "failed to fill whole buffer",
+            ));
+        }
+
+        String::from_utf8(bytes).map_err(|_| invalid_data("string is not valid UTF-8"))
+    }
+}
+
+impl<T: Persist> Persist for Vec<T> {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.len().persist(output)?;
+
+        for item in self {
+            item.persist(output)?;
+        }
+
+        Ok(())
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<Vec<T>> {
+        let len = usize::restore(input)?;
+        // The length is not trusted to preallocate memory as the input may be
+        // corrupted.
+        let mut result = Vec::new();
+
+        for _ in 0..len {
+            result.push(T::restore(input)?);
+        }
+
+        Ok(result)
+    }
+}
+
+impl<T: Persist, U: Persist> Persist for (T, U) {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.0.persist(output)?;
+        self.1.persist(output)
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<(T, U)> {
+        Ok((T::restore(input)?, U::restore(input)?))
+    }
+}
+
+impl Persist for BitVec {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.len().persist(output)?;
+
+        for block in self.storage() {
+            block.persist(output)?;
+        }
+
+        Ok(())
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<BitVec> {
+        let nbits = usize::restore(input)?;
+        let mut bits = BitVec::new();
+
+        unsafe {
+            let storage = bits.storage_mut();
+
+            for _ in 0..nbits.div_ceil(32) {
+                storage.push(u32::restore(input)?);
+            }
+
+            // Bits past the end of the vector must be left unset.
+            if let Some(last) = storage.last_mut() {
+                if nbits % 32 != 0 {
+                    *last &= (1 << (nbits % 32)) - 1;
+                }
+            }
+
+            bits.set_len(nbits);
+        }
+
+        Ok(bits)
+    }
+}
+
+impl Persist for BitSet {
+    fn persist<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        self.get_ref().persist(output)
+    }
+
+    fn restore<R: Read>(input: &mut R) -> io::Result<BitSet> {
+        Ok(BitSet::from_bit_vec(BitVec::restore(input)?))
+    }
+}
@@ -162,7 +162,7 @@ where
             match ret {
                 None => {
                     self.refresh();
-                    print!("\n");
+                    println!();
                 }
                 Some(_) => {
                     if self.last_refresh.elapsed().as_millis() > REFRESH_DELAY {
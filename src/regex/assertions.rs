@@ -0,0 +1,273 @@
+//< Elimination of zero-width assertions from the automaton built by Glushkov's
+//< construction.
+//<
+//< States of the resulting automaton are triples `(state, prev, next)` where
+//< `prev` is the kind of the last character that was read and `next` the set of
+//< kinds the next character is still allowed to have, as required by the
+//< assertions that were crossed since then. Transitions labeled with an
+//< assertion are then replaced by copies of the transitions that follow them.
+
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+
+use regex_syntax::hir::{self, ClassBytes, ClassBytesRange, ClassUnicode, ClassUnicodeRange};
+
+use super::super::automaton::Label;
+use super::super::automaton::assertion::{Assertion, CharKind};
+use super::super::automaton::atom::Atom;
+
+/// Set of kinds of characters, as a bitmask.
+type KindSet = u8;
+
+const ALL_KINDS: KindSet = 0b1111;
+
+type Transitions = Vec<(usize, Arc<Label>, usize)>;
+
+fn kind_mask(kind: CharKind) -> KindSet {
+    1 << (kind as u8)
+}
+
+/// Set of kinds of the next character that satisfy an assertion.
+fn allowed_next(assertion: Assertion, prev: CharKind) -> KindSet {
+    CharKind::ALL
+        .iter()
+        .filter(|&&next| assertion.is_match(prev, next))
+        .fold(0, |acc, &next| acc | kind_mask(next))
+}
+
+/// Split atoms along the kinds of characters they match, results are cached
+/// to share labels between transitions.
+///
+/// Byte atoms only occur in patterns over raw bytes, where unicode atoms only
+/// match ASCII characters, so that unicode word characters and ASCII word
+/// bytes agree on these atoms.
+struct AtomSplitter {
+    classes: Vec<(CharKind, ClassUnicode, ClassBytes)>,
+    cache: HashMap<(*const Label, CharKind), Option<Arc<Label>>>,
+}
+
+impl AtomSplitter {
+    fn new() -> AtomSplitter {
+        let newline = ClassUnicode::new(vec![ClassUnicodeRange::new('\n', '\n')]);
+        let newline_bytes = ClassBytes::new(vec![ClassBytesRange::new(b'\n', b'\n')]);
+
+        let word = match regex_syntax::Parser::new()
+            .parse(r"\w")
+            .map(|hir| hir.into_kind())
+        {
+            Ok(hir::HirKind::Class(hir::Class::Unicode(class))) => class,
+            _ => panic!("regex_syntax failed to parse \\w"),
+        };
+
+        let word_bytes = ClassBytes::new(vec![
+            ClassBytesRange::new(b'0', b'9'),
+            ClassBytesRange::new(b'A', b'Z'),
+            ClassBytesRange::new(b'_', b'_'),
+            ClassBytesRange::new(b'a', b'z'),
+        ]);
+
+        let mut other = word.clone();
+        other.union(&newline);
+        other.negate();
+
+        let mut other_bytes = word_bytes.clone();
+        other_bytes.union(&newline_bytes);
+        other_bytes.negate();
+
+        AtomSplitter {
+            classes: vec![
+                (CharKind::NewLine, newline, newline_bytes),
+                (CharKind::Word, word, word_bytes),
+                (CharKind::Other, other, other_bytes),
+            ],
+            cache: HashMap::new(),
+        }
+    }
+
+    /// Get the restriction of an atom to characters of given kind, if it is
+    /// not empty.
+    fn split(&mut self, label: &Arc<Label>, kind: CharKind) -> Option<Arc<Label>> {
+        let classes = &self.classes;
+
+        self.cache
+            .entry((Arc::as_ptr(label), kind))
+            .or_insert_with(|| match &**label {
+                Label::Atom(Atom::Literal(hir::Literal::Unicode(x))) => {
+                    if CharKind::of(*x) == kind {
+                        Some(label.clone())
+                    } else {
+                        None
+                    }
+                }
+                Label::Atom(Atom::Literal(hir::Literal::Byte(x))) => {
+                    if CharKind::of_byte(*x) == kind {
+                        Some(label.clone())
+                    } else {
+                        None
+                    }
+                }
+                Label::Atom(Atom::Class(hir::Class::Unicode(class))) => {
+                    let (_, kind_class, _) = classes.iter().find(|(k, _, _)| *k == kind).unwrap();
+                    let mut class = class.clone();
+                    class.intersect(kind_class);
+
+                    if class.iter().next().is_none() {
+                        None
+                    } else {
+                        Some(Arc::new(Label::Atom(Atom::Class(hir::Class::Unicode(
+                            class,
+                        )))))
+                    }
+                }
+                Label::Atom(Atom::Class(hir::Class::Bytes(class))) => {
+                    let (_, _, kind_class) = classes.iter().find(|(k, _, _)| *k == kind).unwrap();
+                    let mut class = class.clone();
+                    class.intersect(kind_class);
+
+                    if class.iter().next().is_none() {
+                        None
+                    } else {
+                        Some(Arc::new(Label::Atom(Atom::Class(hir::Class::Bytes(class)))))
+                    }
+                }
+                _ => panic!("only atoms can be split"),
+            })
+            .clone()
+    }
+}
+
+/// Replace transitions labeled with an assertion with equivalent transitions
+/// of a larger automaton, the input is left unchanged if it doesn't contain
+/// any assertion.
+pub fn eliminate(
+    nb_states: usize,
+    transitions: Transitions,
+    finals: Vec<usize>,
+) -> (usize, Transitions, Vec<usize>) {
+    let has_assertions = transitions
+        .iter()
+        .any(|(_, label, _)| matches!(**label, Label::Assertion(_)));
+
+    if !has_assertions {
+        return (nb_states, transitions, finals);
+    }
+
+    let mut adj = vec![Vec::new(); nb_states];
+
+    for (source, label, target) in transitions {
+        adj[source].push((label, target));
+    }
+
+    let finals: HashSet<_> = finals.into_iter().collect();
+    let mut splitter = AtomSplitter::new();
+
+    // Explore states of the product automaton that are reachable from the
+    // initial state.
+    let initial = (0, CharKind::Edge, ALL_KINDS);
+    let mut states = BTreeSet::new();
+    let mut product_transitions = HashSet::new();
+    let mut product_finals = Vec::new();
+    let mut queue = VecDeque::new();
+
+    states.insert(initial);
+    queue.push_back(initial);
+
+    while let Some(source @ (state, prev, next)) = queue.pop_front() {
+        // Follow transitions labeled with an assertion.
+        let mut closure = vec![(state, next)];
+        let mut seen: HashSet<_> = closure.iter().cloned().collect();
+        let mut i = 0;
+
+        while i < closure.len() {
+            let (state, next) = closure[i];
+            i += 1;
+
+            for (label, target) in &adj[state] {
+                if let Label::Assertion(assertion) = **label {
+                    let target_next = next & allowed_next(assertion, prev);
+
+                    if target_next != 0 && seen.insert((*target, target_next)) {
+                        closure.push((*target, target_next));
+                    }
+                }
+            }
+        }
+
+        let mut is_final = false;
+
+        for (state, next) in closure {
+            is_final |= finals.contains(&state) && next & kind_mask(CharKind::Edge) != 0;
+
+            for (label, target) in &adj[state] {
+                let mut add_transition = |label: Arc<Label>, target_state| {
+                    if states.insert(target_state) {
+                        queue.push_back(target_state);
+                    }
+
+                    product_transitions.insert((source, ByAddress(label), target_state));
+                };
+
+                match **label {
+                    Label::Assertion(_) => {}
+                    Label::Assignation(_) => add_transition(label.clone(), (*target, prev, next)),
+                    Label::Atom(_) => {
+                        for &kind in &CharKind::ALL[1..] {
+                            if next & kind_mask(kind) == 0 {
+                                continue;
+                            }
+
+                            if let Some(sublabel) = splitter.split(label, kind) {
+                                add_transition(sublabel, (*target, kind, ALL_KINDS));
+                            }
+                        }
+                    }
+                }
+            }
+        }
+
+        if is_final {
+            product_finals.push(source);
+        }
+    }
+
+    // States are numbered in lexicographic order, which preserves the order
+    // of assignations.
+    let ids: HashMap<_, _> = states
+        .iter()
+        .enumerate()
+        .map(|(id, &key)| (key, id))
+        .collect();
+    debug_assert_eq!(ids[&initial], 0);
+
+    let mut transitions: Transitions = product_transitions
+        .into_iter()
+        .map(|(source, ByAddress(label), target)| (ids[&source], label, ids[&target]))
+        .collect();
+    transitions.sort_by_key(|(source, _, target)| (*source, *target));
+
+    let mut finals: Vec<_> = product_finals
+        .into_iter()
+        .map(|state| ids[&state])
+        .collect();
+    finals.sort_unstable();
+
+    (ids.len(), transitions, finals)
+}
+
+/// Compare labels by their address, which is shared by duplicated
+/// transitions.
+struct ByAddress(Arc<Label>);
+
+impl PartialEq for ByAddress {
+    fn eq(&self, other: &ByAddress) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for ByAddress {}
+
+impl std::hash::Hash for ByAddress {
+    fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
+        Arc::as_ptr(&self.0).hash(state)
+    }
+}
@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashMap};
+use std::convert::Infallible;
+use std::io;
+use std::io::prelude::*;
+use std::sync::Arc;
+
+use regex_syntax::hir::{
+    Class, ClassBytes, ClassBytesRange, ClassUnicode, ClassUnicodeRange, Literal,
+};
+
+use super::super::automaton::Label;
+use super::super::automaton::atom::Atom;
+use super::parse::Hir;
+use super::replacements::{Replacements, name_len};
+
+/// Prefix of the names of the groups that replace placeholders of a pattern.
+pub const PLACEHOLDER_PREFIX: &str = "__dictionary_";
+
+//  ____  _      _   _
+// |  _ \(_) ___| |_(_) ___  _ __   __ _ _ __ _   _
+// | | | | |/ __| __| |/ _ \| '_ \ / _` | '__| | | |
+// | |_| | | (__| |_| | (_) | | | | (_| | |  | |_| |
+// |____/|_|\___|\__|_|\___/|_| |_|\__,_|_|   \__, |
+//                                            |___/
+
+/// A list of words, such as a gazetteer, that a pattern can refer to with
+/// `(?&name)`, see `Compiler::with_dictionary`.
+///
+/// Entries are compiled into a trie, which only creates a state of the
+/// automaton for each distinct prefix of an entry instead of a state for each
+/// character of each entry.
+#[derive(Clone, Debug, Default)]
+pub struct Dictionary {
+    entries: Vec<String>,
+    case_folding: bool,
+}
+
+impl Dictionary {
+    /// Build a dictionary from a list of entries, empty entries are ignored.
+    pub fn new<I, S>(entries: I) -> Dictionary
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        Dictionary {
+            entries: entries
+                .into_iter()
+                .map(Into::into)
+                .filter(|entry| !entry.is_empty())
+                .collect(),
+            case_folding: false,
+        }
+    }
+
+    /// Read a dictionary with one entry per line, empty lines are ignored.
+    pub fn read(input: impl BufRead) -> io::Result<Dictionary> {
+        let mut entries = Vec::new();
+
+        for line in input.lines() {
+            let line = line?;
+            entries.push(line.strip_suffix('\r').map(String::from).unwrap_or(line));
+        }
+
+        Ok(Dictionary::new(entries))
+    }
+
+    /// Choose if entries are matched regardless of case, with simple case
+    /// folding over unicode text and ASCII case folding over raw bytes.
+    pub fn with_case_folding(mut self, case_folding: bool) -> Dictionary {
+        self.case_folding = case_folding;
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    /// Build an Hir matching exactly the entries of the dictionary, whose
+    /// entries are matched through their UTF-8 encoding if `bytes` is set.
+    pub fn to_hir(&self, bytes: bool) -> Hir {
+        let mut labels = HashMap::new();
+        let mut trie = Trie::default();
+
+        for entry in &self.entries {
+            let mut node = &mut trie;
+
+            let symbols: Vec<u32> = match bytes {
+                true => entry.bytes().map(u32::from).collect(),
+                false => entry.chars().map(u32::from).collect(),
+            };
+
+            for symbol in symbols {
+                let (key, label) = labels
+                    .entry(symbol)
+                    .or_insert_with(|| self.label(symbol, bytes));
+
+                node = &mut node
+                    .children
+                    .entry(*key)
+                    .or_insert_with(|| (label.clone(), Trie::default()))
+                    .1;
+            }
+
+            node.is_entry = true;
+        }
+
+        trie.into_hir()
+    }
+
+    /// Label matching a symbol, together with a key that is shared by all
+    /// the symbols it matches.
+    fn label(&self, symbol: u32, bytes: bool) -> (u32, Arc<Label>) {
+        let class = match bytes {
+            true => {
+                let byte = symbol as u8;
+                let mut class = ClassBytes::new(vec![ClassBytesRange::new(byte, byte)]);
+
+                if self.case_folding {
+                    class.case_fold_simple();
+                }
+
+                Class::Bytes(class)
+            }
+            false => {
+                let x = char::from_u32(symbol).unwrap();
+                let mut class = ClassUnicode::new(vec![ClassUnicodeRange::new(x, x)]);
+
+                if self.case_folding {
+                    class.case_fold_simple();
+                }
+
+                Class::Unicode(class)
+            }
+        };
+
+        let ranges = Atom::Class(class.clone()).ranges();
+        let key = ranges[0].0;
+
+        let atom = match ranges[..] {
+            [(start, end)] if start == end && bytes => Atom::Literal(Literal::Byte(start as u8)),
+            [(start, end)] if start == end => {
+                Atom::Literal(Literal::Unicode(char::from_u32(start).unwrap()))
+            }
+            _ => Atom::Class(class),
+        };
+
+        (key, Arc::new(Label::Atom(atom)))
+    }
+}
+
+#[derive(Default)]
+struct Trie {
+    is_entry: bool,
+    children: BTreeMap<u32, (Arc<Label>, Trie)>,
+}
+
+impl Trie {
+    /// Build an Hir that factorizes common prefixes of entries, thus the
+    /// Glushkov automaton of this Hir is the trie itself.
+    fn into_hir(self) -> Hir {
+        let branches = self
+            .children
+            .into_values()
+            .fold(Hir::Empty, |acc, (label, child)| {
+                let branch = Hir::Concat(Box::new(Hir::Label(label)), Box::new(child.into_hir()));
+                Hir::Alternation(Box::new(acc), Box::new(branch))
+            });
+
+        match self.is_entry {
+            true => Hir::Option(Box::new(branches)),
+            false => branches,
+        }
+    }
+}
+
+//  ____  _                _           _     _
+// |  _ \| | __ _  ___ ___| |__   ___ | | __| | ___ _ __ ___
+// | |_) | |/ _` |/ __/ _ \ '_ \ / _ \| |/ _` |/ _ \ '__/ __|
+// |  __/| | (_| | (_|  __/ | | | (_) | | (_| |  __/ |  \__ \
+// |_|   |_|\__,_|\___\___|_| |_|\___/|_|\__,_|\___|_|  |___/
+//
+
+/// Placeholders `(?&name)` of a pattern, which are replaced with empty named
+/// groups that can be parsed by regex_syntax.
+pub struct Placeholders {
+    /// Name of the dictionary of each placeholder, in order of appearance.
+    pub names: Vec<String>,
+    pub replacements: Replacements,
+}
+
+impl Placeholders {
+    /// Replace the placeholders of a pattern, placeholders are not searched
+    /// for in escape sequences and character classes.
+    pub fn replace(regex: &str) -> (String, Placeholders) {
+        let mut names = Vec::new();
+
+        let replaced = Replacements::apply(regex, |rest| {
+            let name_len = match rest.strip_prefix("(?&") {
+                None => return Ok::<_, Infallible>(None),
+                Some(rest) => name_len(rest),
+            };
+
+            if name_len == 0 || rest.as_bytes().get(3 + name_len) != Some(&b')') {
+                return Ok(None);
+            }
+
+            let group = format!("(?P<{}{}>)", PLACEHOLDER_PREFIX, names.len());
+            names.push(rest[3..3 + name_len].to_string());
+            Ok(Some((name_len + 4, group)))
+        });
+
+        let (result, replacements) = match replaced {
+            Ok(replaced) => replaced,
+            Err(err) => match err {},
+        };
+
+        (
+            result,
+            Placeholders {
+                names,
+                replacements,
+            },
+        )
+    }
+}
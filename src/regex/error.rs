@@ -0,0 +1,101 @@
+use std::error;
+use std::fmt;
+
+use regex_syntax;
+use regex_syntax::hir::HirKind as LibHir;
+use regex_syntax::hir::WordBoundary;
+
+use super::super::automaton::normalize;
+
+/// Maximal number of states of a compiled automaton, levels of the index are
+/// stored in matrices whose dimensions are encoded over 16 bits.
+pub const MAX_STATES: usize = u16::MAX as usize;
+
+/// Reasons for which a pattern can't be compiled into an automaton.
+#[derive(Clone, Debug)]
+pub enum Error {
+    /// The pattern is not a valid regular expression.
+    Syntax {
+        /// Description of the syntax error.
+        message: String,
+        /// Byte offset of the error in the pattern.
+        position: usize,
+    },
+    /// The pattern uses a construct that can't be expressed with the automaton.
+    Unsupported(LibHir),
+    /// The pattern matches raw bytes instead of unicode characters, which is
+    /// only supported by patterns compiled with `compile_bytes`.
+    ByteClass,
+    /// The automaton has more states than the index can handle.
+    TooManyStates(usize),
+    /// The automaton assigns markers in loops and can't be normalized.
+    Normalization(normalize::Error),
+    /// The pattern refers to a dictionary that was not given.
+    UnknownDictionary(String),
+    /// The pattern refers to a macro that was not defined.
+    UnknownMacro(String),
+    /// Macros refer to each other in a cycle, which starts and ends with the
+    /// same macro.
+    MacroCycle(Vec<String>),
+    /// The expansion of a macro can't be compiled, positions of syntax errors
+    /// are given in the definition of the macro.
+    InMacro(String, Box<Error>),
+    /// One of several patterns compiled together can't be compiled.
+    InPattern(usize, Box<Error>),
+}
+
+impl Error {
+    /// Convert an error from regex_syntax, `offset` being the number of bytes
+    /// that were stripped from the beginning of the pattern before parsing.
+    pub(super) fn from_lib_error(err: regex_syntax::Error, offset: usize) -> Error {
+        let (message, position) = match &err {
+            regex_syntax::Error::Parse(err) => (err.kind().to_string(), err.span().start.offset),
+            regex_syntax::Error::Translate(err) => {
+                (err.kind().to_string(), err.span().start.offset)
+            }
+            _ => (err.to_string(), 0),
+        };
+
+        Error::Syntax {
+            message,
+            position: position + offset,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Syntax { message, position } => {
+                write!(f, "invalid syntax at position {}: {}", position, message)
+            }
+            Error::Unsupported(LibHir::WordBoundary(
+                WordBoundary::Unicode | WordBoundary::UnicodeNegate,
+            )) => write!(
+                f,
+                "unicode word boundaries are not supported over raw bytes"
+            ),
+            Error::Unsupported(LibHir::WordBoundary(_)) => {
+                write!(f, "ASCII word boundaries are only supported over raw bytes")
+            }
+            Error::Unsupported(other) => write!(f, "unsupported construct: {:?}", other),
+            Error::ByteClass => write!(
+                f,
+                "byte literals and classes are only supported over raw bytes"
+            ),
+            Error::TooManyStates(nb_states) => write!(
+                f,
+                "the automaton has {} states, which exceeds the limit of {}",
+                nb_states, MAX_STATES
+            ),
+            Error::Normalization(err) => write!(f, "{}", err),
+            Error::UnknownDictionary(name) => write!(f, "unknown dictionary: {}", name),
+            Error::UnknownMacro(name) => write!(f, "unknown macro: {}", name),
+            Error::MacroCycle(cycle) => write!(f, "cyclic macros: {}", cycle.join(" -> ")),
+            Error::InMacro(name, err) => write!(f, "macro {}: {}", name, err),
+            Error::InPattern(pattern, err) => write!(f, "pattern {}: {}", pattern, err),
+        }
+    }
+}
+
+impl error::Error for Error {}
@@ -0,0 +1,123 @@
+use super::Error;
+use super::replacements::{Replacements, name_len};
+
+/// Prefix of the names of the groups that replace variable bindings of a
+/// regex formula.
+pub const BINDING_PREFIX: &str = "__binding_";
+
+/// Prefix of the names of the groups that replace the empty language `∅` of a
+/// regex formula, which can't be written with regex_syntax.
+pub const EMPTY_PREFIX: &str = "__empty_";
+
+//  _____                           _
+// |  ___|__  _ __ _ __ ___  _   _| | __ _
+// | |_ / _ \| '__| '_ ` _ \| | | | |/ _` |
+// |  _| (_) | |  | | | | | | |_| | | (_| |
+// |_|  \___/|_|  |_| |_| |_|\__,_|_|\__,_|
+//
+
+/// Variable bindings `x{...}` of a regex formula, as written in the spanner
+/// literature, which are replaced with named groups that can be parsed by
+/// regex_syntax. Each binding gets a distinct group, thus a same variable can
+/// be bound in several branches of an alternation.
+///
+/// The variable of a binding is the longest name made of letters, digits and
+/// underscores before a brace, such that `ab{c}` binds `ab`. Braces of counted
+/// repetitions such as `a{2,3}` are left unchanged. The symbols `∨`, `·`, `ε`
+/// and `∅` of the literature are also accepted for alternation,
+/// concatenation, the empty word and the empty language.
+pub struct Bindings {
+    /// Name of the variable of each binding, in order of appearance.
+    pub names: Vec<String>,
+    pub replacements: Replacements,
+}
+
+impl Bindings {
+    pub fn replace(formula: &str) -> Result<(String, Bindings), Error> {
+        let mut names = Vec::new();
+        let mut num_empty = 0;
+
+        // Positions of the bindings that are not closed yet.
+        let mut open = Vec::new();
+
+        let (result, replacements) = Replacements::apply::<_, Error>(formula, |rest| {
+            let position = formula.len() - rest.len();
+            let first = rest.chars().next().unwrap();
+
+            let symbol = match first {
+                '∨' => Some("|"),
+                '·' => Some(""),
+                'ε' => Some("(?:)"),
+                _ => None,
+            };
+
+            if let Some(symbol) = symbol {
+                return Ok(Some((first.len_utf8(), symbol.to_string())));
+            }
+
+            if first == '∅' {
+                let group = format!("(?P<{}{}>)", EMPTY_PREFIX, num_empty);
+                num_empty += 1;
+                return Ok(Some((first.len_utf8(), group)));
+            }
+
+            if first == '}' {
+                return Ok(open.pop().map(|_| (1, ")".to_string())));
+            }
+
+            // Counted repetitions are skipped at once.
+            if first == '{' {
+                return Ok(repetition_len(rest).map(|len| (len, rest[..len].to_string())));
+            }
+
+            let name_len = name_len(rest);
+
+            if name_len == 0
+                || first.is_ascii_digit()
+                || !rest[name_len..].starts_with('{')
+                || repetition_len(&rest[name_len..]).is_some()
+            {
+                return Ok(None);
+            }
+
+            let group = format!("(?P<{}{}>", BINDING_PREFIX, names.len());
+            names.push(rest[..name_len].to_string());
+            open.push(position);
+            Ok(Some((name_len + 1, group)))
+        })?;
+
+        if let Some(&position) = open.first() {
+            return Err(Error::Syntax {
+                message: "unclosed variable binding".to_string(),
+                position,
+            });
+        }
+
+        Ok((
+            result,
+            Bindings {
+                names,
+                replacements,
+            },
+        ))
+    }
+}
+
+/// Length of a counted repetition `{n}`, `{n,}` or `{n,m}` at the beginning
+/// of a text.
+fn repetition_len(text: &str) -> Option<usize> {
+    let inner = text.strip_prefix('{')?;
+    let len = inner.find('}')?;
+    let mut bounds = inner[..len].splitn(2, ',').map(str::trim);
+
+    let is_number = |bound: &str| !bound.is_empty() && bound.bytes().all(|x| x.is_ascii_digit());
+    let min_is_number = bounds.next().is_some_and(is_number);
+    let max_is_valid = bounds
+        .next()
+        .is_none_or(|max| max.is_empty() || is_number(max));
+
+    match min_is_number && max_is_valid {
+        true => Some(len + 2),
+        false => None,
+    }
+}
@@ -2,9 +2,8 @@
 /// linearized language out of a regexp's HIR, and finaly convert this
 /// expression to a variable NFA.
 use std::collections::LinkedList;
-use std::rc::Rc;
+use std::sync::Arc;
 
-use super::super::automaton::Automaton;
 use super::super::automaton::Label;
 use super::parse::Hir;
 
@@ -26,7 +25,7 @@ pub struct GlushkovFactors {
 #[derive(Clone, Debug)]
 pub struct GlushkovTerm {
     id: usize,
-    label: Rc<Label>,
+    label: Arc<Label>,
 }
 
 #[derive(Clone, Debug)]
@@ -39,8 +38,10 @@ pub struct LocalLang {
 /// factors of size 2, its prefixes and suffixes and wether it contains the
 /// empty word or not.
 impl LocalLang {
-    /// Create an automaton that recognise the same langage.
-    pub fn into_automaton(self) -> Automaton {
+    /// Get the number of states, the transitions and the final states of an
+    /// automaton that recognise the same langage.
+    pub fn into_parts(self) -> (usize, Vec<(usize, Arc<Label>, usize)>, Vec<usize>) {
+        let nb_states = self.nb_states();
         let iner_transitions = self
             .factors
             .f
@@ -52,14 +53,19 @@ impl LocalLang {
             .into_iter()
             .map(|target| (0, target.label, target.id + 1));
 
-        let transitions = iner_transitions.chain(pref_transitions);
+        let transitions = iner_transitions.chain(pref_transitions).collect();
         let mut finals: Vec<usize> = self.factors.d.into_iter().map(|x| x.id + 1).collect();
 
         if self.factors.g {
             finals.push(0);
         }
 
-        Automaton::new(self.nb_terms + 1, transitions, finals.into_iter())
+        (nb_states, transitions, finals)
+    }
+
+    /// Number of states of the automaton built by `into_parts`.
+    pub fn nb_states(&self) -> usize {
+        self.nb_terms + 1
     }
 
     /// Return a language representing the input Hir.
@@ -84,7 +90,7 @@ impl LocalLang {
 
     /// Register a new atom in the local language and return the associated
     /// term.
-    fn register_label(&mut self, label: Rc<Label>, id_offset: usize) -> GlushkovTerm {
+    fn register_label(&mut self, label: Arc<Label>, id_offset: usize) -> GlushkovTerm {
         self.nb_terms += 1;
         GlushkovTerm {
             id: self.nb_terms + id_offset - 1,
@@ -94,7 +100,7 @@ impl LocalLang {
 
     /// Return a local language representing an expression containing a single
     /// term.
-    fn label(label: Rc<Label>, id_offset: usize) -> LocalLang {
+    fn label(label: Arc<Label>, id_offset: usize) -> LocalLang {
         let mut lang = LocalLang::empty();
         let term = lang.register_label(label, id_offset);
         lang.factors.p.push_back(term.clone());
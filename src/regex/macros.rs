@@ -0,0 +1,205 @@
+use std::collections::HashMap;
+use std::io;
+use std::io::prelude::*;
+
+use regex_syntax::ast;
+
+use super::Error;
+use super::dictionary::Placeholders;
+use super::formula::Bindings;
+use super::replacements::{Replacements, name_len};
+
+/// Fragments that are always available, they can be overridden.
+const BUILTIN: &[(&str, &str)] = &[
+    ("digit", r"[0-9]"),
+    ("integer", r"[+-]?[0-9]+"),
+    ("number", r"[+-]?[0-9]+(?:\.[0-9]+)?"),
+    ("word", r"\w+"),
+    (
+        "date",
+        r"[0-9]{4}-(?:0[1-9]|1[0-2])-(?:0[1-9]|[12][0-9]|3[01])",
+    ),
+    ("time", r"(?:[01][0-9]|2[0-3]):[0-5][0-9](?::[0-5][0-9])?"),
+    ("ipv4_byte", r"25[0-5]|2[0-4][0-9]|1[0-9]{2}|[1-9]?[0-9]"),
+    ("ipv4", r"(?:{{ipv4_byte}}\.){3}{{ipv4_byte}}"),
+    ("email_local", r"[\w.%+-]+"),
+    ("domain", r"(?:[\w-]+\.)+[a-zA-Z]{2,}"),
+    ("email", r"{{email_local}}@{{domain}}"),
+];
+
+//  __  __
+// |  \/  | __ _  ___ _ __ ___  ___
+// | |\/| |/ _` |/ __| '__/ _ \/ __|
+// | |  | | (_| | (__| | | (_) \__ \
+// |_|  |_|\__,_|\___|_|  \___/|___/
+//
+
+/// Named fragments of patterns, a pattern refers to a fragment with
+/// `{{name}}`, which is expanded into a non-capturing group before the
+/// pattern is parsed. Fragments may refer to other fragments.
+///
+/// A small library of fragments is built in: `digit`, `integer`, `number`,
+/// `word`, `date` (such as 2019-03-26), `time` (such as 09:30 or 09:30:00),
+/// `ipv4`, `email_local`, `domain` and `email`.
+#[derive(Clone, Debug)]
+pub struct Macros {
+    definitions: HashMap<String, String>,
+}
+
+impl Default for Macros {
+    fn default() -> Macros {
+        Macros::builtin()
+    }
+}
+
+impl Macros {
+    /// No fragment, not even the built-in ones.
+    pub fn empty() -> Macros {
+        Macros {
+            definitions: HashMap::new(),
+        }
+    }
+
+    /// The library of built-in fragments.
+    pub fn builtin() -> Macros {
+        let definitions = BUILTIN
+            .iter()
+            .map(|&(name, regex)| (name.to_string(), regex.to_string()))
+            .collect();
+
+        Macros { definitions }
+    }
+
+    /// Define a fragment, which overrides any fragment with the same name.
+    pub fn with_definition(mut self, name: impl Into<String>, regex: impl Into<String>) -> Macros {
+        self.definitions.insert(name.into(), regex.into());
+        self
+    }
+
+    /// Add the definitions of another set of fragments, which override the
+    /// fragments with the same name.
+    pub fn with_macros(mut self, macros: Macros) -> Macros {
+        self.definitions.extend(macros.definitions);
+        self
+    }
+
+    /// Read a definitions file with one `name = pattern` definition per line.
+    /// Spaces around the pattern are removed, empty lines and lines starting
+    /// with `#` are ignored.
+    pub fn read(input: impl BufRead) -> io::Result<Macros> {
+        let mut macros = Macros::empty();
+
+        for (line_number, line) in input.lines().enumerate() {
+            let line = line?;
+            let line = line.trim();
+
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let invalid = |message: String| {
+                io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("line {}: {}", line_number + 1, message),
+                )
+            };
+
+            let (name, regex) = match line.find('=') {
+                None => return Err(invalid("expected a definition `name = pattern`".into())),
+                Some(i) => (line[..i].trim(), line[i + 1..].trim()),
+            };
+
+            if name.is_empty() || name_len(name) != name.len() {
+                return Err(invalid(format!(
+                    "invalid name `{}`, only letters, digits and underscores are allowed",
+                    name
+                )));
+            }
+
+            if macros.definitions.contains_key(name) {
+                return Err(invalid(format!("`{}` is already defined", name)));
+            }
+
+            macros
+                .definitions
+                .insert(name.to_string(), regex.to_string());
+        }
+
+        Ok(macros)
+    }
+
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.definitions.get(name).map(String::as_str)
+    }
+
+    /// Expand the fragments a pattern refers to, fragments are not searched
+    /// for in escape sequences and character classes.
+    ///
+    /// An error is reported if a fragment is not defined, if fragments refer
+    /// to each other in a cycle or if a fragment is not a valid pattern.
+    pub fn expand(&self, regex: &str) -> Result<(String, Replacements), Error> {
+        self.expand_from(regex, &mut Vec::new())
+    }
+
+    /// Expand a pattern within the expansion of the fragments of `stack`.
+    fn expand_from(
+        &self,
+        regex: &str,
+        stack: &mut Vec<String>,
+    ) -> Result<(String, Replacements), Error> {
+        Replacements::apply(regex, |rest| {
+            let name_len = match rest.strip_prefix("{{") {
+                None => return Ok(None),
+                Some(rest) => name_len(rest),
+            };
+
+            if name_len == 0 || !rest[2 + name_len..].starts_with("}}") {
+                return Ok(None);
+            }
+
+            let name = &rest[2..2 + name_len];
+
+            let body = self
+                .get(name)
+                .ok_or_else(|| Error::UnknownMacro(name.to_string()))?;
+
+            if let Some(start) = stack.iter().position(|other| other == name) {
+                let mut cycle = stack[start..].to_vec();
+                cycle.push(name.to_string());
+                return Err(Error::MacroCycle(cycle));
+            }
+
+            stack.push(name.to_string());
+            let expanded = self.expand_from(body, stack);
+            stack.pop();
+
+            let in_macro = |err| Error::InMacro(name.to_string(), Box::new(err));
+            let (expanded, replacements) = expanded.map_err(|err| match err {
+                // Only report the innermost fragment.
+                Error::InMacro(..) | Error::MacroCycle(_) => err,
+                err => in_macro(err),
+            })?;
+
+            // Check that the fragment is valid by itself, to report errors at
+            // their position in the fragment. Fragments may be written as
+            // regex formulas, which leaves valid patterns unchanged.
+            let (formula, bindings) = Bindings::replace(&expanded).map_err(in_macro)?;
+            let (parsed, placeholders) = Placeholders::replace(&formula);
+
+            if let Err(err) = ast::parse::Parser::new().parse(&parsed) {
+                let position = placeholders
+                    .replacements
+                    .original_position(err.span().start.offset);
+                let position = bindings.replacements.original_position(position);
+                let position = replacements.original_position(position);
+
+                return Err(in_macro(Error::Syntax {
+                    message: err.kind().to_string(),
+                    position,
+                }));
+            }
+
+            Ok(Some((name_len + 4, format!("(?:{})", expanded))))
+        })
+    }
+}
@@ -1,24 +1,217 @@
+mod assertions;
+mod dictionary;
+mod error;
+mod formula;
 mod glushkov;
+mod macros;
 mod parse;
+mod replacements;
+
+pub use dictionary::Dictionary;
+pub use error::{Error, MAX_STATES};
+pub use macros::Macros;
+
+use std::collections::HashMap;
 
 use super::automaton::Automaton;
+#[cfg(test)]
+use super::mapping::SpannerEnumerator;
+#[cfg(test)]
+use super::mapping::indexed_dag::{IndexedDag, TrimmingStrategy};
 
-pub fn compile(regex: &str) -> Automaton {
-    let hir = parse::Hir::from_regex(&regex, false);
+/// Compile a pattern into an automaton matching all the substrings of a text
+/// that match the pattern. If the pattern has no named group, the whole match
+/// is captured by an implicit group called "match".
+pub fn compile(regex: &str) -> Result<Automaton, Error> {
+    Compiler::unicode().compile(regex)
+}
+
+/// Compile several patterns into a single automaton, whose mappings are the
+/// mappings of each pattern. The pattern each mapping comes from is given by
+/// `Mapping::get_pattern`, variables of distinct patterns being distinct.
+pub fn compile_many(regexes: &[&str]) -> Result<Automaton, Error> {
+    Compiler::unicode().compile_many(regexes)
+}
+
+/// Same as `compile_many` for patterns matching raw bytes, see
+/// `compile_bytes`.
+pub fn compile_many_bytes(regexes: &[&str]) -> Result<Automaton, Error> {
+    Compiler::bytes().compile_many(regexes)
+}
 
-    glushkov::LocalLang::from_hir(hir, 0).into_automaton()
+/// Compile a pattern into an automaton matching all the substrings of a
+/// sequence of raw bytes, where each byte is read as the character with the
+/// same code point. Unicode mode is disabled by default, and characters
+/// matched with `(?u)` are matched through their UTF-8 encoding.
+pub fn compile_bytes(regex: &str) -> Result<Automaton, Error> {
+    Compiler::bytes().compile(regex)
 }
 
-pub fn compile_raw(regex: &str) -> Automaton {
-    let hir = parse::Hir::from_regex(&regex, true);
+/// Compile a regex formula, where variables are bound with `x{...}` as in the
+/// spanner literature, see `Syntax::Formula`.
+pub fn compile_formula(formula: &str) -> Result<Automaton, Error> {
+    Compiler::unicode()
+        .with_syntax(Syntax::Formula)
+        .compile(formula)
+}
+
+/// Compile a pattern into an automaton matching exactly the pattern, without
+/// any implicit group.
+///
+/// Anchors and word boundaries are evaluated at the boundaries of the text
+/// read by the automaton: when it is run over each substring of a text, as
+/// done by `NaiveEnumQuadratic`, `^` and `$` match at the start and at the end
+/// of the substring rather than of the whole text.
+pub fn compile_raw(regex: &str) -> Result<Automaton, Error> {
+    let hir = parse::Hir::from_regex(
+        regex,
+        Syntax::Regex,
+        true,
+        false,
+        &mut parse::Variables::new(),
+        &HashMap::new(),
+    )?;
+
+    into_automaton(hir)
+}
+
+/// Syntax in which variables of a pattern are written.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Syntax {
+    /// Named groups `(?P<x>...)` of Rust's regex crate.
+    Regex,
+    /// Variable bindings `x{...}` of regex formulas, such that a same variable
+    /// can be bound in several branches of an alternation. Other constructs
+    /// are written as in Rust's regex crate, and counted repetitions such as
+    /// `a{2,3}` are still supported.
+    Formula,
+}
+
+//   ____                      _ _
+//  / ___|___  _ __ ___  _ __ (_) | ___ _ __
+// | |   / _ \| '_ ` _ \| '_ \| | |/ _ \ '__|
+// | |__| (_) | | | | | | |_) | | |  __/ |
+//  \____\___/|_| |_| |_| .__/|_|_|\___|_|
+//                     |_|
+
+/// Compile patterns that may refer to dictionaries and macros: a placeholder
+/// `(?&name)` matches any entry of the dictionary called `name`, which is
+/// captured by a variable called `name`, and `{{name}}` is replaced with the
+/// definition of the macro called `name`.
+#[derive(Clone, Debug)]
+pub struct Compiler {
+    bytes: bool,
+    syntax: Syntax,
+    dictionaries: HashMap<String, Dictionary>,
+    macros: Macros,
+}
+
+impl Compiler {
+    /// Compile patterns matching unicode text, see `compile`.
+    pub fn unicode() -> Compiler {
+        Compiler {
+            bytes: false,
+            syntax: Syntax::Regex,
+            dictionaries: HashMap::new(),
+            macros: Macros::builtin(),
+        }
+    }
+
+    /// Compile patterns matching raw bytes, see `compile_bytes`.
+    pub fn bytes() -> Compiler {
+        Compiler {
+            bytes: true,
+            ..Compiler::unicode()
+        }
+    }
+
+    /// Choose the syntax patterns are written in, named groups of Rust's regex
+    /// crate by default.
+    pub fn with_syntax(mut self, syntax: Syntax) -> Compiler {
+        self.syntax = syntax;
+        self
+    }
+
+    /// Add a dictionary that patterns can refer to as `(?&name)`.
+    pub fn with_dictionary(mut self, name: impl Into<String>, dictionary: Dictionary) -> Compiler {
+        self.dictionaries.insert(name.into(), dictionary);
+        self
+    }
+
+    /// Add macros that patterns can refer to as `{{name}}`, which override
+    /// the macros with the same name, including the built-in ones.
+    pub fn with_macros(mut self, macros: Macros) -> Compiler {
+        self.macros = self.macros.with_macros(macros);
+        self
+    }
+
+    pub fn compile(&self, regex: &str) -> Result<Automaton, Error> {
+        let hir = self.parse(regex, &mut parse::Variables::new())?;
+        into_automaton(hir)
+    }
+
+    /// Compile several patterns into a single automaton, see `compile_many`.
+    pub fn compile_many(&self, regexes: &[&str]) -> Result<Automaton, Error> {
+        let mut variables = parse::Variables::new();
+        let mut hir = parse::Hir::Empty;
+
+        for (pattern, regex) in regexes.iter().enumerate() {
+            variables.start_pattern(pattern);
+            let branch = self
+                .parse(regex, &mut variables)
+                .map_err(|err| Error::InPattern(pattern, Box::new(err)))?;
+            hir = parse::Hir::Alternation(Box::new(hir), Box::new(branch));
+        }
+
+        into_automaton(hir)
+    }
+
+    /// Expand the macros of a pattern and parse it, syntax errors being
+    /// reported at their position in the original pattern.
+    fn parse(&self, regex: &str, variables: &mut parse::Variables) -> Result<parse::Hir, Error> {
+        let (expanded, replacements) = self.macros.expand(regex)?;
+
+        parse::Hir::from_regex(
+            &expanded,
+            self.syntax,
+            false,
+            self.bytes,
+            variables,
+            &self.dictionaries,
+        )
+        .map_err(|err| match err {
+            Error::Syntax { message, position } => Error::Syntax {
+                message,
+                position: replacements.original_position(position),
+            },
+            err => err,
+        })
+    }
+}
+
+fn into_automaton(hir: parse::Hir) -> Result<Automaton, Error> {
+    let lang = glushkov::LocalLang::from_hir(hir, 0);
+    let (nb_states, transitions, finals) = lang.into_parts();
+    let (nb_states, transitions, finals) = assertions::eliminate(nb_states, transitions, finals);
+
+    if nb_states > MAX_STATES {
+        return Err(Error::TooManyStates(nb_states));
+    }
+
+    let automaton = Automaton::new(nb_states, transitions.into_iter(), finals.into_iter());
 
-    glushkov::LocalLang::from_hir(hir, 0).into_automaton()
+    // Groups within repetitions are assigned in loops.
+    match automaton.is_normalized() {
+        true => Ok(automaton),
+        false => automaton.normalize().map_err(Error::Normalization),
+    }
 }
 
 #[cfg(test)]
 pub fn is_match(regex: &str, text: &str) -> bool {
-    let automaton = compile(&regex);
-    let matches = compile_matches(automaton, text, 1);
+    let automaton = compile(regex).unwrap();
+    let mut matches = IndexedDag::new(automaton, text, 1, TrimmingStrategy::FullTrimming, false);
+    matches.preprocess().unwrap();
 
     let ret = matches.iter().next().is_some();
     ret
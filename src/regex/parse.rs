@@ -1,15 +1,63 @@
+use std::borrow::Cow;
 use std::collections::HashMap;
-use std::rc::Rc;
+use std::sync::Arc;
 
 use regex_syntax;
+use regex_syntax::hir::Anchor as LibAnchor;
+use regex_syntax::hir::Class as LibClass;
 use regex_syntax::hir::GroupKind as LibGroup;
 use regex_syntax::hir::HirKind as LibHir;
+use regex_syntax::hir::Literal as LibLiteral;
 use regex_syntax::hir::RepetitionKind as LibRepKind;
 use regex_syntax::hir::RepetitionRange as LibRepRange;
+use regex_syntax::hir::WordBoundary as LibWordBoundary;
+use regex_syntax::hir::{ClassBytes, ClassBytesRange, ClassUnicode};
+use regex_syntax::utf8::Utf8Sequences;
 
-use super::super::automaton::atom::Atom;
 use super::super::automaton::Label;
+use super::super::automaton::assertion::Assertion;
+use super::super::automaton::atom::Atom;
 use super::super::mapping::{Marker, Variable};
+use super::dictionary::{Dictionary, PLACEHOLDER_PREFIX, Placeholders};
+use super::formula::{BINDING_PREFIX, Bindings, EMPTY_PREFIX};
+use super::{Error, Syntax};
+
+/// Variables created while parsing patterns, which are numbered in their
+/// order of appearance. Variables of distinct patterns are distinct even if
+/// they have the same name.
+pub struct Variables {
+    names: HashMap<String, Arc<Variable>>,
+    count: usize,
+    pattern: usize,
+}
+
+impl Variables {
+    pub fn new() -> Variables {
+        Variables {
+            names: HashMap::new(),
+            count: 0,
+            pattern: 0,
+        }
+    }
+
+    /// Create the variables of another pattern from now on.
+    pub fn start_pattern(&mut self, pattern: usize) {
+        self.names.clear();
+        self.pattern = pattern;
+    }
+
+    /// Get the variable of the current pattern with given name.
+    fn get(&mut self, name: String) -> Arc<Variable> {
+        if let Some(var) = self.names.get(&name) {
+            return var.clone();
+        }
+
+        let var = Arc::new(Variable::new(name.clone(), self.count).with_pattern(self.pattern));
+        self.names.insert(name, var.clone());
+        self.count += 1;
+        var
+    }
+}
 
 /// A simple Hir, with branchements of arity at most 2 and at little redundancy
 /// as possible.
@@ -18,7 +66,7 @@ pub enum Hir {
     /// Empty langage
     Empty,
     /// Langage of words of length 1
-    Label(Rc<Label>), // embeded into an Rc to avoid duplicating heavy complex literals
+    Label(Arc<Label>), // embeded into an Arc to avoid duplicating heavy complex literals
     /// Concatenation of two langages
     Concat(Box<Hir>, Box<Hir>),
     /// Union of two langages
@@ -30,29 +78,73 @@ pub enum Hir {
 }
 
 impl Hir {
-    pub fn from_regex(regex: &str, raw: bool) -> Hir {
-        let (anchor_begin, anchor_end, regex) = if raw {
-            (true, true, regex.to_string())
-        } else {
-            Hir::reformat(regex)
+    /// Parse a pattern written with the given syntax, which matches raw bytes
+    /// instead of unicode characters if `bytes` is set. Its variables are
+    /// created in `variables`, and its placeholders `(?&name)` refer to
+    /// `dictionaries`.
+    pub fn from_regex(
+        regex: &str,
+        syntax: Syntax,
+        raw: bool,
+        bytes: bool,
+        variables: &mut Variables,
+        dictionaries: &HashMap<String, Dictionary>,
+    ) -> Result<Hir, Error> {
+        let (regex, bindings) = match syntax {
+            Syntax::Regex => (Cow::Borrowed(regex), None),
+            Syntax::Formula => {
+                let (regex, bindings) = Bindings::replace(regex)?;
+                (Cow::Owned(regex), Some(bindings))
+            }
         };
 
-        let mut variables = HashMap::new();
+        let (spliced, placeholders) = Placeholders::replace(&regex);
 
         let lib_hir = regex_syntax::ParserBuilder::new()
             .dot_matches_new_line(true)
+            .unicode(!bytes)
+            .allow_invalid_utf8(bytes)
             .build()
-            .parse(&regex)
-            .expect("Invalid regexp syntax");
-        let hir = Hir::from_lib_hir(lib_hir, &mut variables);
+            .parse(&spliced)
+            .map_err(|err| match Error::from_lib_error(err, 0) {
+                Error::Syntax { message, position } => {
+                    let position = placeholders.replacements.original_position(position);
+
+                    Error::Syntax {
+                        message,
+                        position: bindings.as_ref().map_or(position, |bindings| {
+                            bindings.replacements.original_position(position)
+                        }),
+                    }
+                }
+                err => err,
+            })?;
+
+        let fragments = placeholders
+            .names
+            .into_iter()
+            .map(|name| match dictionaries.get(&name) {
+                None => Err(Error::UnknownDictionary(name)),
+                Some(dictionary) => Ok((dictionary.to_hir(bytes), name)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let (anchor_begin, anchor_end, lib_hir) = if raw {
+            (true, true, lib_hir)
+        } else {
+            Hir::strip_anchors(lib_hir)
+        };
+
+        let bindings = bindings.map(|bindings| bindings.names);
+        let hir = Hir::from_lib_hir(lib_hir, variables, &fragments, bindings.as_deref(), bytes)?;
 
         if raw {
-            return hir;
+            return Ok(hir);
         }
 
-        let hir = match variables.len() {
+        let hir = match variables.names.len() {
             0 => {
-                let var = Rc::new(Variable::new("match".to_string(), 0));
+                let var = variables.get("match".to_string());
                 let marker_open = Label::Assignation(Marker::Open(var.clone()));
                 let marker_close = Label::Assignation(Marker::Close(var));
 
@@ -64,7 +156,7 @@ impl Hir {
             _ => hir,
         };
 
-        let any = match regex_syntax::hir::Hir::any(false).into_kind() {
+        let any = match regex_syntax::hir::Hir::any(bytes).into_kind() {
             LibHir::Class(x) => x,
             _ => panic!("LibHir broken!"),
         };
@@ -79,63 +171,135 @@ impl Hir {
             ),
         };
 
-        match anchor_end {
+        Ok(match anchor_end {
             true => hir,
             false => Hir::concat(
                 hir,
                 Hir::option(Hir::closure(Hir::label(Label::Atom(Atom::Class(any))))),
             ),
-        }
+        })
     }
 
     /// Construct an Hir from regex_syntax's Hir format.
     ///
-    /// It also takes as an input the counter of already created variables and
-    /// return the count of variables that have been created in the generated
-    /// Hir.
+    /// Variables of named groups are created in `variables`, a same name
+    /// referring to the same variable. Placeholders are replaced with the Hir
+    /// of their dictionary in `fragments`, captured by a variable named after
+    /// the dictionary. Groups that replace variable bindings of a regex formula
+    /// are captured by the variable of their binding in `bindings`, which is
+    /// only given for a regex formula, and groups that replace `∅` match the
+    /// empty language.
+    ///
+    /// If `bytes` is set, unicode characters are matched through their UTF-8
+    /// encoding.
     fn from_lib_hir(
         hir: regex_syntax::hir::Hir,
-        variables: &mut HashMap<String, Rc<Variable>>,
-    ) -> Hir {
-        match hir.into_kind() {
+        variables: &mut Variables,
+        fragments: &[(Hir, String)],
+        bindings: Option<&[String]>,
+        bytes: bool,
+    ) -> Result<Hir, Error> {
+        Ok(match hir.into_kind() {
             LibHir::Empty => Hir::epsilon(),
 
+            LibHir::Literal(LibLiteral::Byte(_)) | LibHir::Class(LibClass::Bytes(_)) if !bytes => {
+                return Err(Error::ByteClass);
+            }
+
+            LibHir::Literal(LibLiteral::Unicode(x)) if bytes && !x.is_ascii() => {
+                let mut buffer = [0; 4];
+
+                x.encode_utf8(&mut buffer)
+                    .bytes()
+                    .fold(Hir::epsilon(), |acc, byte| {
+                        Hir::concat(
+                            acc,
+                            Hir::label(Label::Atom(Atom::Literal(LibLiteral::Byte(byte)))),
+                        )
+                    })
+            }
+
+            LibHir::Class(LibClass::Unicode(class)) if bytes => Hir::utf8_class(&class),
+
             LibHir::Literal(lit) => Hir::label(Label::Atom(Atom::Literal(lit))),
 
             LibHir::Class(class) => Hir::label(Label::Atom(Atom::Class(class))),
 
+            LibHir::Anchor(anchor) => Hir::label(Label::Assertion(match anchor {
+                LibAnchor::StartLine => Assertion::StartLine,
+                LibAnchor::EndLine => Assertion::EndLine,
+                LibAnchor::StartText => Assertion::StartText,
+                LibAnchor::EndText => Assertion::EndText,
+            })),
+
+            // Word characters are unicode ones over a text and ASCII ones over
+            // raw bytes, the other kind of boundaries can't be mixed in.
+            LibHir::WordBoundary(LibWordBoundary::Unicode) if !bytes => {
+                Hir::label(Label::Assertion(Assertion::WordBoundary))
+            }
+
+            LibHir::WordBoundary(LibWordBoundary::UnicodeNegate) if !bytes => {
+                Hir::label(Label::Assertion(Assertion::NotWordBoundary))
+            }
+
+            LibHir::WordBoundary(LibWordBoundary::Ascii) if bytes => {
+                Hir::label(Label::Assertion(Assertion::WordBoundary))
+            }
+
+            LibHir::WordBoundary(LibWordBoundary::AsciiNegate) if bytes => {
+                Hir::label(Label::Assertion(Assertion::NotWordBoundary))
+            }
+
             LibHir::Repetition(rep) => {
-                let hir = Hir::from_lib_hir(*rep.hir, variables);
-                let new_hir = match rep.kind {
+                let hir = Hir::from_lib_hir(*rep.hir, variables, fragments, bindings, bytes)?;
+
+                match rep.kind {
                     LibRepKind::ZeroOrOne => Hir::option(hir),
                     LibRepKind::ZeroOrMore => Hir::option(Hir::closure(hir)),
                     LibRepKind::OneOrMore => Hir::closure(hir),
                     LibRepKind::Range(range) => Hir::repetition(hir, range),
-                };
-                new_hir
+                }
             }
 
             LibHir::Group(group) => {
-                let subtree = Hir::from_lib_hir(*group.hir, variables);
-                let new_hir = match group.kind {
+                let subtree = Hir::from_lib_hir(*group.hir, variables, fragments, bindings, bytes)?;
+                match group.kind {
                     LibGroup::NonCapturing | LibGroup::CaptureIndex(_) => subtree,
+                    LibGroup::CaptureName { name, index: _ }
+                        if name.starts_with(PLACEHOLDER_PREFIX) =>
+                    {
+                        let id: usize = name[PLACEHOLDER_PREFIX.len()..].parse().unwrap();
+                        let (fragment, name) = &fragments[id];
+                        let var = variables.get(name.clone());
+
+                        let marker_open = Label::Assignation(Marker::Open(var.clone()));
+                        let marker_close = Label::Assignation(Marker::Close(var));
+
+                        Hir::concat(
+                            Hir::Concat(
+                                Box::new(Hir::label(marker_open)),
+                                Box::new(fragment.clone()),
+                            ),
+                            Hir::label(marker_close),
+                        )
+                    }
+                    LibGroup::CaptureName { name, index: _ }
+                        if bindings.is_some() && name.starts_with(EMPTY_PREFIX) =>
+                    {
+                        Hir::Empty
+                    }
                     LibGroup::CaptureName { name, index: _ } => {
-                        let real_name = match name.find("__") {
-                            None => name.clone(),
-                            Some(i) => name[0..i].to_string(),
+                        let binding = (name.strip_prefix(BINDING_PREFIX))
+                            .and_then(|id| id.parse::<usize>().ok())
+                            .and_then(|id| bindings.and_then(|bindings| bindings.get(id)));
+
+                        let real_name = match (binding, name.find("__")) {
+                            (Some(binding), _) => binding.clone(),
+                            (None, None) => name.clone(),
+                            (None, Some(i)) => name[0..i].to_string(),
                         };
 
-                        let var =
-                            variables
-                                .get(&real_name)
-                                .map(|v| v.clone())
-                                .unwrap_or_else(|| {
-                                    let x =
-                                        Rc::new(Variable::new(real_name.clone(), variables.len()));
-                                    variables.insert(real_name, x.clone());
-
-                                    x
-                                });
+                        let var = variables.get(real_name);
 
                         let marker_open = Label::Assignation(Marker::Open(var.clone()));
                         let marker_close = Label::Assignation(Marker::Close(var));
@@ -145,23 +309,21 @@ impl Hir {
                             Hir::label(marker_close),
                         )
                     }
-                };
-
-                new_hir
+                }
             }
 
-            LibHir::Concat(sub) => sub.into_iter().fold(Hir::epsilon(), |acc, branch| {
-                let add_hir = Hir::from_lib_hir(branch, variables);
-                Hir::concat(acc, add_hir)
-            }),
+            LibHir::Concat(sub) => sub.into_iter().try_fold(Hir::epsilon(), |acc, branch| {
+                let add_hir = Hir::from_lib_hir(branch, variables, fragments, bindings, bytes)?;
+                Ok(Hir::concat(acc, add_hir))
+            })?,
 
-            LibHir::Alternation(sub) => sub.into_iter().fold(Hir::Empty, |acc, branch| {
-                let add_hir = Hir::from_lib_hir(branch, variables);
-                Hir::alternation(acc, add_hir)
-            }),
+            LibHir::Alternation(sub) => sub.into_iter().try_fold(Hir::Empty, |acc, branch| {
+                let add_hir = Hir::from_lib_hir(branch, variables, fragments, bindings, bytes)?;
+                Ok(Hir::alternation(acc, add_hir))
+            })?,
 
-            other => panic!("Not implemented: {:?}", other),
-        }
+            other => return Err(Error::Unsupported(other)),
+        })
     }
 
     fn epsilon() -> Hir {
@@ -169,7 +331,7 @@ impl Hir {
     }
 
     fn label(label: Label) -> Hir {
-        Hir::Label(Rc::new(label))
+        Hir::Label(Arc::new(label))
     }
 
     fn option(hir: Hir) -> Hir {
@@ -198,7 +360,7 @@ impl Hir {
         let mut result = Hir::epsilon();
 
         for i in 0..min {
-            if i == min - 1 && max == None {
+            if i == min - 1 && max.is_none() {
                 // If the repetition has no upper bound, the last repetition
                 // of the input langage is replaced with a closure. It saves
                 // a few states to do it here.
@@ -221,21 +383,85 @@ impl Hir {
         result
     }
 
-    fn reformat(regex: &str) -> (bool, bool, String) {
-        let mut regex = String::from(regex);
+    /// Build an Hir matching the UTF-8 encoding of characters of a class.
+    /// Characters encoded over a single byte are gathered in a single atom.
+    fn utf8_class(class: &ClassUnicode) -> Hir {
+        let mut ascii = ClassBytes::empty();
+        let mut result = Hir::Empty;
+
+        for range in class.iter() {
+            for sequence in Utf8Sequences::new(range.start(), range.end()) {
+                match sequence.as_slice() {
+                    [byte_range] => {
+                        ascii.push(ClassBytesRange::new(byte_range.start, byte_range.end))
+                    }
+                    byte_ranges => {
+                        let encoding =
+                            byte_ranges.iter().fold(Hir::epsilon(), |acc, byte_range| {
+                                let class = ClassBytes::new(vec![ClassBytesRange::new(
+                                    byte_range.start,
+                                    byte_range.end,
+                                )]);
+
+                                Hir::concat(
+                                    acc,
+                                    Hir::label(Label::Atom(Atom::Class(LibClass::Bytes(class)))),
+                                )
+                            });
+
+                        result = Hir::alternation(result, encoding);
+                    }
+                }
+            }
+        }
+
+        if ascii.iter().next().is_some() {
+            result = Hir::alternation(
+                result,
+                Hir::label(Label::Atom(Atom::Class(LibClass::Bytes(ascii)))),
+            );
+        }
+
+        result
+    }
 
-        let anchor_begin = Some(&b'^') == regex.as_bytes().first();
-        let anchor_end = Some(&b'$') == regex.as_bytes().last();
+    /// Remove anchors to the beginning or the end of the text that surround
+    /// the whole pattern, which saves the cost of handling them as
+    /// assertions.
+    fn strip_anchors(hir: regex_syntax::hir::Hir) -> (bool, bool, regex_syntax::hir::Hir) {
+        if !matches!(hir.kind(), LibHir::Concat(_)) {
+            return (false, false, hir);
+        }
+
+        let mut sub = match hir.into_kind() {
+            LibHir::Concat(sub) => sub,
+            _ => unreachable!(),
+        };
+
+        let anchor_begin = is_anchor(sub.first(), LibAnchor::StartText);
 
-        // Remove anchor characters
         if anchor_begin {
-            regex.remove(0);
+            sub.remove(0);
         }
 
+        let anchor_end = is_anchor(sub.last(), LibAnchor::EndText);
+
         if anchor_end {
-            regex.remove(regex.len() - 1);
+            sub.pop();
         }
 
-        (anchor_begin, anchor_end, regex)
+        (
+            anchor_begin,
+            anchor_end,
+            regex_syntax::hir::Hir::concat(sub),
+        )
+    }
+}
+
+/// Check if a subexpression is the expected anchor.
+fn is_anchor(hir: Option<&regex_syntax::hir::Hir>, expected: LibAnchor) -> bool {
+    match hir.map(|hir| hir.kind()) {
+        Some(LibHir::Anchor(anchor)) => *anchor == expected,
+        _ => false,
     }
 }
@@ -0,0 +1,105 @@
+/// Parts of a pattern that were replaced before parsing it, which allows to
+/// report errors at their position in the original pattern.
+pub struct Replacements {
+    /// Position of each replaced part in the original pattern, its length and
+    /// the length of its replacement.
+    replaced: Vec<(usize, usize, usize)>,
+}
+
+impl Replacements {
+    /// Replace parts of a pattern outside of escape sequences and character
+    /// classes. At each position, `replace` is given the rest of the pattern
+    /// and returns the length of the part to replace and its replacement.
+    pub fn apply<F, E>(regex: &str, mut replace: F) -> Result<(String, Replacements), E>
+    where
+        F: FnMut(&str) -> Result<Option<(usize, String)>, E>,
+    {
+        let mut result = String::with_capacity(regex.len());
+        let mut replacements = Replacements {
+            replaced: Vec::new(),
+        };
+
+        let bytes = regex.as_bytes();
+        let mut class_depth = 0;
+        let mut pos = 0;
+
+        while pos < bytes.len() {
+            let next = match bytes[pos] {
+                b'\\' => {
+                    let next = pos + 1 + regex[pos + 1..].chars().next().map_or(0, char::len_utf8);
+
+                    // Escapes such as \p{Greek} or \x{10FFFF} are read at once.
+                    match (&bytes[pos + 1..next], regex[next..].starts_with('{')) {
+                        (b"p" | b"P" | b"x" | b"u" | b"U", true) => regex[next..]
+                            .find('}')
+                            .map_or(bytes.len(), |i| next + i + 1),
+                        _ => next,
+                    }
+                }
+                b'[' => {
+                    class_depth += 1;
+                    let mut next = pos + 1;
+
+                    // A closing bracket right after the opening one is literal.
+                    if bytes.get(next) == Some(&b'^') {
+                        next += 1;
+                    }
+
+                    if bytes.get(next) == Some(&b']') {
+                        next += 1;
+                    }
+
+                    next
+                }
+                b']' if class_depth > 0 => {
+                    class_depth -= 1;
+                    pos + 1
+                }
+                _ if class_depth == 0 => {
+                    if let Some((len, replacement)) = replace(&regex[pos..])? {
+                        replacements.replaced.push((pos, len, replacement.len()));
+                        result.push_str(&replacement);
+                        pos += len;
+                        continue;
+                    }
+
+                    pos + regex[pos..].chars().next().unwrap().len_utf8()
+                }
+                _ => pos + regex[pos..].chars().next().unwrap().len_utf8(),
+            };
+
+            result.push_str(&regex[pos..next]);
+            pos = next;
+        }
+
+        Ok((result, replacements))
+    }
+
+    /// Translate a position in the pattern where parts were replaced into a
+    /// position in the original pattern, a position inside of a replacement
+    /// is translated into the position of the replaced part.
+    pub fn original_position(&self, position: usize) -> usize {
+        let mut shift: isize = 0;
+
+        for &(start, len, replacement_len) in &self.replaced {
+            let new_start = (start as isize + shift) as usize;
+
+            if position < new_start {
+                break;
+            } else if position < new_start + replacement_len {
+                return start;
+            }
+
+            shift += replacement_len as isize - len as isize;
+        }
+
+        (position as isize - shift) as usize
+    }
+}
+
+/// Length of a name made of ASCII alphanumeric characters and underscores at
+/// the beginning of a text.
+pub fn name_len(text: &str) -> usize {
+    text.find(|x: char| !x.is_ascii_alphanumeric() && x != '_')
+        .unwrap_or(text.len())
+}
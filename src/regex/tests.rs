@@ -1,4 +1,35 @@
-use super::is_match;
+use super::super::mapping::SpannerEnumerator;
+use super::super::mapping::Text;
+use super::super::mapping::indexed_dag::{IndexedDag, TrimmingStrategy};
+use super::{
+    Compiler, Dictionary, Error, Macros, Syntax, compile, compile_bytes, compile_formula, is_match,
+};
+
+/// Sorted list of spans matched by a pattern over a text.
+fn spans(regex: &str, text: &str) -> Vec<(usize, usize)> {
+    let automaton = compile(regex).unwrap();
+    spans_over(automaton, Text::Unicode(text))
+}
+
+/// Sorted list of spans matched by a pattern over raw bytes.
+fn byte_spans(regex: &str, text: &[u8]) -> Vec<(usize, usize)> {
+    let automaton = compile_bytes(regex).unwrap();
+    spans_over(automaton, Text::Bytes(text))
+}
+
+fn spans_over(automaton: super::Automaton, text: Text) -> Vec<(usize, usize)> {
+    let mut matches = IndexedDag::new(automaton, text, 1, TrimmingStrategy::FullTrimming, false);
+    matches.preprocess().unwrap();
+
+    let mut spans: Vec<_> = matches
+        .iter()
+        .map(|mapping| mapping.main_span().unwrap())
+        .map(|span| (span.start, span.end))
+        .collect();
+
+    spans.sort();
+    spans
+}
 
 #[test]
 fn wildcard() {
@@ -47,6 +78,7 @@ fn star() {
     assert!(is_match(r"^a*$", ""));
     assert!(is_match(r"^a*$", "aaaaaaaa"));
     assert!(is_match(r"^(foo)*$", "foofoofoo"));
+    assert!(is_match(r"^(?P<x>a)*$", "a"));
     assert!(!is_match(r"^a*$", "bbbb"));
 }
 
@@ -54,6 +86,7 @@ fn star() {
 fn plus() {
     assert!(is_match(r"^a+$", "aaaaaaaa"));
     assert!(is_match(r"^(foo)+$", "foofoofoo"));
+    assert!(is_match(r"^(?P<x>a)+$", "a"));
     assert!(!is_match(r"^a+$", ""));
     assert!(!is_match(r"^a+$", "bbbb"));
 }
@@ -74,7 +107,9 @@ fn concatenation() {
 #[test]
 fn union() {
     assert!(is_match(r"^foo|bar$", "bar"));
-    assert!(!is_match(r"^foo|bar$", "foobar"));
+    assert!(is_match(r"^foo|bar$", "foobar"));
+    assert!(!is_match(r"^foo|bar$", "barfoo"));
+    assert!(!is_match(r"^(foo|bar)$", "foobar"));
 }
 
 #[test]
@@ -110,3 +145,321 @@ fn end_token() {
     assert!(is_match(r"foo", "foobar"));
     assert!(!is_match(r"foo$", "foobar"));
 }
+
+#[test]
+fn text_anchors() {
+    assert_eq!(spans(r"\Aa", "aa"), vec![(0, 1)]);
+    assert_eq!(spans(r"a\z", "aa"), vec![(1, 2)]);
+    assert_eq!(spans(r"(a$)", "aa"), vec![(1, 2)]);
+    assert_eq!(spans(r"(^a)|b", "aab"), vec![(0, 1), (2, 3)]);
+    assert!(!is_match(r"a^b", "ab"));
+    assert!(!is_match(r"a\z\Ab", "ab"));
+    assert!(is_match(r"\A\z", ""));
+}
+
+#[test]
+fn line_anchors() {
+    let text = "foo\nbar baz\nqux";
+    assert_eq!(spans(r"(?m)^\w+$", text), vec![(0, 3), (12, 15)]);
+    assert_eq!(spans(r"(?m)^\w", text), vec![(0, 1), (4, 5), (12, 13)]);
+    assert_eq!(spans(r"(?m)\w$", text), vec![(2, 3), (10, 11), (14, 15)]);
+    assert_eq!(spans(r"(?m)$\n^", text), vec![(3, 4), (11, 12)]);
+}
+
+#[test]
+fn word_boundaries() {
+    assert_eq!(
+        spans(r"\b\w+\b", "foo bar_1 é!"),
+        vec![(0, 3), (4, 9), (10, 12)]
+    );
+    assert_eq!(spans(r"\B\w\B", "abcd"), vec![(1, 2), (2, 3)]);
+    assert_eq!(spans(r"\b", "a b"), vec![(0, 0), (1, 1), (2, 2), (3, 3)]);
+    assert_eq!(spans(r"\B", "a  b"), vec![(2, 2)]);
+    assert!(is_match(r"foo\b", "foo bar"));
+    assert!(!is_match(r"foo\b", "foobar"));
+    assert!(!is_match(r"\b\B", "a b"));
+}
+
+#[test]
+fn bytes() {
+    assert_eq!(
+        byte_spans(r"\xFF+", b"a\xFF\xFFb"),
+        vec![(1, 2), (1, 3), (2, 3)]
+    );
+    assert_eq!(byte_spans(r"a.b", b"a\x80b"), vec![(0, 3)]);
+    assert_eq!(byte_spans(r"[^a]", b"a\x00\xE9"), vec![(1, 2), (2, 3)]);
+    assert_eq!(byte_spans(r"(?i)ab", b"xAbx"), vec![(1, 3)]);
+
+    // Unicode characters are matched through their UTF-8 encoding.
+    assert_eq!(byte_spans(r"(?u)é", "aé".as_bytes()), vec![(1, 3)]);
+    assert_eq!(
+        byte_spans(r"(?u:\w)", b"a\xC3\xA9\xFF"),
+        vec![(0, 1), (1, 3)]
+    );
+    assert_eq!(byte_spans(r"(?u:.)", b"a\xFF"), vec![(0, 1)]);
+
+    // Word characters are ASCII ones.
+    assert_eq!(
+        byte_spans(r"\b\w+\b", b"foo\xE9bar_1"),
+        vec![(0, 3), (4, 9)]
+    );
+    assert_eq!(
+        byte_spans(r"(?m)^\xFF$", b"\xFF\n\xFF"),
+        vec![(0, 1), (2, 3)]
+    );
+}
+
+#[test]
+fn bytes_groups() {
+    let automaton = compile_bytes(r"(?P<key>\w+)=(?P<value>[^;]*)").unwrap();
+    let text: &[u8] = b"a=\x01\x02;b=\xFF";
+    let mut matches = IndexedDag::new(automaton, text, 1, TrimmingStrategy::FullTrimming, false);
+    matches.preprocess().unwrap();
+
+    let mut groups: Vec<Vec<_>> = matches
+        .iter()
+        .map(|mapping| {
+            mapping
+                .iter_groups_bytes()
+                .map(|(name, bytes)| (name.to_string(), bytes.into_owned()))
+                .collect()
+        })
+        .collect();
+    groups.sort();
+
+    let expected: &[&[(&str, &[u8])]] = &[
+        &[("key", b"a"), ("value", b"")],
+        &[("key", b"a"), ("value", b"\x01")],
+        &[("key", b"a"), ("value", b"\x01\x02")],
+        &[("key", b"b"), ("value", b"")],
+        &[("key", b"b"), ("value", b"\xFF")],
+    ];
+    assert_eq!(groups.len(), expected.len());
+
+    for (group, expected) in groups.iter().zip(expected) {
+        let expected: Vec<_> = expected
+            .iter()
+            .map(|&(name, bytes)| (name.to_string(), bytes.to_vec()))
+            .collect();
+        assert_eq!(group, &expected);
+    }
+}
+
+#[test]
+fn syntax_error() {
+    match compile(r"ab(c") {
+        Err(Error::Syntax { position, .. }) => assert_eq!(position, 2),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    // The position takes the stripped anchor into account.
+    match compile(r"^ab(c") {
+        Err(Error::Syntax { position, .. }) => assert_eq!(position, 3),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}
+
+#[test]
+fn unsupported() {
+    assert!(matches!(compile(r"(?-u:\b)"), Err(Error::Unsupported(_))));
+    assert!(matches!(compile(r"(?-u:[a-z])"), Err(Error::ByteClass)));
+    assert!(matches!(
+        compile_bytes(r"(?u:\b)"),
+        Err(Error::Unsupported(_))
+    ));
+}
+
+#[test]
+fn dictionary() {
+    let words = [
+        "funny",
+        "fun",
+        "better",
+        "worst",
+        "worse",
+        "well done",
+        "ête",
+    ];
+    let compiler =
+        Compiler::unicode().with_dictionary("attribute", Dictionary::new(words.iter().cloned()));
+    let text = "the worst fun, well done and funny. Worse: Fun ête";
+
+    let automaton = compiler.compile(r"(?&attribute)").unwrap();
+    let alternation = compile(&format!("(?P<attribute>{})", words.join("|"))).unwrap();
+    assert_eq!(automaton.get_variables()[0].get_name(), "attribute");
+    assert_eq!(
+        spans_over(automaton.clone(), Text::Unicode(text)),
+        spans_over(alternation.clone(), Text::Unicode(text))
+    );
+
+    // Common prefixes of the entries share the same states.
+    assert!(automaton.get_nb_states() < alternation.get_nb_states());
+
+    // Placeholders can be spliced anywhere in a pattern.
+    let automaton = compiler.compile(r"\b(?&attribute)\b[,.]").unwrap();
+    assert_eq!(
+        spans_over(automaton, Text::Unicode(text)),
+        vec![(10, 13), (29, 34)]
+    );
+
+    let automaton = Compiler::unicode()
+        .with_dictionary(
+            "attribute",
+            Dictionary::new(words.iter().cloned()).with_case_folding(true),
+        )
+        .compile(r"(?&attribute)")
+        .unwrap();
+    assert_eq!(
+        spans_over(automaton, Text::Unicode("FUN Worse: êTE")),
+        vec![(0, 3), (4, 9), (11, 15)]
+    );
+
+    let automaton = Compiler::bytes()
+        .with_dictionary(
+            "attribute",
+            Dictionary::new(words.iter().cloned()).with_case_folding(true),
+        )
+        .compile(r"(?&attribute)")
+        .unwrap();
+    assert_eq!(
+        spans_over(automaton, Text::Bytes("FUN ÊTE ête".as_bytes())),
+        vec![(0, 3), (9, 13)]
+    );
+
+    // Placeholders are not searched for in character classes.
+    let automaton = compiler.compile(r"[(?&attribute)]").unwrap();
+    assert_eq!(
+        spans_over(automaton, Text::Unicode("(?")),
+        vec![(0, 1), (1, 2)]
+    );
+
+    assert!(matches!(
+        compiler.compile(r"(?&other)"),
+        Err(Error::UnknownDictionary(name)) if name == "other"
+    ));
+
+    match compiler.compile(r"(?&attribute)a(b") {
+        Err(Error::Syntax { position, .. }) => assert_eq!(position, 14),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    let dictionary = Dictionary::read("fun\r\n\nbetter\n".as_bytes()).unwrap();
+    assert_eq!(dictionary.len(), 2);
+}
+
+#[test]
+fn macros() {
+    let compiler = Compiler::unicode();
+    let text = "on 2019-03-26 and 2019-13-01";
+    let automaton = compiler.compile(r"{{date}}").unwrap();
+    assert_eq!(spans_over(automaton, Text::Unicode(text)), vec![(3, 13)]);
+
+    let definitions = "# Dates\nyear = [0-9]{4}\n\n month={{digit}}{2}  \n";
+    let compiler = compiler.with_macros(Macros::read(definitions.as_bytes()).unwrap());
+    let automaton = compiler.compile(r"{{year}}-{{month}}").unwrap();
+    assert_eq!(
+        spans_over(automaton, Text::Unicode("2019-03")),
+        vec![(0, 7)]
+    );
+
+    // Macros are not searched for in escape sequences and character classes.
+    let automaton = compiler.compile(r"[{{date}}]").unwrap();
+    assert_eq!(
+        spans_over(automaton, Text::Unicode("{d")),
+        vec![(0, 1), (1, 2)]
+    );
+
+    assert!(matches!(
+        compiler.compile(r"{{other}}"),
+        Err(Error::UnknownMacro(name)) if name == "other"
+    ));
+
+    let compiler = Compiler::unicode().with_macros(
+        Macros::empty()
+            .with_definition("a", "{{b}}")
+            .with_definition("b", "x{{a}}")
+            .with_definition("bad", "a(b")
+            .with_definition("outer", "x|{{bad}}"),
+    );
+
+    match compiler.compile(r"{{a}}") {
+        Err(Error::MacroCycle(cycle)) => assert_eq!(cycle, ["a", "b", "a"]),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    // Syntax errors are located in the innermost macro.
+    match compiler.compile(r"y{{outer}}") {
+        Err(Error::InMacro(name, err)) => {
+            assert_eq!(name, "bad");
+            assert!(matches!(*err, Error::Syntax { position: 1, .. }));
+        }
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    match compiler.compile(r"{{date}}(x") {
+        Err(Error::Syntax { position, .. }) => assert_eq!(position, 8),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    assert!(Macros::read("a = x\na = y\n".as_bytes()).is_err());
+    assert!(Macros::read("a-b = x\n".as_bytes()).is_err());
+    assert!(Macros::read("x\n".as_bytes()).is_err());
+}
+
+#[test]
+fn formula() {
+    let text = "aab ba c";
+
+    // Formulas give the same automaton as the equivalent pattern.
+    let same = |formula: &str, regex: &str| {
+        let formula = compile_formula(formula).unwrap();
+        let regex = compile(regex).unwrap();
+        assert_eq!(formula.get_nb_states(), regex.get_nb_states());
+        assert_eq!(
+            spans_over(formula, Text::Unicode(text)),
+            spans_over(regex, Text::Unicode(text))
+        );
+    };
+
+    same(r"x{a+}y{b}", r"(?P<x>a+)(?P<y>b)");
+    same(r"x{a{2}}\s*", r"(?P<x>a{2})\s*");
+    same(r"x{a∨b}·ε", r"(?P<x>a|b)");
+    same(r"x{\p{Ll}}b{1,}", r"(?P<x>\p{Ll})b{1,}");
+    same(r"ab{[a{]}", r"(?P<ab>[a{])");
+    same(r"[x{]", r"[x{]");
+    same(r"a|∅", r"a");
+    same(r"x{b|∅}", r"(?P<x>b)");
+
+    // The empty language never matches, even inside of a binding.
+    let formula_spans =
+        |formula: &str| spans_over(compile_formula(formula).unwrap(), Text::Unicode(text));
+    assert!(formula_spans(r"∅").is_empty());
+    assert!(formula_spans(r"x{∅}").is_empty());
+    assert!(formula_spans(r"a*∅").is_empty());
+    assert_eq!(formula_spans(r"x{a∅}|y{b}"), vec![(2, 3), (4, 5)]);
+
+    // A same variable can be bound in several branches.
+    let automaton = compile_formula(r"x{a+}|b(x{a})").unwrap();
+    assert_eq!(automaton.get_variables().len(), 1);
+    assert_eq!(automaton.get_variables()[0].get_name(), "x");
+    assert_eq!(
+        spans_over(automaton, Text::Unicode(text)),
+        vec![(0, 1), (0, 2), (1, 2), (5, 6)]
+    );
+
+    let automaton = Compiler::bytes()
+        .with_syntax(Syntax::Formula)
+        .compile(r"x{\xFF}")
+        .unwrap();
+    assert_eq!(spans_over(automaton, Text::Bytes(b"a\xFF")), vec![(1, 2)]);
+
+    match compile_formula(r"a x{b") {
+        Err(Error::Syntax { position, .. }) => assert_eq!(position, 2),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+
+    match compile_formula(r"x{a}y{b}(") {
+        Err(Error::Syntax { position, .. }) => assert_eq!(position, 8),
+        other => panic!("unexpected result: {:?}", other.map(|_| ())),
+    }
+}